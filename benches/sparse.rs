@@ -0,0 +1,84 @@
+//! Benchmarks of the sparse Merkle tree on the 100-update fixture of
+//! `tests-data`, covering the update, prove and delete paths. Run with
+//! `cargo bench --bench sparse`.
+
+use fuel_merkle::{common::Bytes32, sparse::in_memory};
+use fuel_merkle_test_helpers::data::EncodedValue;
+use serde::Deserialize;
+use std::{
+    convert::TryInto,
+    fs::File,
+    hint::black_box,
+    time::{Duration, Instant},
+};
+
+const FIXTURE: &str = concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/tests-data/fixtures/Test Update 100.yaml"
+);
+const ITERATIONS: u32 = 200;
+
+#[derive(Deserialize)]
+struct Step {
+    key: EncodedValue,
+    data: EncodedValue,
+}
+
+#[derive(Deserialize)]
+struct Fixture {
+    expected_root: EncodedValue,
+    steps: Vec<Step>,
+}
+
+fn bytes32(value: EncodedValue) -> Bytes32 {
+    value.into_bytes().unwrap().try_into().unwrap()
+}
+
+fn bench<F: FnMut()>(name: &str, mut f: F) {
+    // Warm up.
+    f();
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        f();
+    }
+    let elapsed: Duration = start.elapsed() / ITERATIONS;
+    println!("{name:<40} {elapsed:>12.2?} per iteration ({ITERATIONS} iterations)");
+}
+
+fn main() {
+    let fixture: Fixture = serde_yaml::from_reader(File::open(FIXTURE).unwrap()).unwrap();
+    let expected_root = bytes32(fixture.expected_root);
+    let updates = fixture
+        .steps
+        .into_iter()
+        .map(|step| (bytes32(step.key), step.data.into_bytes().unwrap()))
+        .collect::<Vec<_>>();
+
+    let build = || {
+        let mut tree = in_memory::MerkleTree::new();
+        for (key, data) in updates.iter() {
+            tree.update(key, data);
+        }
+        tree
+    };
+    assert_eq!(build().root(), expected_root);
+
+    bench("sparse: 100 updates", || {
+        black_box(build().root());
+    });
+
+    let tree = build();
+    bench("sparse: prove 100 keys", || {
+        for (key, _) in updates.iter() {
+            black_box(tree.prove(key));
+        }
+    });
+
+    bench("sparse: 100 updates, then 100 deletes", || {
+        let mut tree = build();
+        for (key, _) in updates.iter() {
+            tree.delete(key);
+        }
+        black_box(tree.root());
+    });
+}
@@ -1,12 +1,16 @@
 mod hash;
+mod integrity;
 mod merkle_tree;
 mod node;
 mod primitive;
+mod rebuild;
 
 pub(crate) use hash::empty_sum;
 pub(crate) use hash::{leaf_sum, node_sum};
 pub(crate) use node::Node;
 
+pub use integrity::check;
 pub use merkle_tree::{MerkleTree, MerkleTreeError};
 pub use primitive::Primitive;
+pub use rebuild::{rebuild, RebuildReport};
 pub mod in_memory;
@@ -0,0 +1,194 @@
+use crate::{
+    binary::{merkle_tree::peak_positions, node_sum, Node, Primitive},
+    common::{IntegrityIssue, IntegrityReport, Position},
+    storage::{Mappable, StorageInspect},
+};
+
+use alloc::vec::Vec;
+
+/// Check the integrity of the binary Merkle tree with the given number of
+/// leaves, walking every stored node down from the MMR peaks. The check
+/// reports:
+///
+/// - nodes below a peak, or peaks, not found in storage;
+/// - nodes stored with an in-order index, and so a position and height, that
+///   does not match their key;
+/// - internal nodes whose hash does not match the hash of their children.
+///
+/// The data of the leaves is not stored, so the hashes of the leaves are not
+/// checked. A storage error stops the check and is returned.
+pub fn check<TableType, StorageType>(
+    storage: &StorageType,
+    leaves_count: u64,
+) -> Result<IntegrityReport<u64>, StorageType::Error>
+where
+    TableType: Mappable<Key = u64, SetValue = Primitive, GetValue = Primitive>,
+    StorageType: StorageInspect<TableType>,
+{
+    let mut report = IntegrityReport::default();
+    if leaves_count == 0 {
+        return Ok(report);
+    }
+
+    // Peaks are pushed in reverse, so that nodes are checked from left to
+    // right.
+    let mut stack: Vec<Position> = peak_positions(leaves_count);
+    stack.reverse();
+    while let Some(position) = stack.pop() {
+        let key = position.in_order_index();
+        report.reach(&key);
+        let node = match load_node(storage, &mut report, position)? {
+            Some(node) => node,
+            None => continue,
+        };
+        if position.is_leaf() {
+            report.leaves_checked += 1;
+            continue;
+        }
+
+        let left_child = storage.get(&position.left_child().in_order_index())?;
+        let right_child = storage.get(&position.right_child().in_order_index())?;
+        if let (Some(left_child), Some(right_child)) = (left_child, right_child) {
+            let hash = node_sum(&left_child.1, &right_child.1);
+            if hash != *node.hash() {
+                report.report(IntegrityIssue::HashMismatch(key));
+            }
+        }
+        stack.push(position.right_child());
+        stack.push(position.left_child());
+    }
+
+    Ok(report)
+}
+
+fn load_node<TableType, StorageType>(
+    storage: &StorageType,
+    report: &mut IntegrityReport<u64>,
+    position: Position,
+) -> Result<Option<Node>, StorageType::Error>
+where
+    TableType: Mappable<Key = u64, SetValue = Primitive, GetValue = Primitive>,
+    StorageType: StorageInspect<TableType>,
+{
+    let key = position.in_order_index();
+    let primitive = match storage.get(&key)? {
+        Some(primitive) => primitive.into_owned(),
+        None => {
+            report.report(IntegrityIssue::MissingNode(key));
+            return Ok(None);
+        }
+    };
+    report.nodes_checked += 1;
+    let node = Node::from(primitive);
+    if node.key() != key {
+        report.report(IntegrityIssue::MisplacedNode(key));
+        report.report(IntegrityIssue::HeightMismatch {
+            key,
+            expected: position.height(),
+            found: node.position().height(),
+        });
+    }
+    Ok(Some(node))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        binary::{check, MerkleTree, Primitive},
+        common::{IntegrityIssue, StorageMap},
+        storage::{Mappable, StorageInspect, StorageMutate},
+    };
+
+    #[derive(Debug)]
+    struct TestTable;
+
+    impl Mappable for TestTable {
+        type Key = u64;
+        type SetValue = Primitive;
+        type GetValue = Self::SetValue;
+    }
+
+    fn storage_with_leaves(n: u32) -> StorageMap<TestTable> {
+        let mut storage = StorageMap::<TestTable>::new();
+        let mut tree = MerkleTree::new(&mut storage);
+        for i in 0..n {
+            tree.push(&i.to_be_bytes()).unwrap();
+        }
+        storage
+    }
+
+    #[test]
+    fn test_check_of_a_valid_tree_reports_no_issues() {
+        for n in 0_u32..20 {
+            let storage = storage_with_leaves(n);
+            let mut report = check::<TestTable, _>(&storage, n as u64).unwrap();
+            assert!(report.is_ok(), "{:?}", report.issues);
+            assert_eq!(report.leaves_checked, n as u64);
+
+            report.find_unreachable(
+                (0..2 * n as u64).filter(|key| storage.contains_key(key).unwrap()),
+            );
+            assert!(report.is_ok(), "{:?}", report.issues);
+        }
+    }
+
+    #[test]
+    fn test_check_reports_a_missing_node() {
+        let mut storage = storage_with_leaves(7);
+        storage.remove(&4).unwrap();
+
+        let report = check::<TestTable, _>(&storage, 7).unwrap();
+        assert_eq!(report.issues, [IntegrityIssue::MissingNode(4)]);
+    }
+
+    #[test]
+    fn test_check_reports_a_node_stored_under_the_key_of_another_position() {
+        let mut storage = storage_with_leaves(7);
+        let (_, hash) = storage.get(&5).unwrap().unwrap().into_owned();
+        storage.insert(&5, &(3, hash)).unwrap();
+
+        let report = check::<TestTable, _>(&storage, 7).unwrap();
+        assert_eq!(
+            report.issues,
+            [
+                IntegrityIssue::MisplacedNode(5),
+                IntegrityIssue::HeightMismatch {
+                    key: 5,
+                    expected: 1,
+                    found: 2
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn test_check_reports_a_node_that_does_not_hash_to_its_children() {
+        let mut storage = storage_with_leaves(7);
+        let (_, hash) = storage.get(&6).unwrap().unwrap().into_owned();
+        storage.insert(&4, &(4, hash)).unwrap();
+
+        let report = check::<TestTable, _>(&storage, 7).unwrap();
+        assert_eq!(report.issues, [IntegrityIssue::HashMismatch(5)]);
+    }
+
+    #[test]
+    fn test_check_finds_unreachable_nodes() {
+        // The leaf pushed after the tree's leaves count was recorded is left
+        // behind in storage.
+        let storage = storage_with_leaves(8);
+        let mut report = check::<TestTable, _>(&storage, 7).unwrap();
+        assert!(report.is_ok());
+
+        let keys = (0..16).filter(|key| storage.contains_key(key).unwrap());
+        report.find_unreachable(keys);
+        assert_eq!(
+            report.issues,
+            [
+                IntegrityIssue::Unreachable(7),
+                IntegrityIssue::Unreachable(11),
+                IntegrityIssue::Unreachable(13),
+                IntegrityIssue::Unreachable(14)
+            ]
+        );
+    }
+}
@@ -2,12 +2,13 @@ use crate::{
     binary::{empty_sum, in_memory::NodesTable, Node, Primitive},
     common::{Bytes32, Position, ProofSet, StorageMap, Subtree},
     storage::{
-        Mappable, StorageInspect, StorageInspectInfallible, StorageMutate, StorageMutateInfallible,
+        BatchReads, Mappable, ReadStrategy, SingleReads, StorageInspect, StorageInspectBatch,
+        StorageInspectInfallible, StorageMutate, StorageMutateInfallible,
     },
 };
 
 use alloc::{boxed::Box, vec::Vec};
-use core::marker::PhantomData;
+use core::{iter, marker::PhantomData};
 
 #[derive(Debug, Clone)]
 #[cfg_attr(feature = "std", derive(thiserror::Error))]
@@ -31,12 +32,19 @@ impl<StorageError> From<StorageError> for MerkleTreeError<StorageError> {
     }
 }
 
+/// # Binary Merkle Tree
+///
+/// The tree reads the nodes whose keys are known up front, such as its peaks
+/// when loaded and the side nodes of a proof, according to `Reads`. By
+/// default, nodes are read one at a time; for a storage implementing
+/// [`StorageInspectBatch`], [`load_with_batch_reads`](Self::load_with_batch_reads)
+/// and [`with_batch_reads`](Self::with_batch_reads) read them in batches.
 #[derive(Debug)]
-pub struct MerkleTree<TableType, StorageType> {
+pub struct MerkleTree<TableType, StorageType, Reads = SingleReads> {
     storage: StorageType,
     head: Option<Box<Subtree<Node>>>,
     leaves_count: u64,
-    phantom_table: PhantomData<TableType>,
+    phantom_table: PhantomData<(TableType, Reads)>,
 }
 
 impl<TableType, StorageType, StorageError> MerkleTree<TableType, StorageType>
@@ -45,30 +53,54 @@ where
     StorageType: StorageInspect<TableType, Error = StorageError>,
 {
     pub fn new(storage: StorageType) -> Self {
-        Self {
-            storage,
-            head: None,
-            leaves_count: 0,
-            phantom_table: Default::default(),
-        }
+        Self::from_storage(storage, 0)
     }
 
     pub fn load(
         storage: StorageType,
         leaves_count: u64,
     ) -> Result<Self, MerkleTreeError<StorageError>> {
-        let mut tree = Self {
-            storage,
-            head: None,
-            leaves_count,
-            phantom_table: Default::default(),
-        };
-
+        let mut tree = Self::from_storage(storage, leaves_count);
         tree.build()?;
+        Ok(tree)
+    }
+}
 
+impl<TableType, StorageType, StorageError> MerkleTree<TableType, StorageType>
+where
+    TableType: Mappable<Key = u64, SetValue = Primitive, GetValue = Primitive>,
+    StorageType: StorageInspectBatch<TableType, Error = StorageError>,
+{
+    /// Load the tree like [`load`](Self::load), reading its peaks in a single
+    /// batch, and read in batches afterwards. See
+    /// [`with_batch_reads`](Self::with_batch_reads).
+    pub fn load_with_batch_reads(
+        storage: StorageType,
+        leaves_count: u64,
+    ) -> Result<MerkleTree<TableType, StorageType, BatchReads>, MerkleTreeError<StorageError>> {
+        let mut tree = MerkleTree::from_storage(storage, leaves_count);
+        tree.build()?;
         Ok(tree)
     }
 
+    /// Read the nodes whose keys are known up front with
+    /// [`StorageInspectBatch::get_batch`], instead of one at a time.
+    pub fn with_batch_reads(self) -> MerkleTree<TableType, StorageType, BatchReads> {
+        MerkleTree {
+            storage: self.storage,
+            head: self.head,
+            leaves_count: self.leaves_count,
+            phantom_table: Default::default(),
+        }
+    }
+}
+
+impl<TableType, StorageType, Reads, StorageError> MerkleTree<TableType, StorageType, Reads>
+where
+    TableType: Mappable<Key = u64, SetValue = Primitive, GetValue = Primitive>,
+    StorageType: StorageInspect<TableType, Error = StorageError>,
+    Reads: ReadStrategy<TableType, StorageType>,
+{
     pub fn root(&self) -> Bytes32 {
         let mut scratch_storage = StorageMap::<NodesTable>::new();
         let root_node = self.root_node(&mut scratch_storage);
@@ -88,15 +120,8 @@ where
 
         let mut proof_set = ProofSet::new();
 
-        let root_position = self.root_position();
+        let root_position = root_position(self.leaves_count);
         let leaf_position = Position::from_leaf_index(proof_index);
-        let primitive = self
-            .storage
-            .get(&leaf_position.in_order_index())?
-            .ok_or(MerkleTreeError::LoadError(proof_index))?
-            .into_owned();
-        let leaf_node = Node::from(primitive);
-        proof_set.push(*leaf_node.hash());
 
         let (_, mut side_positions): (Vec<_>, Vec<_>) = root_position
             .path(&leaf_position, self.leaves_count)
@@ -112,17 +137,31 @@ where
             .root_node(&mut scratch_storage)
             .expect("Root node must be present");
 
-        // Get side nodes. First, we check the scratch storage. If the side node
-        // is not found in scratch storage, we then check main storage. Finally,
-        // if the side node is not found in main storage, we exit with a load
-        // error.
-        for side_position in side_positions {
-            let key = side_position.in_order_index();
-            let primitive = StorageInspectInfallible::get(&scratch_storage, &key)
-                .or(StorageInspect::get(&self.storage, &key)?)
-                .ok_or(MerkleTreeError::LoadError(key))?
-                .into_owned();
-            let node = Node::from(primitive);
+        // Get the leaf and the side nodes. First, we check the scratch storage.
+        // The nodes not found in scratch storage are read from main storage in
+        // a single batch. Finally, if a node is not found in main storage, we
+        // exit with a load error.
+        let keys = iter::once(leaf_position)
+            .chain(side_positions)
+            .map(|position| position.in_order_index())
+            .collect::<Vec<_>>();
+        let stored_keys = keys
+            .iter()
+            .filter(|key| !StorageInspectInfallible::contains_key(&scratch_storage, key))
+            .copied()
+            .collect::<Vec<_>>();
+        let mut stored_primitives = Reads::get_batch(&self.storage, &stored_keys)?.into_iter();
+        for (i, key) in keys.into_iter().enumerate() {
+            let primitive = match StorageInspectInfallible::get(&scratch_storage, &key) {
+                Some(primitive) => Some(primitive),
+                None => stored_primitives.next().flatten(),
+            };
+            let load_error = if i == 0 {
+                MerkleTreeError::LoadError(proof_index)
+            } else {
+                MerkleTreeError::LoadError(key)
+            };
+            let node = Node::from(primitive.ok_or(load_error)?.into_owned());
             proof_set.push(*node.hash());
         }
 
@@ -134,6 +173,15 @@ where
     // PRIVATE
     //
 
+    fn from_storage(storage: StorageType, leaves_count: u64) -> Self {
+        Self {
+            storage,
+            head: None,
+            leaves_count,
+            phantom_table: Default::default(),
+        }
+    }
+
     /// A binary Merkle tree can be built from a collection of Merkle Mountain
     /// Range (MMR) peaks. The MMR structure can be accurately defined by the
     /// number of leaves in the leaf row.
@@ -216,12 +264,13 @@ where
     ///
     fn build(&mut self) -> Result<(), MerkleTreeError<StorageError>> {
         let mut current_head = None;
-        let peaks = &self.peak_positions();
-        for peak in peaks.iter() {
-            let key = peak.in_order_index();
-            let node = self
-                .storage
-                .get(&key)?
+        let keys = peak_positions(self.leaves_count)
+            .iter()
+            .map(|peak| peak.in_order_index())
+            .collect::<Vec<_>>();
+        let primitives = Reads::get_batch(&self.storage, &keys)?;
+        for (key, primitive) in keys.into_iter().zip(primitives) {
+            let node = primitive
                 .ok_or(MerkleTreeError::LoadError(key))?
                 .into_owned()
                 .into();
@@ -234,35 +283,6 @@ where
         Ok(())
     }
 
-    fn peak_positions(&self) -> Vec<Position> {
-        // Define a new tree with a leaf count 1 greater than the current leaf
-        // count.
-        let leaves_count = self.leaves_count + 1;
-
-        // The rightmost leaf position of a tree will always have a leaf index
-        // N - 1, where N is the number of leaves.
-        let leaf_position = Position::from_leaf_index(leaves_count - 1);
-        let root_position = self.root_position();
-        let mut peaks_itr = root_position.path(&leaf_position, leaves_count).iter();
-        peaks_itr.next(); // Omit the root
-
-        let (_, peaks): (Vec<_>, Vec<_>) = peaks_itr.unzip();
-
-        peaks
-    }
-
-    fn root_position(&self) -> Position {
-        // Define a new tree with a leaf count 1 greater than the current leaf
-        // count.
-        let leaves_count = self.leaves_count + 1;
-
-        // The root position of a tree will always have an in-order index equal
-        // to N' - 1, where N is the leaves count and N' is N rounded (or equal)
-        // to the next power of 2.
-        let root_index = leaves_count.next_power_of_two() - 1;
-        Position::from_in_order_index(root_index)
-    }
-
     /// The root node is generated by joining all MMR peaks, where a peak is
     /// defined as the head of a balanced subtree. A tree can be composed of a
     /// single balanced subtree, in which case the tree is itself balanced, or
@@ -287,18 +307,19 @@ where
     }
 }
 
-impl<TableType, StorageType, StorageError> MerkleTree<TableType, StorageType>
+impl<TableType, StorageType, Reads, StorageError> MerkleTree<TableType, StorageType, Reads>
 where
     TableType: Mappable<Key = u64, SetValue = Primitive, GetValue = Primitive>,
     StorageType: StorageMutate<TableType, Error = StorageError>,
 {
+    /// Push a leaf with the given data to the tree. If a storage error
+    /// occurs, the tree is left as it was before the push.
     pub fn push(&mut self, data: &[u8]) -> Result<(), MerkleTreeError<StorageError>> {
         let node = Node::create_leaf(self.leaves_count, data);
         self.storage.insert(&node.key(), &node.as_ref().into())?;
-        let next = self.head.take();
+        let next = self.head.clone();
         let head = Box::new(Subtree::<Node>::new(node, next));
-        self.head = Some(head);
-        self.join_all_subtrees()?;
+        self.head = Some(self.join_all_subtrees(head)?);
 
         self.leaves_count += 1;
 
@@ -309,34 +330,63 @@ where
     // PRIVATE
     //
 
-    fn join_all_subtrees(&mut self) -> Result<(), StorageError> {
+    fn join_all_subtrees(
+        &mut self,
+        mut head: Box<Subtree<Node>>,
+    ) -> Result<Box<Subtree<Node>>, StorageError> {
         loop {
-            let current = self.head.as_ref().unwrap();
-            if !(current.next().is_some()
-                && current.node().position().height()
-                    == current.next_node().unwrap().position().height())
+            if !(head.next().is_some()
+                && head.node().position().height() == head.next_node().unwrap().position().height())
             {
                 break;
             }
 
             // Merge the two front heads of the list into a single head
-            let joined_head = {
-                let mut head = self.head.take().unwrap();
-                let mut head_next = head.take_next().unwrap();
-                let joined_head = join_subtrees(&mut head_next, &mut head);
-                self.storage.insert(
-                    &joined_head.node().key(),
-                    &joined_head.node().as_ref().into(),
-                )?;
-                joined_head
-            };
-            self.head = Some(Box::new(joined_head));
+            let mut head_next = head.take_next().unwrap();
+            let joined_head = join_subtrees(&mut head_next, &mut head);
+            self.storage.insert(
+                &joined_head.node().key(),
+                &joined_head.node().as_ref().into(),
+            )?;
+            head = Box::new(joined_head);
         }
 
-        Ok(())
+        Ok(head)
     }
 }
 
+/// The positions of the MMR peaks of a tree with the given number of leaves,
+/// from left to right. See `MerkleTree::build`.
+pub(crate) fn peak_positions(leaves_count: u64) -> Vec<Position> {
+    let root_position = root_position(leaves_count);
+
+    // Define a new tree with a leaf count 1 greater than the current leaf
+    // count.
+    let leaves_count = leaves_count + 1;
+
+    // The rightmost leaf position of a tree will always have a leaf index
+    // N - 1, where N is the number of leaves.
+    let leaf_position = Position::from_leaf_index(leaves_count - 1);
+    let mut peaks_itr = root_position.path(&leaf_position, leaves_count).iter();
+    peaks_itr.next(); // Omit the root
+
+    let (_, peaks): (Vec<_>, Vec<_>) = peaks_itr.unzip();
+
+    peaks
+}
+
+fn root_position(leaves_count: u64) -> Position {
+    // Define a new tree with a leaf count 1 greater than the current leaf
+    // count.
+    let leaves_count = leaves_count + 1;
+
+    // The root position of a tree will always have an in-order index equal
+    // to N' - 1, where N is the leaves count and N' is N rounded (or equal)
+    // to the next power of 2.
+    let root_index = leaves_count.next_power_of_two() - 1;
+    Position::from_in_order_index(root_index)
+}
+
 fn join_subtrees(lhs: &mut Subtree<Node>, rhs: &mut Subtree<Node>) -> Subtree<Node> {
     let joined_node = Node::create_node(lhs.node(), rhs.node());
     Subtree::new(joined_node, lhs.take_next())
@@ -362,12 +412,17 @@ mod test {
     use super::{MerkleTree, MerkleTreeError};
     use crate::{
         binary::{empty_sum, leaf_sum, node_sum, Node, Primitive},
-        common::StorageMap,
+        common::{
+            faulty_storage::{FaultError, FaultyStorage},
+            StorageMap,
+        },
     };
     use fuel_merkle_test_helpers::TEST_DATA;
-    use fuel_storage::{Mappable, StorageInspect};
+    use fuel_storage::{Mappable, StorageInspect, StorageMutate};
 
-    use alloc::vec::Vec;
+    use crate::storage::{GetBatchResult, StorageInspectBatch};
+    use alloc::{borrow::Cow, vec::Vec};
+    use core::{cell::Cell, convert::Infallible};
 
     #[derive(Debug)]
     struct TestTable;
@@ -890,4 +945,186 @@ mod test {
             assert_eq!(set[2], node_3);
         }
     }
+
+    /// A storage that counts the reads of single keys and the batch reads.
+    #[derive(Default)]
+    struct BatchCountingStorage {
+        map: StorageMap<TestTable>,
+        gets: Cell<usize>,
+        batches: Cell<usize>,
+    }
+
+    impl StorageInspect<TestTable> for BatchCountingStorage {
+        type Error = Infallible;
+
+        fn get(&self, key: &u64) -> Result<Option<Cow<'_, Primitive>>, Self::Error> {
+            self.gets.set(self.gets.get() + 1);
+            self.map.get(key)
+        }
+
+        fn contains_key(&self, key: &u64) -> Result<bool, Self::Error> {
+            self.map.contains_key(key)
+        }
+    }
+
+    impl StorageInspectBatch<TestTable> for BatchCountingStorage {
+        fn get_batch(&self, keys: &[u64]) -> GetBatchResult<'_, Primitive, Self::Error> {
+            self.batches.set(self.batches.get() + 1);
+            keys.iter().map(|key| self.map.get(key)).collect()
+        }
+    }
+
+    impl StorageMutate<TestTable> for BatchCountingStorage {
+        fn insert(
+            &mut self,
+            key: &u64,
+            value: &Primitive,
+        ) -> Result<Option<Primitive>, Self::Error> {
+            self.map.insert(key, value)
+        }
+
+        fn remove(&mut self, key: &u64) -> Result<Option<Primitive>, Self::Error> {
+            self.map.remove(key)
+        }
+    }
+
+    #[test]
+    fn test_load_and_prove_with_batch_reads_read_the_nodes_in_a_single_batch() {
+        let mut storage = BatchCountingStorage::default();
+        let mut tree = MerkleTree::new(&mut storage);
+        let data = &TEST_DATA[0..7]; // 7 leaves
+        for datum in data.iter() {
+            let _ = tree.push(datum);
+        }
+        let expected_proof = tree.prove(5).unwrap();
+
+        // By default, the nodes are read one at a time.
+        storage.batches.set(0);
+        let tree = MerkleTree::load(&storage, 7).unwrap();
+        assert_eq!(tree.prove(5).unwrap(), expected_proof);
+        assert_eq!(storage.batches.get(), 0);
+
+        storage.gets.set(0);
+        let tree = MerkleTree::load_with_batch_reads(&storage, 7).unwrap();
+        assert_eq!(storage.batches.get(), 1);
+
+        let proof = tree.prove(5).unwrap();
+        assert_eq!(proof, expected_proof);
+        assert_eq!(storage.batches.get(), 2);
+        assert_eq!(storage.gets.get(), 0);
+    }
+
+    #[test]
+    fn test_prove_returns_a_load_error_for_a_missing_leaf() {
+        let mut storage_map = StorageMap::<TestTable>::new();
+        let mut tree = MerkleTree::new(&mut storage_map);
+        let data = &TEST_DATA[0..4]; // 4 leaves
+        for datum in data.iter() {
+            let _ = tree.push(datum);
+        }
+        let _ = storage_map.remove(&4); // Leaf 2
+
+        let tree = MerkleTree::load(&mut storage_map, 4).unwrap();
+        let err = tree
+            .prove(2)
+            .expect_err("Expected prove() to return Error; got Ok");
+        assert!(matches!(err, MerkleTreeError::LoadError(2)));
+    }
+
+    type FaultyTree<'a> =
+        MerkleTree<TestTable, &'a mut FaultyStorage<TestTable, StorageMap<TestTable>>>;
+
+    #[test]
+    fn test_push_with_failing_writes_keeps_the_tree_consistent() {
+        let data = &TEST_DATA[0..8];
+        let mut expected_storage = StorageMap::<TestTable>::new();
+        let mut expected_tree = MerkleTree::new(&mut expected_storage);
+        for datum in data.iter() {
+            expected_tree.push(datum).unwrap();
+        }
+        let expected_root = expected_tree.root();
+
+        // Pushing the eighth leaf writes the leaf and joins three subtrees.
+        for writes in 0..4 {
+            let mut storage = FaultyStorage::new(StorageMap::<TestTable>::new());
+            let faults = storage.faults();
+            let mut tree = FaultyTree::new(&mut storage);
+            for datum in data[0..7].iter() {
+                tree.push(datum).unwrap();
+            }
+            let root = tree.root();
+
+            faults.fail_writes_after(writes);
+            let err = tree
+                .push(data[7])
+                .expect_err("Expected push() to return Error; got Ok");
+            assert!(matches!(
+                err,
+                MerkleTreeError::StorageError(FaultError::WriteFailed)
+            ));
+            assert_eq!(tree.root(), root);
+            assert_eq!(tree.leaves_count, 7);
+
+            faults.clear();
+            tree.push(data[7]).unwrap();
+            assert_eq!(tree.root(), expected_root);
+            assert!(tree.prove(7).is_ok());
+        }
+    }
+
+    #[test]
+    fn test_load_with_a_missing_peak_returns_a_load_error() {
+        let mut storage = FaultyStorage::new(StorageMap::<TestTable>::new());
+        let faults = storage.faults();
+        let mut tree = FaultyTree::new(&mut storage);
+        for datum in TEST_DATA[0..7].iter() {
+            tree.push(datum).unwrap();
+        }
+
+        // The peaks of a tree with seven leaves are at 03, 09 and 12.
+        faults.drop_key(9);
+        let err =
+            FaultyTree::load(&mut storage, 7).expect_err("Expected load() to return Error; got Ok");
+        assert!(matches!(err, MerkleTreeError::LoadError(9)));
+    }
+
+    #[test]
+    fn test_prove_with_failing_reads_returns_a_storage_error() {
+        let mut storage = FaultyStorage::new(StorageMap::<TestTable>::new());
+        let faults = storage.faults();
+        let mut tree = FaultyTree::new(&mut storage);
+        for datum in TEST_DATA[0..7].iter() {
+            tree.push(datum).unwrap();
+        }
+
+        faults.fail_reads_after(0);
+        let err = tree
+            .prove(0)
+            .expect_err("Expected prove() to return Error; got Ok");
+        assert!(matches!(
+            err,
+            MerkleTreeError::StorageError(FaultError::ReadFailed)
+        ));
+
+        faults.clear();
+        assert!(tree.prove(0).is_ok());
+    }
+
+    #[test]
+    fn test_prove_with_a_missing_side_node_returns_a_load_error() {
+        let mut storage = FaultyStorage::new(StorageMap::<TestTable>::new());
+        let faults = storage.faults();
+        let mut tree = FaultyTree::new(&mut storage);
+        for datum in TEST_DATA[0..7].iter() {
+            tree.push(datum).unwrap();
+        }
+
+        // The side nodes of leaf 0 are 02, 05 and 11; 11 is built in scratch
+        // storage.
+        faults.drop_key(5);
+        let err = tree
+            .prove(0)
+            .expect_err("Expected prove() to return Error; got Ok");
+        assert!(matches!(err, MerkleTreeError::LoadError(5)));
+    }
 }
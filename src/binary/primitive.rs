@@ -1,8 +1,15 @@
 use crate::{
     binary::Node,
-    common::{Bytes32, Position},
+    common::{check_length, Bytes32, DeserializeError, Encoding, Position, VerifyKey},
 };
 
+/// **Buffer:**
+///
+/// | Allocation | Data                       |
+/// |------------|----------------------------|
+/// | `00 - 08`  | In-order index (8 bytes)   |
+/// | `08 - 40`  | Hash (32 bytes)            |
+///
 pub type Primitive = (u64, Bytes32);
 
 pub trait PrimitiveView {
@@ -33,3 +40,89 @@ impl From<Primitive> for Node {
         Node::new(position, hash)
     }
 }
+
+impl VerifyKey<u64> for Primitive {
+    /// A node is stored under its in-order index. The data of a leaf is not
+    /// stored, so the hash cannot be recomputed from the value.
+    fn verify_key(&self, key: &u64) -> bool {
+        self.0 == *key
+    }
+}
+
+impl Encoding for Primitive {
+    const SIZE: usize = 40;
+
+    type Bytes = [u8; 40];
+
+    fn to_bytes(&self) -> Self::Bytes {
+        let mut bytes = [0u8; 40];
+        bytes[0..8].copy_from_slice(&self.0.to_be_bytes());
+        bytes[8..40].copy_from_slice(&self.1);
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self, DeserializeError> {
+        let bytes = check_length::<40>(bytes)?;
+        let index = u64::from_be_bytes(bytes[0..8].try_into().unwrap());
+        let hash = bytes[8..40].try_into().unwrap();
+        Ok((index, hash))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        binary::{Node, Primitive},
+        common::{DeserializeError, Encoding, Position},
+    };
+
+    #[test]
+    fn test_encoding_round_trips() {
+        let node = Node::create_leaf(3, b"DATA");
+        let primitive = Primitive::from(&node);
+
+        let bytes = primitive.to_bytes();
+        assert_eq!(bytes[0..8], 6u64.to_be_bytes());
+        assert_eq!(&bytes[8..40], node.hash());
+        assert_eq!(Primitive::from_bytes(&bytes).unwrap(), primitive);
+
+        let bytes = primitive.to_versioned_bytes();
+        assert_eq!(bytes.len(), 41);
+        assert_eq!(bytes[0], Primitive::VERSION);
+        assert_eq!(Primitive::from_versioned_bytes(&bytes).unwrap(), primitive);
+    }
+
+    #[test]
+    fn test_from_bytes_with_wrong_length_returns_invalid_length_error() {
+        let primitive = Primitive::from(&Node::new(Position::from_in_order_index(5), [0xaa; 32]));
+        let bytes = primitive.to_bytes();
+
+        let err = Primitive::from_bytes(&bytes[..39]).unwrap_err();
+        assert!(matches!(
+            err,
+            DeserializeError::InvalidLength {
+                expected: 40,
+                received: 39
+            }
+        ));
+    }
+
+    #[test]
+    fn test_from_versioned_bytes_with_unknown_version_returns_unsupported_version_error() {
+        let primitive = Primitive::from(&Node::new(Position::from_in_order_index(5), [0xaa; 32]));
+        let mut bytes = primitive.to_versioned_bytes();
+        bytes[0] = 0xff;
+
+        let err = Primitive::from_versioned_bytes(&bytes).unwrap_err();
+        assert!(matches!(err, DeserializeError::UnsupportedVersion(0xff)));
+
+        let err = Primitive::from_versioned_bytes(&[]).unwrap_err();
+        assert!(matches!(
+            err,
+            DeserializeError::InvalidLength {
+                expected: 41,
+                received: 0
+            }
+        ));
+    }
+}
@@ -0,0 +1,192 @@
+use crate::{
+    binary::{MerkleTreeError, Node, Primitive},
+    common::Position,
+    storage::{Mappable, StorageMutate},
+};
+
+use alloc::vec::Vec;
+
+/// The interior nodes written by [`rebuild`], by in-order index.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RebuildReport {
+    /// The nodes that were missing from storage.
+    pub restored: Vec<u64>,
+    /// The nodes that were in storage with a wrong hash or in-order index.
+    pub replaced: Vec<u64>,
+}
+
+impl RebuildReport {
+    /// Returns true if no nodes were written.
+    pub fn is_empty(&self) -> bool {
+        self.restored.is_empty() && self.replaced.is_empty()
+    }
+}
+
+/// Rebuild the interior nodes of the binary Merkle tree with the given number
+/// of leaves from its leaves. Every interior node at the head of a balanced
+/// subtree is recomputed from the leaves stored at the even in-order indices,
+/// and written back if it is missing from storage or does not match the
+/// recomputed node. The nodes written are listed in the returned report.
+///
+/// The leaves are the source of truth and are not checked: their data is not
+/// stored, so their hashes cannot be recomputed. If a leaf is missing, the
+/// tree cannot be rebuilt, and a [`MerkleTreeError::LoadError`] with the
+/// in-order index of the leaf is returned before anything is written.
+pub fn rebuild<TableType, StorageType, StorageError>(
+    storage: &mut StorageType,
+    leaves_count: u64,
+) -> Result<RebuildReport, MerkleTreeError<StorageError>>
+where
+    TableType: Mappable<Key = u64, SetValue = Primitive, GetValue = Primitive>,
+    StorageType: StorageMutate<TableType, Error = StorageError>,
+{
+    let mut report = RebuildReport::default();
+
+    let keys = (0..leaves_count)
+        .map(|index| Position::from_leaf_index(index).in_order_index())
+        .collect::<Vec<_>>();
+    let mut level = keys
+        .into_iter()
+        .map(|key| {
+            let primitive = storage.get(&key)?.ok_or(MerkleTreeError::LoadError(key))?;
+            Ok(Node::new(
+                Position::from_in_order_index(key),
+                primitive.into_owned().1,
+            ))
+        })
+        .collect::<Result<Vec<_>, MerkleTreeError<StorageError>>>()?;
+
+    // Join the nodes of each level in pairs to form the next level, up to
+    // the level of the highest balanced subtree. A node left without a pair
+    // is the head of a balanced subtree and has no stored parent.
+    while level.len() > 1 {
+        let next_level = level
+            .chunks_exact(2)
+            .map(|pair| Node::create_node(&pair[0], &pair[1]))
+            .collect::<Vec<_>>();
+        let keys = next_level.iter().map(Node::key).collect::<Vec<_>>();
+        let stored = keys
+            .iter()
+            .map(|key| storage.get(key))
+            .collect::<Result<Vec<_>, _>>()?;
+        let fixes = next_level
+            .iter()
+            .zip(stored)
+            .filter_map(|(node, primitive)| match primitive {
+                None => Some((node, true)),
+                Some(primitive) if *primitive != Primitive::from(node) => Some((node, false)),
+                Some(_) => None,
+            })
+            .collect::<Vec<_>>();
+        for (node, missing) in fixes {
+            storage.insert(&node.key(), &Primitive::from(node))?;
+            if missing {
+                report.restored.push(node.key());
+            } else {
+                report.replaced.push(node.key());
+            }
+        }
+        level = next_level;
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        binary::{check, rebuild, MerkleTree, MerkleTreeError, Primitive},
+        common::StorageMap,
+        storage::{Mappable, StorageInspect, StorageMutate},
+    };
+    use alloc::vec::Vec;
+    use fuel_merkle_test_helpers::TEST_DATA;
+
+    #[derive(Debug)]
+    struct TestTable;
+
+    impl Mappable for TestTable {
+        type Key = u64;
+        type SetValue = Primitive;
+        type GetValue = Self::SetValue;
+    }
+
+    fn storage_with_leaves(n: usize) -> StorageMap<TestTable> {
+        let mut storage = StorageMap::<TestTable>::new();
+        let mut tree = MerkleTree::new(&mut storage);
+        for datum in TEST_DATA[0..n].iter() {
+            tree.push(datum).unwrap();
+        }
+        storage
+    }
+
+    fn proofs(storage: &mut StorageMap<TestTable>, n: usize) -> Vec<Vec<[u8; 32]>> {
+        let tree = MerkleTree::load(storage, n as u64).unwrap();
+        (0..n as u64)
+            .map(|index| tree.prove(index).unwrap().1)
+            .collect()
+    }
+
+    #[test]
+    fn test_rebuild_of_a_valid_tree_writes_nothing() {
+        for n in 0..TEST_DATA.len() {
+            let mut storage = storage_with_leaves(n);
+            let report = rebuild::<TestTable, _, _>(&mut storage, n as u64).unwrap();
+            assert!(report.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_rebuild_restores_missing_interior_nodes() {
+        let n = 7;
+        let mut storage = storage_with_leaves(n);
+        let expected_proofs = proofs(&mut storage, n);
+
+        // Remove every interior node.
+        for key in (1..2 * n as u64).step_by(2) {
+            let _ = storage.remove(&key);
+        }
+        assert!(matches!(
+            MerkleTree::load(&mut storage, n as u64),
+            Err(MerkleTreeError::LoadError(_))
+        ));
+
+        let report = rebuild::<TestTable, _, _>(&mut storage, n as u64).unwrap();
+        assert_eq!(report.restored, [1, 5, 9, 3]);
+        assert!(report.replaced.is_empty());
+
+        assert!(check::<TestTable, _>(&storage, n as u64).unwrap().is_ok());
+        assert_eq!(proofs(&mut storage, n), expected_proofs);
+    }
+
+    #[test]
+    fn test_rebuild_replaces_corrupt_interior_nodes() {
+        let n = 8;
+        let mut storage = storage_with_leaves(n);
+        let expected_proofs = proofs(&mut storage, n);
+
+        let (_, hash) = storage.get(&0).unwrap().unwrap().into_owned();
+        storage.insert(&3, &(3, hash)).unwrap();
+        storage.insert(&9, &(11, hash)).unwrap();
+
+        let report = rebuild::<TestTable, _, _>(&mut storage, n as u64).unwrap();
+        assert!(report.restored.is_empty());
+        assert_eq!(report.replaced, [9, 3]);
+
+        assert!(check::<TestTable, _>(&storage, n as u64).unwrap().is_ok());
+        assert_eq!(proofs(&mut storage, n), expected_proofs);
+    }
+
+    #[test]
+    fn test_rebuild_with_a_missing_leaf_returns_a_load_error() {
+        let n = 7;
+        let mut storage = storage_with_leaves(n);
+        storage.remove(&1).unwrap();
+        storage.remove(&4).unwrap();
+
+        let err = rebuild::<TestTable, _, _>(&mut storage, n as u64)
+            .expect_err("Expected rebuild() to return Error; got Ok");
+        assert!(matches!(err, MerkleTreeError::LoadError(4)));
+        assert!(!storage.contains_key(&1).unwrap());
+    }
+}
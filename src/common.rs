@@ -1,25 +1,49 @@
+mod encoding;
+mod instrumented_storage;
+mod integrity;
 mod msb;
+mod namespaced_storage;
 mod node;
 mod path_iterator;
 mod position;
 mod position_path;
 mod prefix;
+mod storage_cache;
 mod storage_map;
+mod storage_overlay;
 mod subtree;
+mod verifying_storage;
 
 pub(crate) mod error;
+#[cfg(test)]
+pub(crate) mod faulty_storage;
 pub(crate) mod path;
+#[cfg(feature = "serde")]
+pub mod serde;
 
+pub use encoding::Encoding;
+pub use error::DeserializeError;
+pub use instrumented_storage::{
+    InstrumentedStorage, StorageHook, StorageMetrics, StorageOperation,
+};
+pub use integrity::{IntegrityIssue, IntegrityReport};
+pub use namespaced_storage::{Namespaced, NamespacedStorage};
 pub use node::{Node, ParentNode};
 pub use path_iterator::AsPathIterator;
 pub use position::Position;
+pub use storage_cache::StorageCache;
 pub use storage_map::StorageMap;
+pub use storage_overlay::StorageOverlay;
 pub use subtree::Subtree;
+pub use verifying_storage::{IntegrityError, SumVerifyingStorage, VerifyKey, VerifyingStorage};
 
+pub(crate) use encoding::check_length;
 pub(crate) use msb::{Bit, Msb};
 pub(crate) use node::{ChildError, ChildResult};
 pub(crate) use position_path::PositionPath;
-pub(crate) use prefix::{Prefix, PrefixError};
+pub(crate) use prefix::Prefix;
+pub use prefix::PrefixError;
+pub(crate) use storage_overlay::ChangeSet;
 
 pub type Bytes1 = [u8; 1];
 pub type Bytes2 = [u8; 2];
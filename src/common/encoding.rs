@@ -0,0 +1,58 @@
+use crate::common::error::DeserializeError;
+
+use alloc::vec::Vec;
+
+/// A stable byte encoding of a value stored by a tree, such as a node
+/// primitive. The encoding is independent of the in-memory layout of the
+/// value, so the same tree can be stored in the same format by any storage
+/// backend.
+///
+/// Multi-byte integers are encoded in big-endian order. The encoding returned
+/// by [`to_bytes`](Self::to_bytes) has a fixed size and no version; a backend
+/// that needs to migrate its data between versions of the encoding stores the
+/// [versioned](Self::to_versioned_bytes) encoding instead, prefixed with the
+/// version byte.
+pub trait Encoding: Sized {
+    /// The version of the encoding.
+    const VERSION: u8 = 1;
+
+    /// The size of the encoding, in bytes.
+    const SIZE: usize;
+
+    type Bytes: AsRef<[u8]>;
+
+    fn to_bytes(&self) -> Self::Bytes;
+
+    /// Decode a value from exactly [`SIZE`](Self::SIZE) bytes.
+    fn from_bytes(bytes: &[u8]) -> Result<Self, DeserializeError>;
+
+    /// The encoding prefixed with the version byte.
+    fn to_versioned_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(1 + Self::SIZE);
+        bytes.push(Self::VERSION);
+        bytes.extend_from_slice(self.to_bytes().as_ref());
+        bytes
+    }
+
+    /// Decode a value from its encoding prefixed with the version byte.
+    fn from_versioned_bytes(bytes: &[u8]) -> Result<Self, DeserializeError> {
+        match bytes.split_first() {
+            Some((&version, bytes)) if version == Self::VERSION => Self::from_bytes(bytes),
+            Some((&version, _)) => Err(DeserializeError::UnsupportedVersion(version)),
+            None => Err(DeserializeError::InvalidLength {
+                expected: 1 + Self::SIZE,
+                received: 0,
+            }),
+        }
+    }
+}
+
+/// Check that the encoding of a value has the expected size.
+pub(crate) fn check_length<const N: usize>(bytes: &[u8]) -> Result<&[u8; N], DeserializeError> {
+    bytes
+        .try_into()
+        .map_err(|_| DeserializeError::InvalidLength {
+            expected: N,
+            received: bytes.len(),
+        })
+}
@@ -5,6 +5,18 @@ use crate::common::PrefixError;
 pub enum DeserializeError {
     #[cfg_attr(feature = "std", error(transparent))]
     PrefixError(PrefixError),
+
+    #[cfg_attr(
+        feature = "std",
+        error("expected {expected} bytes; received {received} bytes")
+    )]
+    InvalidLength { expected: usize, received: usize },
+
+    #[cfg_attr(feature = "std", error("encoding version {0} is not supported"))]
+    UnsupportedVersion(u8),
+
+    #[cfg_attr(feature = "std", error("the bytes are not a canonical encoding"))]
+    NonCanonical,
 }
 
 impl From<PrefixError> for DeserializeError {
@@ -0,0 +1,198 @@
+use crate::storage::{
+    Mappable, StorageInspect, StorageInspectBatch, StorageMutate, StorageMutateBatch,
+};
+
+use alloc::{borrow::Cow, rc::Rc};
+use core::{cell::RefCell, hash::Hash};
+use hashbrown::{HashMap, HashSet};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum FaultError {
+    ReadFailed,
+    WriteFailed,
+}
+
+#[derive(Debug)]
+struct Faults<Key, Value> {
+    reads_before_failure: Option<usize>,
+    writes_before_failure: Option<usize>,
+    dropped: HashSet<Key>,
+    corrupted: HashMap<Key, Value>,
+}
+
+impl<Key, Value> Default for Faults<Key, Value> {
+    fn default() -> Self {
+        Self {
+            reads_before_failure: None,
+            writes_before_failure: None,
+            dropped: HashSet::new(),
+            corrupted: HashMap::new(),
+        }
+    }
+}
+
+/// Count down the operations before a failure; returns true if the operation
+/// fails.
+fn fails(operations_before_failure: &mut Option<usize>) -> bool {
+    match operations_before_failure {
+        Some(0) => true,
+        Some(n) => {
+            *n -= 1;
+            false
+        }
+        None => false,
+    }
+}
+
+/// A handle to the faults injected into a [`FaultyStorage`]. The handle
+/// remains usable while a tree holds the storage, so faults can be injected
+/// and cleared between the tree's operations.
+#[derive(Debug)]
+pub(crate) struct FaultHandle<Type: Mappable> {
+    faults: Rc<RefCell<Faults<Type::Key, Type::GetValue>>>,
+}
+
+impl<Type> FaultHandle<Type>
+where
+    Type: Mappable,
+    Type::Key: Eq + Hash,
+{
+    /// Let `n` more reads succeed, then fail every read.
+    pub(crate) fn fail_reads_after(&self, n: usize) {
+        self.faults.borrow_mut().reads_before_failure = Some(n);
+    }
+
+    /// Let `n` more writes succeed, then fail every write.
+    pub(crate) fn fail_writes_after(&self, n: usize) {
+        self.faults.borrow_mut().writes_before_failure = Some(n);
+    }
+
+    /// Read the given key as if it were not in storage.
+    pub(crate) fn drop_key(&self, key: Type::Key) {
+        self.faults.borrow_mut().dropped.insert(key);
+    }
+
+    /// Read the given value in place of the value stored under the key.
+    pub(crate) fn corrupt(&self, key: Type::Key, value: Type::GetValue) {
+        self.faults.borrow_mut().corrupted.insert(key, value);
+    }
+
+    /// Remove all faults.
+    pub(crate) fn clear(&self) {
+        *self.faults.borrow_mut() = Faults::default();
+    }
+}
+
+/// # Faulty Storage
+///
+/// A test storage that injects faults into the storage it wraps: it fails
+/// reads or writes after a number of operations, reads specific keys as
+/// missing, or reads corrupted values in place of the stored values. Faults
+/// are injected through a [`FaultHandle`].
+#[derive(Debug)]
+pub(crate) struct FaultyStorage<Type: Mappable, StorageType> {
+    storage: StorageType,
+    faults: Rc<RefCell<Faults<Type::Key, Type::GetValue>>>,
+}
+
+impl<Type, StorageType> FaultyStorage<Type, StorageType>
+where
+    Type: Mappable,
+    Type::Key: Eq + Hash,
+{
+    pub(crate) fn new(storage: StorageType) -> Self {
+        Self {
+            storage,
+            faults: Rc::new(RefCell::new(Faults::default())),
+        }
+    }
+
+    pub(crate) fn faults(&self) -> FaultHandle<Type> {
+        FaultHandle {
+            faults: self.faults.clone(),
+        }
+    }
+}
+
+impl<Type, StorageType> StorageInspect<Type> for FaultyStorage<Type, StorageType>
+where
+    Type: Mappable,
+    Type::Key: Eq + Hash,
+    StorageType: StorageInspect<Type>,
+{
+    type Error = FaultError;
+
+    fn get(&self, key: &Type::Key) -> Result<Option<Cow<'_, Type::GetValue>>, Self::Error> {
+        let mut faults = self.faults.borrow_mut();
+        if fails(&mut faults.reads_before_failure) {
+            return Err(FaultError::ReadFailed);
+        }
+        if faults.dropped.contains(key) {
+            return Ok(None);
+        }
+        if let Some(value) = faults.corrupted.get(key) {
+            return Ok(Some(Cow::Owned(value.clone())));
+        }
+        drop(faults);
+        self.storage.get(key).map_err(|_| FaultError::ReadFailed)
+    }
+
+    fn contains_key(&self, key: &Type::Key) -> Result<bool, Self::Error> {
+        let mut faults = self.faults.borrow_mut();
+        if fails(&mut faults.reads_before_failure) {
+            return Err(FaultError::ReadFailed);
+        }
+        if faults.dropped.contains(key) {
+            return Ok(false);
+        }
+        drop(faults);
+        self.storage
+            .contains_key(key)
+            .map_err(|_| FaultError::ReadFailed)
+    }
+}
+
+impl<Type, StorageType> StorageInspectBatch<Type> for FaultyStorage<Type, StorageType>
+where
+    Type: Mappable,
+    Type::Key: Eq + Hash,
+    StorageType: StorageInspect<Type>,
+{
+}
+
+impl<Type, StorageType> StorageMutate<Type> for FaultyStorage<Type, StorageType>
+where
+    Type: Mappable,
+    Type::Key: Eq + Hash,
+    StorageType: StorageMutate<Type>,
+{
+    fn insert(
+        &mut self,
+        key: &Type::Key,
+        value: &Type::SetValue,
+    ) -> Result<Option<Type::GetValue>, Self::Error> {
+        if fails(&mut self.faults.borrow_mut().writes_before_failure) {
+            return Err(FaultError::WriteFailed);
+        }
+        self.storage
+            .insert(key, value)
+            .map_err(|_| FaultError::WriteFailed)
+    }
+
+    fn remove(&mut self, key: &Type::Key) -> Result<Option<Type::GetValue>, Self::Error> {
+        if fails(&mut self.faults.borrow_mut().writes_before_failure) {
+            return Err(FaultError::WriteFailed);
+        }
+        self.storage
+            .remove(key)
+            .map_err(|_| FaultError::WriteFailed)
+    }
+}
+
+impl<Type, StorageType> StorageMutateBatch<Type> for FaultyStorage<Type, StorageType>
+where
+    Type: Mappable,
+    Type::Key: Eq + Hash,
+    StorageType: StorageMutate<Type>,
+{
+}
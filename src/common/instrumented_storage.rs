@@ -0,0 +1,456 @@
+use crate::{
+    common::Encoding,
+    storage::{
+        GetBatchResult, Mappable, StorageInspect, StorageInspectBatch, StorageMutate,
+        StorageMutateBatch,
+    },
+};
+
+use alloc::borrow::Cow;
+use core::{
+    cell::{Cell, RefCell},
+    hash::Hash,
+};
+use hashbrown::HashSet;
+
+/// The counts of the storage operations performed through an
+/// [`InstrumentedStorage`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StorageMetrics {
+    /// The number of keys read, including the keys read in batches.
+    pub gets: u64,
+    /// The number of batch reads.
+    pub batch_gets: u64,
+    pub contains_keys: u64,
+    pub inserts: u64,
+    pub removes: u64,
+    /// The size of the values inserted, in their [`Encoding`].
+    pub bytes_written: u64,
+    /// The number of distinct keys read, inserted, removed, or checked, or
+    /// `None` if the storage does not
+    /// [track the keys touched](InstrumentedStorage::track_keys_touched).
+    pub keys_touched: Option<u64>,
+}
+
+/// A storage operation reported to a [`StorageHook`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StorageOperation {
+    /// A read of a single key; `true` if the key was found.
+    Get(bool),
+    /// A batch read of the given number of keys.
+    GetBatch(usize),
+    ContainsKey,
+    /// An insert of a value of the given encoded size.
+    Insert(usize),
+    Remove,
+}
+
+/// A hook notified of each storage operation performed through an
+/// [`InstrumentedStorage`], to forward the operations to an external metrics
+/// system. The hook is called after the operation succeeds.
+pub trait StorageHook {
+    fn on_operation(&self, operation: StorageOperation);
+}
+
+impl StorageHook for () {
+    fn on_operation(&self, _operation: StorageOperation) {}
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    gets: Cell<u64>,
+    batch_gets: Cell<u64>,
+    contains_keys: Cell<u64>,
+    inserts: Cell<u64>,
+    removes: Cell<u64>,
+    bytes_written: Cell<u64>,
+}
+
+fn increment(counter: &Cell<u64>, n: u64) {
+    counter.set(counter.get() + n);
+}
+
+/// # Instrumented Storage
+///
+/// A storage wrapper that counts the operations performed on the storage it
+/// wraps: reads, batch reads, `contains_key` checks, inserts, removes, and
+/// the bytes written. Only successful operations are counted.
+///
+/// Counting the distinct keys touched requires holding the keys in memory
+/// until the metrics are reset, and is enabled with
+/// [`track_keys_touched`](Self::track_keys_touched).
+///
+/// To measure the cost of a single tree operation, take the metrics with
+/// [`take_metrics`](Self::take_metrics) after the operation; this returns
+/// the counts since the previous call and resets them.
+///
+/// Each operation is also reported to an optional [`StorageHook`].
+#[derive(Debug)]
+pub struct InstrumentedStorage<Type: Mappable, StorageType, Hook = ()> {
+    storage: StorageType,
+    hook: Hook,
+    counters: Counters,
+    keys_touched: Option<RefCell<HashSet<Type::Key>>>,
+}
+
+impl<Type, StorageType> InstrumentedStorage<Type, StorageType>
+where
+    Type: Mappable,
+    Type::Key: Eq + Hash + Clone,
+{
+    pub fn new(storage: StorageType) -> Self {
+        Self::with_hook(storage, ())
+    }
+}
+
+impl<Type, StorageType, Hook> InstrumentedStorage<Type, StorageType, Hook>
+where
+    Type: Mappable,
+    Type::Key: Eq + Hash + Clone,
+    Hook: StorageHook,
+{
+    pub fn with_hook(storage: StorageType, hook: Hook) -> Self {
+        Self {
+            storage,
+            hook,
+            counters: Counters::default(),
+            keys_touched: None,
+        }
+    }
+
+    /// Count the distinct keys touched. Each key touched is held in memory
+    /// until the metrics are reset, so the memory used grows with the number
+    /// of distinct keys touched between resets.
+    pub fn track_keys_touched(mut self) -> Self {
+        self.keys_touched = Some(RefCell::new(HashSet::new()));
+        self
+    }
+
+    /// The counts of the operations performed since the storage was created
+    /// or the metrics were last reset.
+    pub fn metrics(&self) -> StorageMetrics {
+        StorageMetrics {
+            gets: self.counters.gets.get(),
+            batch_gets: self.counters.batch_gets.get(),
+            contains_keys: self.counters.contains_keys.get(),
+            inserts: self.counters.inserts.get(),
+            removes: self.counters.removes.get(),
+            bytes_written: self.counters.bytes_written.get(),
+            keys_touched: self
+                .keys_touched
+                .as_ref()
+                .map(|keys_touched| keys_touched.borrow().len() as u64),
+        }
+    }
+
+    pub fn reset_metrics(&self) {
+        if let Some(keys_touched) = self.keys_touched.as_ref() {
+            keys_touched.borrow_mut().clear();
+        }
+        for counter in [
+            &self.counters.gets,
+            &self.counters.batch_gets,
+            &self.counters.contains_keys,
+            &self.counters.inserts,
+            &self.counters.removes,
+            &self.counters.bytes_written,
+        ] {
+            counter.set(0);
+        }
+    }
+
+    /// Return the metrics and reset them.
+    pub fn take_metrics(&self) -> StorageMetrics {
+        let metrics = self.metrics();
+        self.reset_metrics();
+        metrics
+    }
+
+    pub fn into_inner(self) -> StorageType {
+        self.storage
+    }
+
+    fn touch(&self, key: &Type::Key) {
+        if let Some(keys_touched) = self.keys_touched.as_ref() {
+            let mut keys_touched = keys_touched.borrow_mut();
+            if !keys_touched.contains(key) {
+                keys_touched.insert(key.clone());
+            }
+        }
+    }
+}
+
+impl<Type, StorageType, Hook> StorageInspect<Type> for InstrumentedStorage<Type, StorageType, Hook>
+where
+    Type: Mappable,
+    Type::Key: Eq + Hash + Clone,
+    StorageType: StorageInspect<Type>,
+    Hook: StorageHook,
+{
+    type Error = StorageType::Error;
+
+    fn get(&self, key: &Type::Key) -> Result<Option<Cow<'_, Type::GetValue>>, Self::Error> {
+        let value = self.storage.get(key)?;
+        increment(&self.counters.gets, 1);
+        self.touch(key);
+        self.hook
+            .on_operation(StorageOperation::Get(value.is_some()));
+        Ok(value)
+    }
+
+    fn contains_key(&self, key: &Type::Key) -> Result<bool, Self::Error> {
+        let contains = self.storage.contains_key(key)?;
+        increment(&self.counters.contains_keys, 1);
+        self.touch(key);
+        self.hook.on_operation(StorageOperation::ContainsKey);
+        Ok(contains)
+    }
+}
+
+impl<Type, StorageType, Hook> StorageInspectBatch<Type>
+    for InstrumentedStorage<Type, StorageType, Hook>
+where
+    Type: Mappable,
+    Type::Key: Eq + Hash + Clone,
+    StorageType: StorageInspectBatch<Type>,
+    Hook: StorageHook,
+{
+    fn get_batch(&self, keys: &[Type::Key]) -> GetBatchResult<'_, Type::GetValue, Self::Error> {
+        let values = self.storage.get_batch(keys)?;
+        increment(&self.counters.gets, keys.len() as u64);
+        increment(&self.counters.batch_gets, 1);
+        for key in keys {
+            self.touch(key);
+        }
+        self.hook
+            .on_operation(StorageOperation::GetBatch(keys.len()));
+        Ok(values)
+    }
+}
+
+impl<Type, StorageType, Hook> StorageMutate<Type> for InstrumentedStorage<Type, StorageType, Hook>
+where
+    Type: Mappable,
+    Type::Key: Eq + Hash + Clone,
+    Type::SetValue: Encoding,
+    StorageType: StorageMutate<Type>,
+    Hook: StorageHook,
+{
+    fn insert(
+        &mut self,
+        key: &Type::Key,
+        value: &Type::SetValue,
+    ) -> Result<Option<Type::GetValue>, Self::Error> {
+        let previous = self.storage.insert(key, value)?;
+        let size = Type::SetValue::SIZE;
+        increment(&self.counters.inserts, 1);
+        increment(&self.counters.bytes_written, size as u64);
+        self.touch(key);
+        self.hook.on_operation(StorageOperation::Insert(size));
+        Ok(previous)
+    }
+
+    fn remove(&mut self, key: &Type::Key) -> Result<Option<Type::GetValue>, Self::Error> {
+        let previous = self.storage.remove(key)?;
+        increment(&self.counters.removes, 1);
+        self.touch(key);
+        self.hook.on_operation(StorageOperation::Remove);
+        Ok(previous)
+    }
+}
+
+impl<Type, StorageType, Hook> StorageMutateBatch<Type>
+    for InstrumentedStorage<Type, StorageType, Hook>
+where
+    Type: Mappable,
+    Type::Key: Eq + Hash + Clone,
+    Type::SetValue: Encoding,
+    StorageType: StorageMutateBatch<Type>,
+    Hook: StorageHook,
+{
+    /// Write the changes to the backend in a single batch, counting each
+    /// change as an insert or a remove.
+    fn write_batch(
+        &mut self,
+        changes: &[(&Type::Key, Option<&Type::SetValue>)],
+    ) -> Result<(), Self::Error> {
+        self.storage.write_batch(changes)?;
+        for (key, value) in changes {
+            let operation = match value {
+                Some(_) => {
+                    let size = Type::SetValue::SIZE;
+                    increment(&self.counters.inserts, 1);
+                    increment(&self.counters.bytes_written, size as u64);
+                    StorageOperation::Insert(size)
+                }
+                None => {
+                    increment(&self.counters.removes, 1);
+                    StorageOperation::Remove
+                }
+            };
+            self.touch(key);
+            self.hook.on_operation(operation);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        binary,
+        common::{Bytes32, StorageMap},
+        sparse,
+    };
+    use alloc::{vec, vec::Vec};
+    use digest::Digest;
+    use sha2::Sha256;
+
+    #[derive(Debug)]
+    struct TestTable;
+
+    impl Mappable for TestTable {
+        type Key = u32;
+        type SetValue = binary::Primitive;
+        type GetValue = Self::SetValue;
+    }
+
+    #[test]
+    fn test_metrics_count_each_operation() {
+        let mut storage =
+            InstrumentedStorage::new(StorageMap::<TestTable>::new()).track_keys_touched();
+        let _ = storage.insert(&0, &(0, [0; 32]));
+        let _ = storage.insert(&1, &(1, [1; 32]));
+        let _ = storage.get(&0);
+        let _ = storage.get(&2);
+        let _ = storage.get_batch(&[0, 1, 3]);
+        let _ = storage.contains_key(&1);
+        let _ = storage.remove(&0);
+
+        let expected_metrics = StorageMetrics {
+            gets: 5,
+            batch_gets: 1,
+            contains_keys: 1,
+            inserts: 2,
+            removes: 1,
+            bytes_written: 80,
+            keys_touched: Some(4),
+        };
+        assert_eq!(storage.take_metrics(), expected_metrics);
+
+        let expected_metrics = StorageMetrics {
+            keys_touched: Some(0),
+            ..StorageMetrics::default()
+        };
+        assert_eq!(storage.metrics(), expected_metrics);
+    }
+
+    #[test]
+    fn test_keys_touched_are_not_counted_unless_tracked() {
+        let mut storage = InstrumentedStorage::new(StorageMap::<TestTable>::new());
+        let _ = storage.insert(&0, &(0, [0; 32]));
+        let _ = storage.get(&1);
+
+        let metrics = storage.metrics();
+        assert_eq!(metrics.inserts, 1);
+        assert_eq!(metrics.gets, 1);
+        assert_eq!(metrics.keys_touched, None);
+    }
+
+    #[derive(Default)]
+    struct RecordingHook {
+        operations: RefCell<Vec<StorageOperation>>,
+    }
+
+    impl StorageHook for &RecordingHook {
+        fn on_operation(&self, operation: StorageOperation) {
+            self.operations.borrow_mut().push(operation);
+        }
+    }
+
+    #[test]
+    fn test_hook_is_notified_of_each_operation() {
+        let hook = RecordingHook::default();
+        let mut storage = InstrumentedStorage::with_hook(StorageMap::<TestTable>::new(), &hook);
+        let _ = storage.insert(&0, &(0, [0; 32]));
+        let _ = storage.get(&0);
+        let _ = storage.get(&1);
+        let _ = storage.get_batch(&[0, 1]);
+        let _ = storage.contains_key(&0);
+        let _ = storage.remove(&0);
+
+        let expected_operations = vec![
+            StorageOperation::Insert(40),
+            StorageOperation::Get(true),
+            StorageOperation::Get(false),
+            StorageOperation::GetBatch(2),
+            StorageOperation::ContainsKey,
+            StorageOperation::Remove,
+        ];
+        assert_eq!(*hook.operations.borrow(), expected_operations);
+    }
+
+    #[derive(Debug)]
+    struct SparseNodesTable;
+
+    impl Mappable for SparseNodesTable {
+        type Key = Bytes32;
+        type SetValue = sparse::Primitive;
+        type GetValue = Self::SetValue;
+    }
+
+    #[derive(Debug)]
+    struct BinaryNodesTable;
+
+    impl Mappable for BinaryNodesTable {
+        type Key = u64;
+        type SetValue = binary::Primitive;
+        type GetValue = Self::SetValue;
+    }
+
+    fn sum<D: AsRef<[u8]>>(data: D) -> Bytes32 {
+        Sha256::digest(data.as_ref()).into()
+    }
+
+    #[test]
+    fn test_metrics_measure_a_sparse_tree_update() {
+        let mut storage = InstrumentedStorage::new(StorageMap::<SparseNodesTable>::new());
+        let mut tree = sparse::MerkleTree::new(&mut storage);
+        for i in 0_u32..10 {
+            tree.update(&sum(i.to_be_bytes()), b"DATA").unwrap();
+        }
+        let root = tree.root();
+        storage.reset_metrics();
+
+        let mut tree = sparse::MerkleTree::load(&mut storage, &root)
+            .unwrap()
+            .with_batch_reads();
+        tree.update(&sum(10_u32.to_be_bytes()), b"DATA").unwrap();
+        let metrics = storage.take_metrics();
+
+        // Loading the tree reads the root; the update reads the path of the
+        // new leaf in batches, writes the leaf by hash and by key, and writes
+        // the new nodes of the path.
+        assert!(metrics.batch_gets > 0);
+        assert_eq!(metrics.gets, 1 + 2 * metrics.batch_gets);
+        assert!(metrics.inserts > 2);
+        assert_eq!(metrics.removes, 0);
+    }
+
+    #[test]
+    fn test_metrics_measure_a_binary_tree_push() {
+        let mut storage =
+            InstrumentedStorage::new(StorageMap::<BinaryNodesTable>::new()).track_keys_touched();
+        let mut tree = binary::MerkleTree::new(&mut storage);
+        for i in 0_u32..3 {
+            tree.push(&i.to_be_bytes()).unwrap();
+        }
+        let metrics = storage.take_metrics();
+
+        // Three leaves, and the node joining the first two.
+        assert_eq!(metrics.inserts, 4);
+        assert_eq!(metrics.keys_touched, Some(4));
+        assert_eq!(metrics.removes, 0);
+    }
+}
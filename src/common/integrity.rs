@@ -0,0 +1,119 @@
+use alloc::vec::Vec;
+use core::hash::Hash;
+use hashbrown::HashSet;
+
+/// A problem found in a tree by an integrity check.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IntegrityIssue<Key> {
+    /// The node is referenced by the tree, but is not found in storage.
+    MissingNode(Key),
+    /// The value stored under the key cannot be read as a node, or does not
+    /// have the children its height requires.
+    MalformedNode(Key),
+    /// The hash of the node, recomputed from its contents or its children,
+    /// does not match the hash it is referenced by.
+    HashMismatch(Key),
+    /// The height of the node does not match its place in the tree.
+    HeightMismatch { key: Key, expected: u32, found: u32 },
+    /// The node is stored at a place in the tree that does not match its
+    /// position or, for a leaf of a sparse tree, its leaf key.
+    MisplacedNode(Key),
+    /// The leaf with the given leaf key is not stored under its leaf key.
+    MissingLeafKey(Key),
+    /// The fee of the node is not the sum of the fees of its children.
+    FeeMismatch(Key),
+    /// The key is in storage, but is not reachable from the tree.
+    Unreachable(Key),
+}
+
+/// # Integrity Report
+///
+/// The result of an integrity check of a tree, listing the nodes checked and
+/// every issue found. A check reads the whole tree, so it is meant to be run
+/// offline: after a crash, or after migrating the storage of a tree.
+///
+/// A check only reaches the nodes referenced by the tree. To also find the
+/// nodes left behind in storage, pass the keys in storage to
+/// [`find_unreachable`](Self::find_unreachable).
+#[derive(Debug, Clone)]
+pub struct IntegrityReport<Key> {
+    /// The number of nodes read, including leaves.
+    pub nodes_checked: u64,
+    /// The number of leaves read.
+    pub leaves_checked: u64,
+    /// The issues found, in the order they were found.
+    pub issues: Vec<IntegrityIssue<Key>>,
+    reachable: HashSet<Key>,
+}
+
+impl<Key> Default for IntegrityReport<Key> {
+    fn default() -> Self {
+        Self {
+            nodes_checked: 0,
+            leaves_checked: 0,
+            issues: Vec::new(),
+            reachable: HashSet::new(),
+        }
+    }
+}
+
+impl<Key> IntegrityReport<Key>
+where
+    Key: Eq + Hash + Clone,
+{
+    /// Returns true if no issues were found.
+    pub fn is_ok(&self) -> bool {
+        self.issues.is_empty()
+    }
+
+    /// Report each of the given keys that is not reachable from the tree as an
+    /// [`IntegrityIssue::Unreachable`] issue. The keys are typically all the
+    /// keys of the table holding the tree.
+    pub fn find_unreachable<I>(&mut self, stored_keys: I)
+    where
+        I: IntoIterator<Item = Key>,
+    {
+        let unreachable = stored_keys
+            .into_iter()
+            .filter(|key| !self.reachable.contains(key))
+            .map(IntegrityIssue::Unreachable);
+        self.issues.extend(unreachable);
+    }
+
+    /// Mark the key as reachable from the tree. Returns false if the key was
+    /// already reached.
+    pub(crate) fn reach(&mut self, key: &Key) -> bool {
+        if self.reachable.contains(key) {
+            return false;
+        }
+        self.reachable.insert(key.clone());
+        true
+    }
+
+    pub(crate) fn report(&mut self, issue: IntegrityIssue<Key>) {
+        self.issues.push(issue);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use alloc::vec;
+
+    #[test]
+    fn test_find_unreachable_reports_the_keys_not_reached() {
+        let mut report = IntegrityReport::<u64>::default();
+        report.reach(&1);
+        report.reach(&2);
+        assert!(report.is_ok());
+
+        report.find_unreachable(vec![0, 1, 2, 3]);
+        assert_eq!(
+            report.issues,
+            vec![
+                IntegrityIssue::Unreachable(0),
+                IntegrityIssue::Unreachable(3)
+            ]
+        );
+    }
+}
@@ -0,0 +1,251 @@
+use crate::storage::{
+    GetBatchResult, Mappable, StorageInspect, StorageInspectBatch, StorageMutate,
+    StorageMutateBatch,
+};
+
+use alloc::{borrow::Cow, vec::Vec};
+use core::marker::PhantomData;
+
+/// The view of a table keyed by `(Namespace, Key)` pairs as a table keyed by
+/// `Key`, for use with a [`NamespacedStorage`].
+#[derive(Debug)]
+pub struct Namespaced<TableType>(PhantomData<TableType>);
+
+impl<TableType, Namespace, Key> Mappable for Namespaced<TableType>
+where
+    TableType: Mappable<Key = (Namespace, Key)>,
+{
+    type Key = Key;
+    type SetValue = TableType::SetValue;
+    type GetValue = TableType::GetValue;
+}
+
+/// # Namespaced Storage
+///
+/// A storage adapter that lets many trees share a single table. The shared
+/// table `TableType` is keyed by `(Namespace, Key)` pairs, for example
+/// `(ContractId, Bytes32)`. The adapter is bound to a single namespace and
+/// exposes the entries of the shared table within that namespace as the table
+/// [`Namespaced<TableType>`], keyed by `Key` alone. Each key is prefixed with
+/// the namespace before it is passed to the underlying storage, so trees with
+/// different namespaces never read or overwrite each other's nodes.
+///
+/// ```ignore
+/// let storage = NamespacedStorage::new(&mut database, contract_id);
+/// let tree = sparse::MerkleTree::<Namespaced<ContractNodes>, _>::new(storage);
+/// ```
+#[derive(Debug)]
+pub struct NamespacedStorage<TableType, Namespace, StorageType> {
+    storage: StorageType,
+    namespace: Namespace,
+    phantom_table: PhantomData<TableType>,
+}
+
+impl<TableType, Namespace, StorageType> NamespacedStorage<TableType, Namespace, StorageType> {
+    pub fn new(storage: StorageType, namespace: Namespace) -> Self {
+        Self {
+            storage,
+            namespace,
+            phantom_table: Default::default(),
+        }
+    }
+
+    pub fn namespace(&self) -> &Namespace {
+        &self.namespace
+    }
+
+    pub fn into_inner(self) -> StorageType {
+        self.storage
+    }
+
+    fn namespaced_key<Key: Clone>(&self, key: &Key) -> (Namespace, Key)
+    where
+        Namespace: Clone,
+    {
+        (self.namespace.clone(), key.clone())
+    }
+}
+
+impl<TableType, Namespace, Key, StorageType> StorageInspect<Namespaced<TableType>>
+    for NamespacedStorage<TableType, Namespace, StorageType>
+where
+    TableType: Mappable<Key = (Namespace, Key)>,
+    Namespace: Clone,
+    Key: Clone,
+    StorageType: StorageInspect<TableType>,
+{
+    type Error = StorageType::Error;
+
+    fn get(&self, key: &Key) -> Result<Option<Cow<'_, TableType::GetValue>>, Self::Error> {
+        self.storage.get(&self.namespaced_key(key))
+    }
+
+    fn contains_key(&self, key: &Key) -> Result<bool, Self::Error> {
+        self.storage.contains_key(&self.namespaced_key(key))
+    }
+}
+
+impl<TableType, Namespace, Key, StorageType> StorageInspectBatch<Namespaced<TableType>>
+    for NamespacedStorage<TableType, Namespace, StorageType>
+where
+    TableType: Mappable<Key = (Namespace, Key)>,
+    Namespace: Clone,
+    Key: Clone,
+    StorageType: StorageInspectBatch<TableType>,
+{
+    fn get_batch(&self, keys: &[Key]) -> GetBatchResult<'_, TableType::GetValue, Self::Error> {
+        let keys = keys
+            .iter()
+            .map(|key| self.namespaced_key(key))
+            .collect::<Vec<_>>();
+        self.storage.get_batch(&keys)
+    }
+}
+
+impl<TableType, Namespace, Key, StorageType> StorageMutate<Namespaced<TableType>>
+    for NamespacedStorage<TableType, Namespace, StorageType>
+where
+    TableType: Mappable<Key = (Namespace, Key)>,
+    Namespace: Clone,
+    Key: Clone,
+    StorageType: StorageMutate<TableType>,
+{
+    fn insert(
+        &mut self,
+        key: &Key,
+        value: &TableType::SetValue,
+    ) -> Result<Option<TableType::GetValue>, Self::Error> {
+        let key = self.namespaced_key(key);
+        self.storage.insert(&key, value)
+    }
+
+    fn remove(&mut self, key: &Key) -> Result<Option<TableType::GetValue>, Self::Error> {
+        let key = self.namespaced_key(key);
+        self.storage.remove(&key)
+    }
+}
+
+impl<TableType, Namespace, Key, StorageType> StorageMutateBatch<Namespaced<TableType>>
+    for NamespacedStorage<TableType, Namespace, StorageType>
+where
+    TableType: Mappable<Key = (Namespace, Key)>,
+    Namespace: Clone,
+    Key: Clone,
+    StorageType: StorageMutateBatch<TableType>,
+{
+    fn write_batch(
+        &mut self,
+        changes: &[(&Key, Option<&TableType::SetValue>)],
+    ) -> Result<(), Self::Error> {
+        let keys = changes
+            .iter()
+            .map(|(key, _)| self.namespaced_key(*key))
+            .collect::<Vec<_>>();
+        let changes = keys
+            .iter()
+            .zip(changes)
+            .map(|(key, (_, value))| (key, *value))
+            .collect::<Vec<_>>();
+        self.storage.write_batch(&changes)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        binary,
+        common::{Bytes32, StorageMap},
+        sparse,
+    };
+    use digest::Digest;
+    use sha2::Sha256;
+
+    #[derive(Debug)]
+    struct ContractNodesTable;
+
+    impl Mappable for ContractNodesTable {
+        type Key = (u32, Bytes32);
+        type SetValue = sparse::Primitive;
+        type GetValue = Self::SetValue;
+    }
+
+    #[derive(Debug)]
+    struct BlockNodesTable;
+
+    impl Mappable for BlockNodesTable {
+        type Key = (u64, u64);
+        type SetValue = binary::Primitive;
+        type GetValue = Self::SetValue;
+    }
+
+    type ContractTree<'a> = sparse::MerkleTree<
+        Namespaced<ContractNodesTable>,
+        NamespacedStorage<ContractNodesTable, u32, &'a mut StorageMap<ContractNodesTable>>,
+    >;
+
+    type BlockTree<'a> = binary::MerkleTree<
+        Namespaced<BlockNodesTable>,
+        NamespacedStorage<BlockNodesTable, u64, &'a mut StorageMap<BlockNodesTable>>,
+    >;
+
+    fn sum<D: AsRef<[u8]>>(data: D) -> Bytes32 {
+        Sha256::digest(data.as_ref()).into()
+    }
+
+    #[test]
+    fn test_sparse_trees_in_different_namespaces_are_independent() {
+        let mut storage = StorageMap::<ContractNodesTable>::new();
+
+        let mut tree = ContractTree::new(NamespacedStorage::new(&mut storage, 0));
+        for i in 0_u32..10 {
+            tree.update(&sum(i.to_be_bytes()), b"DATA").unwrap();
+        }
+        let root_0 = tree.root();
+
+        // The same keys with different data in another namespace.
+        let mut tree = ContractTree::new(NamespacedStorage::new(&mut storage, 1));
+        for i in 0_u32..10 {
+            tree.update(&sum(i.to_be_bytes()), b"CHANGE").unwrap();
+        }
+        for i in 0_u32..5 {
+            tree.delete(&sum(i.to_be_bytes())).unwrap();
+        }
+        let root_1 = tree.root();
+
+        let tree = ContractTree::load(NamespacedStorage::new(&mut storage, 0), &root_0).unwrap();
+        assert_eq!(tree.leaves().count(), 10);
+        for i in 0_u32..10 {
+            assert_eq!(tree.get(&sum(i.to_be_bytes())).unwrap(), Some(sum(b"DATA")));
+        }
+
+        let tree = ContractTree::load(NamespacedStorage::new(&mut storage, 1), &root_1).unwrap();
+        assert_eq!(tree.leaves().count(), 5);
+
+        // A root is not found outside of its namespace.
+        assert!(ContractTree::load(NamespacedStorage::new(&mut storage, 2), &root_0).is_err());
+    }
+
+    #[test]
+    fn test_binary_trees_in_different_namespaces_are_independent() {
+        let mut storage = StorageMap::<BlockNodesTable>::new();
+
+        let mut tree = BlockTree::new(NamespacedStorage::new(&mut storage, 0));
+        for i in 0_u32..7 {
+            tree.push(&i.to_be_bytes()).unwrap();
+        }
+        let root_0 = tree.root();
+
+        let mut tree = BlockTree::new(NamespacedStorage::new(&mut storage, 1));
+        for i in 0_u32..3 {
+            tree.push(&i.to_be_bytes()).unwrap();
+        }
+        let root_1 = tree.root();
+        assert_ne!(root_0, root_1);
+
+        let tree = BlockTree::load(NamespacedStorage::new(&mut storage, 0), 7).unwrap();
+        assert_eq!(tree.root(), root_0);
+        let tree = BlockTree::load(NamespacedStorage::new(&mut storage, 1), 3).unwrap();
+        assert_eq!(tree.root(), root_1);
+    }
+}
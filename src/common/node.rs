@@ -21,6 +21,12 @@ where
 
     fn left_child(&self) -> ChildResult<Self>;
     fn right_child(&self) -> ChildResult<Self>;
+
+    /// Get the left and right children together. Nodes that load their
+    /// children from storage override this to read both in a single batch.
+    fn children(&self) -> (ChildResult<Self>, ChildResult<Self>) {
+        (self.left_child(), self.right_child())
+    }
 }
 
 #[allow(type_alias_bounds)]
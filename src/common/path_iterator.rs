@@ -159,9 +159,10 @@ where
                     let instruction = path.get_instruction(self.current_offset);
                     self.current = instruction.map(|instruction| {
                         self.current_offset += 1;
+                        let (left_child, right_child) = path_node.children();
                         match instruction {
-                            Instruction::Left => (path_node.left_child(), path_node.right_child()),
-                            Instruction::Right => (path_node.right_child(), path_node.left_child()),
+                            Instruction::Left => (left_child, right_child),
+                            Instruction::Right => (right_child, left_child),
                         }
                     });
                 }
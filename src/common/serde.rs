@@ -0,0 +1,429 @@
+//! # Serde
+//!
+//! Serialization of the byte strings, hashes and node primitives of the trees.
+//! These are type aliases of arrays and tuples, so they are serialized with
+//! the modules below, using the `#[serde(with = "...")]` field attribute:
+//!
+//! ```ignore
+//! #[derive(Serialize, Deserialize)]
+//! struct Commitment {
+//!     #[serde(with = "fuel_merkle::common::serde::bytes32")]
+//!     root: Bytes32,
+//!     #[serde(with = "fuel_merkle::common::serde::proof_set")]
+//!     proof_set: ProofSet,
+//! }
+//! ```
+//!
+//! Human-readable formats, such as JSON, receive lowercase hex strings.
+//! Compact formats receive raw bytes. Primitives are serialized as their
+//! canonical [`Encoding`].
+
+use crate::common::{Bytes32, DeserializeError, Encoding, ProofSet};
+
+use alloc::{string::String, vec::Vec};
+use core::fmt;
+use serde::{
+    de::{self, SeqAccess, Unexpected, Visitor},
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+
+/// A byte string of any length.
+pub mod bytes {
+    use super::*;
+
+    pub fn serialize<S>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        if serializer.is_human_readable() {
+            serializer.serialize_str(&hex::encode(bytes))
+        } else {
+            serializer.serialize_bytes(bytes)
+        }
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Vec<u8>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        if deserializer.is_human_readable() {
+            deserializer.deserialize_str(BytesVisitor)
+        } else {
+            deserializer.deserialize_bytes(BytesVisitor)
+        }
+    }
+}
+
+/// A 32 byte hash, such as a root or a key.
+pub mod bytes32 {
+    use super::*;
+
+    pub fn serialize<S>(bytes: &Bytes32, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        bytes::serialize(bytes, serializer)
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Bytes32, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let bytes = bytes::deserialize(deserializer)?;
+        bytes
+            .as_slice()
+            .try_into()
+            .map_err(|_| de::Error::invalid_length(bytes.len(), &"32 bytes"))
+    }
+}
+
+/// A proof set, as a sequence of [`bytes32`].
+pub mod proof_set {
+    use super::*;
+
+    pub fn serialize<S>(proof_set: &ProofSet, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_seq(proof_set.iter().copied().map(Hex32))
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<ProofSet, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let proof_set = Vec::<Hex32>::deserialize(deserializer)?;
+        Ok(proof_set.into_iter().map(|hash| hash.0).collect())
+    }
+}
+
+/// A value with a canonical [`Encoding`], such as a node primitive.
+pub mod encoded {
+    use super::*;
+
+    pub fn serialize<T, S>(value: &T, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        T: Encoding,
+        S: Serializer,
+    {
+        bytes::serialize(value.to_bytes().as_ref(), serializer)
+    }
+
+    pub fn deserialize<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+    where
+        T: Encoding,
+        D: Deserializer<'de>,
+    {
+        let bytes = bytes::deserialize(deserializer)?;
+        T::from_bytes(&bytes).map_err(|err| match err {
+            DeserializeError::InvalidLength { received, .. } => {
+                de::Error::invalid_length(received, &"the size of the encoding")
+            }
+            _ => de::Error::invalid_value(Unexpected::Bytes(&bytes), &"a canonical encoding"),
+        })
+    }
+}
+
+/// An optional value with a canonical [`Encoding`].
+pub mod option_encoded {
+    use super::*;
+
+    pub fn serialize<T, S>(value: &Option<T>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        T: Encoding + Copy,
+        S: Serializer,
+    {
+        value.map(Encoded).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
+    where
+        T: Encoding,
+        D: Deserializer<'de>,
+    {
+        let value = Option::<Encoded<T>>::deserialize(deserializer)?;
+        Ok(value.map(|value| value.0))
+    }
+}
+
+/// A sequence of values with a canonical [`Encoding`].
+pub mod encoded_seq {
+    use super::*;
+
+    pub fn serialize<T, S>(values: &[T], serializer: S) -> Result<S::Ok, S::Error>
+    where
+        T: Encoding + Copy,
+        S: Serializer,
+    {
+        serializer.collect_seq(values.iter().copied().map(Encoded))
+    }
+
+    pub fn deserialize<'de, T, D>(deserializer: D) -> Result<Vec<T>, D::Error>
+    where
+        T: Encoding,
+        D: Deserializer<'de>,
+    {
+        let values = Vec::<Encoded<T>>::deserialize(deserializer)?;
+        Ok(values.into_iter().map(|value| value.0).collect())
+    }
+}
+
+/// A hash serialized with [`bytes32`], for use in collections.
+#[derive(Serialize, Deserialize)]
+#[serde(transparent)]
+pub(crate) struct Hex32(#[serde(with = "bytes32")] pub(crate) Bytes32);
+
+/// A value serialized with [`encoded`], for use in collections.
+#[derive(Serialize, Deserialize)]
+#[serde(transparent, bound = "T: Encoding")]
+pub(crate) struct Encoded<T>(#[serde(with = "encoded")] pub(crate) T);
+
+struct BytesVisitor;
+
+impl<'de> Visitor<'de> for BytesVisitor {
+    type Value = Vec<u8>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a hex string or a byte array")
+    }
+
+    fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        hex::decode(value).map_err(|_| E::invalid_value(Unexpected::Str(value), &self))
+    }
+
+    fn visit_string<E>(self, value: String) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        self.visit_str(&value)
+    }
+
+    fn visit_bytes<E>(self, value: &[u8]) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        Ok(value.to_vec())
+    }
+
+    fn visit_byte_buf<E>(self, value: Vec<u8>) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        Ok(value)
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or_default());
+        while let Some(byte) = seq.next_element()? {
+            bytes.push(byte);
+        }
+        Ok(bytes)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        binary,
+        common::{serde::bytes32, Bytes32, Position, StorageMap},
+        sparse::{
+            self, in_memory::NodesTable, MerkleTree, MultiProof, Operation, Proof, SubtreeProof,
+            Witness,
+        },
+    };
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+    struct Commitment {
+        #[serde(with = "bytes32")]
+        root: Bytes32,
+        #[serde(with = "crate::common::serde::encoded")]
+        binary_primitive: binary::Primitive,
+        #[serde(with = "crate::common::serde::encoded")]
+        sparse_primitive: sparse::Primitive,
+    }
+
+    fn key(i: u32) -> Bytes32 {
+        let mut key = [0; 32];
+        key[..4].copy_from_slice(&i.to_be_bytes());
+        key
+    }
+
+    fn tree_with_leaves(n: u32) -> sparse::in_memory::MerkleTree {
+        let mut tree = sparse::in_memory::MerkleTree::new();
+        for i in 0..n {
+            tree.update(&key(i), &i.to_be_bytes());
+        }
+        tree
+    }
+
+    fn round_trip<T>(value: &T) -> (T, T)
+    where
+        T: Serialize + for<'de> Deserialize<'de>,
+    {
+        let json = serde_json::to_string(value).unwrap();
+        let compact = bincode::serialize(value).unwrap();
+        (
+            serde_json::from_str(&json).unwrap(),
+            bincode::deserialize(&compact).unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_human_readable_form_is_hex() {
+        let commitment = Commitment {
+            root: [0xab; 32],
+            binary_primitive: (6, [0x01; 32]),
+            sparse_primitive: (1, 0x00, [0x02; 32], [0x03; 32]),
+        };
+        let json = serde_json::to_value(commitment).unwrap();
+        assert_eq!(json["root"], "ab".repeat(32));
+        assert_eq!(
+            json["binary_primitive"],
+            format!("{}{}", "0000000000000006", "01".repeat(32))
+        );
+        assert_eq!(
+            json["sparse_primitive"],
+            format!(
+                "{}{}{}{}",
+                "00000001",
+                "00",
+                "02".repeat(32),
+                "03".repeat(32)
+            )
+        );
+
+        assert_eq!(round_trip(&commitment), (commitment, commitment));
+    }
+
+    #[test]
+    fn test_compact_form_is_raw_bytes() {
+        let commitment = Commitment {
+            root: [0xab; 32],
+            binary_primitive: (6, [0x01; 32]),
+            sparse_primitive: (1, 0x00, [0x02; 32], [0x03; 32]),
+        };
+        let bytes = bincode::serialize(&commitment).unwrap();
+        // Each field is a byte string, prefixed with its length.
+        assert_eq!(bytes.len(), (8 + 32) + (8 + 40) + (8 + 69));
+        assert_eq!(bytes[8..40], [0xab; 32]);
+    }
+
+    #[test]
+    fn test_deserialize_rejects_invalid_values() {
+        let err = serde_json::from_str::<Commitment>(&format!(
+            r#"{{"root": "{}", "binary_primitive": "{}", "sparse_primitive": "{}"}}"#,
+            "ab".repeat(31),
+            "01".repeat(40),
+            "01".repeat(69),
+        ))
+        .unwrap_err();
+        assert!(err.to_string().contains("invalid length 31"), "{}", err);
+
+        let err = serde_json::from_str::<Commitment>(&format!(
+            r#"{{"root": "{}", "binary_primitive": "{}", "sparse_primitive": "{}"}}"#,
+            "ab".repeat(32),
+            "01".repeat(40),
+            "ff".repeat(69),
+        ))
+        .unwrap_err();
+        assert!(err.to_string().contains("canonical encoding"), "{}", err);
+
+        let err = serde_json::from_str::<Commitment>(&format!(
+            r#"{{"root": "{}", "binary_primitive": "{}", "sparse_primitive": "{}"}}"#,
+            "xy".repeat(32),
+            "01".repeat(40),
+            "01".repeat(69),
+        ))
+        .unwrap_err();
+        assert!(err.to_string().contains("invalid value"), "{}", err);
+    }
+
+    #[test]
+    fn test_position_round_trips() {
+        let position = Position::from_in_order_index(11);
+        assert_eq!(serde_json::to_string(&position).unwrap(), "11");
+        assert_eq!(round_trip(&position), (position, position));
+    }
+
+    #[test]
+    fn test_sparse_proofs_round_trip() {
+        let tree = tree_with_leaves(16);
+        let root = tree.root();
+
+        let proof: Proof = tree.prove(&key(3));
+        let (from_json, from_compact) = round_trip(&proof);
+        assert_eq!(from_json, proof);
+        assert_eq!(from_compact, proof);
+        assert!(from_json.verify_inclusion(&root, &key(3), &3u32.to_be_bytes()));
+
+        let proof: Proof = tree.prove(&key(100));
+        let (from_json, from_compact) = round_trip(&proof);
+        assert_eq!(from_json, proof);
+        assert_eq!(from_compact, proof);
+        assert!(from_json.verify_exclusion(&root, &key(100)));
+
+        let claims = [
+            (key(1), Some(1u32.to_be_bytes())),
+            (key(5), Some(5u32.to_be_bytes())),
+            (key(100), None),
+        ];
+        let keys = claims.map(|(key, _)| key);
+        let proof: MultiProof = tree.prove_many(&keys);
+        let (from_json, from_compact) = round_trip(&proof);
+        assert_eq!(from_json, proof);
+        assert_eq!(from_compact, proof);
+        assert!(from_json.verify(&root, &claims));
+    }
+
+    #[test]
+    fn test_subtree_proof_round_trips() {
+        let mut tree = MerkleTree::new(StorageMap::<NodesTable>::new());
+        for i in 0..16u32 {
+            tree.update(&key(i), &i.to_be_bytes()).unwrap();
+        }
+        let root = tree.root();
+
+        let proof: SubtreeProof = tree.subtree_proof(&key(0), 28).unwrap();
+        let (from_json, from_compact) = round_trip(&proof);
+        assert_eq!(from_json, proof);
+        assert_eq!(from_compact, proof);
+        let subtree_root = tree.subtree_root(&key(0), 28).unwrap();
+        assert!(from_json.verify(&root, &subtree_root));
+    }
+
+    #[test]
+    fn test_witness_round_trips() {
+        let mut tree = MerkleTree::new(StorageMap::<NodesTable>::new());
+        for i in 0..16u32 {
+            tree.update(&key(i), &i.to_be_bytes()).unwrap();
+        }
+        let old_root = tree.root();
+        let operations = [
+            Operation::Update(key(3), b"DATA".to_vec()),
+            Operation::Delete(key(7)),
+        ];
+        tree.start_recording();
+        tree.update(&key(3), b"DATA").unwrap();
+        tree.delete(&key(7)).unwrap();
+        let witness: Witness = tree.stop_recording().unwrap();
+        let new_root = tree.root();
+
+        let (from_json, from_compact) = round_trip(&witness);
+        assert_eq!(from_json, witness);
+        assert_eq!(from_compact, witness);
+        assert!(from_json.verify(&old_root, &new_root, &operations));
+
+        let (from_json, from_compact) = round_trip(&operations[0]);
+        assert_eq!(from_json, operations[0]);
+        assert_eq!(from_compact, operations[0]);
+        let json = serde_json::to_value(&operations[0]).unwrap();
+        assert_eq!(json["Update"][1], hex::encode(b"DATA"));
+    }
+}
@@ -0,0 +1,442 @@
+use crate::storage::{
+    GetBatchResult, Mappable, StorageInspect, StorageInspectBatch, StorageMutate,
+    StorageMutateBatch,
+};
+
+use alloc::{borrow::Cow, collections::BTreeMap, vec::Vec};
+use core::{
+    cell::{Cell, RefCell},
+    hash::Hash,
+};
+use hashbrown::HashMap;
+
+/// The cached values, each tagged with the tick of its most recent use, and
+/// the keys of the cached values ordered by that tick. The key with the
+/// smallest tick is the least recently used.
+#[derive(Debug)]
+struct Entries<Key, Value> {
+    values: HashMap<Key, (Value, u64)>,
+    ticks: BTreeMap<u64, Key>,
+    next_tick: u64,
+}
+
+impl<Key, Value> Entries<Key, Value>
+where
+    Key: Eq + Hash + Clone,
+    Value: Clone,
+{
+    fn new() -> Self {
+        Self {
+            values: HashMap::new(),
+            ticks: BTreeMap::new(),
+            next_tick: 0,
+        }
+    }
+
+    fn tick(&mut self) -> u64 {
+        let tick = self.next_tick;
+        self.next_tick += 1;
+        tick
+    }
+
+    fn get(&mut self, key: &Key) -> Option<Value> {
+        let tick = self.tick();
+        let (value, last_tick) = self.values.get_mut(key)?;
+        let key = self
+            .ticks
+            .remove(last_tick)
+            .expect("Expected every cached key to have a tick");
+        *last_tick = tick;
+        self.ticks.insert(tick, key);
+        Some(value.clone())
+    }
+
+    fn insert(&mut self, key: &Key, value: Value, capacity: usize) {
+        self.remove(key);
+        if self.values.len() >= capacity {
+            if let Some((_, evicted_key)) = self.ticks.pop_first() {
+                self.values.remove(&evicted_key);
+            }
+        }
+        let tick = self.tick();
+        self.values.insert(key.clone(), (value, tick));
+        self.ticks.insert(tick, key.clone());
+    }
+
+    fn remove(&mut self, key: &Key) {
+        if let Some((_, tick)) = self.values.remove(key) {
+            self.ticks.remove(&tick);
+        }
+    }
+
+    fn contains_key(&self, key: &Key) -> bool {
+        self.values.contains_key(key)
+    }
+
+    fn len(&self) -> usize {
+        self.values.len()
+    }
+}
+
+/// # Storage Cache
+///
+/// A bounded, least recently used (LRU) cache in front of a storage backend.
+/// The cache keeps up to `capacity` of the most recently read or written
+/// values; when it is full, caching a new value evicts the least recently used
+/// value.
+///
+/// Reads are served from the cache when possible and fall through to the
+/// backend otherwise, caching the value read. Writes go through to the
+/// backend immediately and update the cache, so the backend is always
+/// consistent with the cache. Keys not found in the backend are not cached.
+///
+/// The cache counts the reads it serves (hits) and the reads that fall
+/// through to the backend (misses).
+#[derive(Debug)]
+pub struct StorageCache<Type: Mappable, StorageType> {
+    storage: StorageType,
+    capacity: usize,
+    entries: RefCell<Entries<Type::Key, Type::GetValue>>,
+    hits: Cell<u64>,
+    misses: Cell<u64>,
+}
+
+impl<Type, StorageType> StorageCache<Type, StorageType>
+where
+    Type: Mappable,
+    Type::Key: Eq + Hash + Clone,
+{
+    /// Create a cache of up to `capacity` values in front of `storage`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` is 0.
+    pub fn new(storage: StorageType, capacity: usize) -> Self {
+        assert!(capacity > 0, "cache capacity must be greater than 0");
+        Self {
+            storage,
+            capacity,
+            entries: RefCell::new(Entries::new()),
+            hits: Cell::new(0),
+            misses: Cell::new(0),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// The number of values currently cached.
+    pub fn len(&self) -> usize {
+        self.entries.borrow().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The number of reads served from the cache.
+    pub fn hits(&self) -> u64 {
+        self.hits.get()
+    }
+
+    /// The number of reads that fell through to the backend.
+    pub fn misses(&self) -> u64 {
+        self.misses.get()
+    }
+
+    pub fn reset_counters(&self) {
+        self.hits.set(0);
+        self.misses.set(0);
+    }
+
+    pub fn into_inner(self) -> StorageType {
+        self.storage
+    }
+}
+
+impl<Type, StorageType> StorageInspect<Type> for StorageCache<Type, StorageType>
+where
+    Type: Mappable,
+    Type::Key: Eq + Hash + Clone,
+    StorageType: StorageInspect<Type>,
+{
+    type Error = StorageType::Error;
+
+    fn get(&self, key: &Type::Key) -> Result<Option<Cow<'_, Type::GetValue>>, Self::Error> {
+        if let Some(value) = self.entries.borrow_mut().get(key) {
+            self.hits.set(self.hits.get() + 1);
+            return Ok(Some(Cow::Owned(value)));
+        }
+
+        self.misses.set(self.misses.get() + 1);
+        let value = self.storage.get(key)?.map(Cow::into_owned);
+        if let Some(value) = &value {
+            self.entries
+                .borrow_mut()
+                .insert(key, value.clone(), self.capacity);
+        }
+        Ok(value.map(Cow::Owned))
+    }
+
+    fn contains_key(&self, key: &Type::Key) -> Result<bool, Self::Error> {
+        if self.entries.borrow().contains_key(key) {
+            return Ok(true);
+        }
+        self.storage.contains_key(key)
+    }
+}
+
+impl<Type, StorageType> StorageInspectBatch<Type> for StorageCache<Type, StorageType>
+where
+    Type: Mappable,
+    Type::Key: Eq + Hash + Clone,
+    StorageType: StorageInspectBatch<Type>,
+{
+    /// Serve the cached keys from the cache, and read the remaining keys from
+    /// the backend in a single batch.
+    fn get_batch(&self, keys: &[Type::Key]) -> GetBatchResult<'_, Type::GetValue, Self::Error> {
+        let cached = {
+            let mut entries = self.entries.borrow_mut();
+            keys.iter().map(|key| entries.get(key)).collect::<Vec<_>>()
+        };
+        let missing = keys
+            .iter()
+            .zip(cached.iter())
+            .filter(|(_, value)| value.is_none())
+            .map(|(key, _)| key.clone())
+            .collect::<Vec<_>>();
+        self.hits
+            .set(self.hits.get() + (keys.len() - missing.len()) as u64);
+        self.misses.set(self.misses.get() + missing.len() as u64);
+
+        let fetched = self.storage.get_batch(&missing)?;
+        let mut fetched = missing.iter().zip(fetched).map(|(key, value)| {
+            let value = value.map(Cow::into_owned);
+            if let Some(value) = &value {
+                self.entries
+                    .borrow_mut()
+                    .insert(key, value.clone(), self.capacity);
+            }
+            value
+        });
+        let values = cached
+            .into_iter()
+            .map(|value| value.or_else(|| fetched.next().flatten()).map(Cow::Owned))
+            .collect();
+        Ok(values)
+    }
+}
+
+impl<Type, StorageType> StorageMutate<Type> for StorageCache<Type, StorageType>
+where
+    Type: Mappable,
+    Type::Key: Eq + Hash + Clone,
+    Type::SetValue: Clone,
+    Type::GetValue: From<Type::SetValue>,
+    StorageType: StorageMutate<Type>,
+{
+    fn insert(
+        &mut self,
+        key: &Type::Key,
+        value: &Type::SetValue,
+    ) -> Result<Option<Type::GetValue>, Self::Error> {
+        let previous = self.storage.insert(key, value)?;
+        self.entries
+            .get_mut()
+            .insert(key, value.clone().into(), self.capacity);
+        Ok(previous)
+    }
+
+    fn remove(&mut self, key: &Type::Key) -> Result<Option<Type::GetValue>, Self::Error> {
+        let previous = self.storage.remove(key)?;
+        self.entries.get_mut().remove(key);
+        Ok(previous)
+    }
+}
+
+impl<Type, StorageType> StorageMutateBatch<Type> for StorageCache<Type, StorageType>
+where
+    Type: Mappable,
+    Type::Key: Eq + Hash + Clone,
+    Type::SetValue: Clone,
+    Type::GetValue: From<Type::SetValue>,
+    StorageType: StorageMutateBatch<Type>,
+{
+    /// Write the changes to the backend in a single batch, then update the
+    /// cache.
+    fn write_batch(
+        &mut self,
+        changes: &[(&Type::Key, Option<&Type::SetValue>)],
+    ) -> Result<(), Self::Error> {
+        self.storage.write_batch(changes)?;
+        let entries = self.entries.get_mut();
+        for (key, value) in changes {
+            match value {
+                Some(value) => entries.insert(key, (*value).clone().into(), self.capacity),
+                None => entries.remove(key),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        common::{Bytes32, StorageMap},
+        sparse::{self, Primitive},
+    };
+    use digest::Digest;
+    use sha2::Sha256;
+
+    #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+    struct TestKey(u32);
+
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+    struct TestValue(u32);
+
+    struct TestTable;
+
+    impl Mappable for TestTable {
+        type Key = TestKey;
+        type SetValue = TestValue;
+        type GetValue = Self::SetValue;
+    }
+
+    #[test]
+    fn test_get_counts_hits_and_misses() {
+        let mut storage = StorageMap::<TestTable>::new();
+        let _ = storage.insert(&TestKey(0), &TestValue(0));
+        let cache = StorageCache::new(storage, 2);
+
+        assert_eq!(
+            cache.get(&TestKey(0)).unwrap().unwrap().into_owned(),
+            TestValue(0)
+        );
+        assert_eq!(
+            cache.get(&TestKey(0)).unwrap().unwrap().into_owned(),
+            TestValue(0)
+        );
+        assert_eq!(cache.get(&TestKey(1)).unwrap(), None);
+        assert_eq!(cache.hits(), 1);
+        assert_eq!(cache.misses(), 2);
+
+        cache.reset_counters();
+        assert_eq!(cache.hits(), 0);
+        assert_eq!(cache.misses(), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "cache capacity must be greater than 0")]
+    fn test_new_with_a_capacity_of_0_panics() {
+        let _ = StorageCache::<TestTable, _>::new(StorageMap::<TestTable>::new(), 0);
+    }
+
+    #[test]
+    fn test_insert_writes_through_to_the_backend() {
+        let mut cache = StorageCache::new(StorageMap::<TestTable>::new(), 2);
+        let _ = cache.insert(&TestKey(0), &TestValue(0));
+
+        assert_eq!(
+            cache.get(&TestKey(0)).unwrap().unwrap().into_owned(),
+            TestValue(0)
+        );
+        assert_eq!(cache.hits(), 1);
+
+        let storage = cache.into_inner();
+        assert_eq!(
+            storage.get(&TestKey(0)).unwrap(),
+            Some(Cow::Borrowed(&TestValue(0)))
+        );
+    }
+
+    #[test]
+    fn test_remove_removes_the_value_from_the_cache_and_the_backend() {
+        let mut cache = StorageCache::new(StorageMap::<TestTable>::new(), 2);
+        let _ = cache.insert(&TestKey(0), &TestValue(0));
+        let previous = cache.remove(&TestKey(0)).unwrap();
+
+        assert_eq!(previous, Some(TestValue(0)));
+        assert_eq!(cache.get(&TestKey(0)).unwrap(), None);
+        assert!(cache.is_empty());
+        assert_eq!(cache.into_inner().get(&TestKey(0)).unwrap(), None);
+    }
+
+    #[test]
+    fn test_the_least_recently_used_value_is_evicted() {
+        let mut cache = StorageCache::new(StorageMap::<TestTable>::new(), 2);
+        let _ = cache.insert(&TestKey(0), &TestValue(0));
+        let _ = cache.insert(&TestKey(1), &TestValue(1));
+
+        // Reading key 0 makes key 1 the least recently used.
+        let _ = cache.get(&TestKey(0));
+        let _ = cache.insert(&TestKey(2), &TestValue(2));
+        assert_eq!(cache.len(), 2);
+        cache.reset_counters();
+
+        let _ = cache.get(&TestKey(0));
+        let _ = cache.get(&TestKey(2));
+        assert_eq!(cache.hits(), 2);
+        assert_eq!(
+            cache.get(&TestKey(1)).unwrap().unwrap().into_owned(),
+            TestValue(1)
+        );
+        assert_eq!(cache.misses(), 1);
+    }
+
+    #[test]
+    fn test_get_batch_serves_cached_keys_and_caches_the_rest() {
+        let mut storage = StorageMap::<TestTable>::new();
+        let _ = storage.insert(&TestKey(0), &TestValue(0));
+        let _ = storage.insert(&TestKey(1), &TestValue(1));
+        let cache = StorageCache::new(storage, 4);
+        let _ = cache.get(&TestKey(0));
+        cache.reset_counters();
+
+        let values = cache
+            .get_batch(&[TestKey(0), TestKey(1), TestKey(2)])
+            .unwrap()
+            .into_iter()
+            .map(|value| value.map(Cow::into_owned))
+            .collect::<Vec<_>>();
+        assert_eq!(values, vec![Some(TestValue(0)), Some(TestValue(1)), None]);
+        assert_eq!(cache.hits(), 1);
+        assert_eq!(cache.misses(), 2);
+        assert_eq!(cache.len(), 2);
+    }
+
+    #[derive(Debug)]
+    struct NodesTable;
+
+    impl Mappable for NodesTable {
+        type Key = Bytes32;
+        type SetValue = Primitive;
+        type GetValue = Self::SetValue;
+    }
+
+    fn sum<D: AsRef<[u8]>>(data: D) -> Bytes32 {
+        Sha256::digest(data.as_ref()).into()
+    }
+
+    #[test]
+    fn test_cache_serves_the_upper_nodes_of_a_sparse_tree() {
+        let mut cache = StorageCache::new(StorageMap::<NodesTable>::new(), 64);
+        let mut tree = sparse::MerkleTree::new(&mut cache);
+        for i in 0_u32..100 {
+            tree.update(&sum(i.to_be_bytes()), b"DATA").unwrap();
+        }
+        let root = tree.root();
+        assert!(cache.hits() > cache.misses());
+
+        let mut expected_storage = StorageMap::<NodesTable>::new();
+        let mut expected_tree = sparse::MerkleTree::new(&mut expected_storage);
+        for i in 0_u32..100 {
+            expected_tree
+                .update(&sum(i.to_be_bytes()), b"DATA")
+                .unwrap();
+        }
+        assert_eq!(root, expected_tree.root());
+    }
+}
@@ -1,4 +1,6 @@
-use crate::storage::{Mappable, StorageInspect, StorageMutate};
+use crate::storage::{
+    Mappable, StorageInspect, StorageInspectBatch, StorageMutate, StorageMutateBatch,
+};
 
 use alloc::borrow::Cow;
 use hashbrown::HashMap;
@@ -42,6 +44,14 @@ where
     }
 }
 
+impl<Type> StorageInspectBatch<Type> for StorageMap<Type>
+where
+    Type: Mappable,
+    Type::Key: Eq + core::hash::Hash + Clone,
+    Type::GetValue: Clone,
+{
+}
+
 impl<Type> StorageMutate<Type> for StorageMap<Type>
 where
     Type: Mappable,
@@ -66,6 +76,15 @@ where
     }
 }
 
+impl<Type> StorageMutateBatch<Type> for StorageMap<Type>
+where
+    Type: Mappable,
+    Type::Key: Eq + core::hash::Hash + Clone,
+    Type::SetValue: Clone,
+    Type::GetValue: Clone + From<Type::SetValue>,
+{
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
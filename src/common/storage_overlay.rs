@@ -0,0 +1,552 @@
+use crate::storage::{
+    GetBatchResult, Mappable, StorageInspect, StorageInspectBatch, StorageMutate,
+    StorageMutateBatch,
+};
+
+use alloc::{borrow::Cow, vec::Vec};
+use core::{borrow::Borrow, hash::Hash};
+use hashbrown::HashMap;
+
+/// The changes buffered for a table: the keys written, mapped to their new
+/// values, or to `None` for removed keys.
+#[derive(Debug, Clone)]
+pub(crate) struct ChangeSet<Key, Value> {
+    changes: HashMap<Key, Option<Value>>,
+}
+
+impl<Key, Value> Default for ChangeSet<Key, Value> {
+    fn default() -> Self {
+        Self {
+            changes: HashMap::new(),
+        }
+    }
+}
+
+impl<Key, Value> ChangeSet<Key, Value>
+where
+    Key: Eq + Hash,
+{
+    pub(crate) fn len(&self) -> usize {
+        self.changes.len()
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    /// The buffered change to the given key: `Some(None)` if the key was
+    /// removed, or `None` if the key was not written.
+    pub(crate) fn get(&self, key: &Key) -> Option<&Option<Value>> {
+        self.changes.get(key)
+    }
+
+    pub(crate) fn insert(&mut self, key: Key, value: Option<Value>) {
+        self.changes.insert(key, value);
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (&Key, &Option<Value>)> {
+        self.changes.iter()
+    }
+
+    /// Move the changes of `other` into this change set. The changes of
+    /// `other` replace the changes to the same keys.
+    pub(crate) fn merge(&mut self, other: Self) {
+        self.changes.extend(other.changes);
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.changes.clear();
+    }
+
+    /// Write the changes to the given storage, removing each change from the
+    /// change set once it is written. If the storage returns an error, the
+    /// changes not yet written remain in the change set.
+    pub(crate) fn write_to<Type, StorageType>(
+        &mut self,
+        storage: &mut StorageType,
+    ) -> Result<(), StorageType::Error>
+    where
+        Type: Mappable<Key = Key, GetValue = Value>,
+        Value: Borrow<Type::SetValue>,
+        StorageType: StorageMutate<Type>,
+    {
+        let mut changes = self.changes.drain().collect::<Vec<_>>().into_iter();
+        while let Some((key, value)) = changes.next() {
+            let result = match &value {
+                Some(value) => storage.insert(&key, value.borrow()),
+                None => storage.remove(&key),
+            };
+            if let Err(err) = result {
+                self.changes.insert(key, value);
+                self.changes.extend(changes);
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// # Storage Overlay
+///
+/// A write buffer in front of a storage backend. Inserts and removes are
+/// buffered in the overlay instead of being written to the backend, and reads
+/// are answered from the buffer first, falling through to the backend for
+/// keys the overlay has not written. The buffered changes are written to the
+/// backend by [`commit`](Self::commit), or thrown away by
+/// [`discard`](Self::discard).
+///
+/// Wrapping the storage of a tree in an overlay keeps a failed operation on
+/// the tree out of the backend: if the operation fails partway, discarding
+/// the overlay leaves the backend untouched. A commit writes the changes in a
+/// single [`StorageMutateBatch::write_batch`], so it is atomic if the
+/// backend's batch writes are.
+///
+/// As required by [`StorageMutate`], inserts and removes return the previous
+/// value of the key, which reads the backend for a key the overlay has not
+/// written. [`buffer_insert`](Self::buffer_insert) and
+/// [`buffer_remove`](Self::buffer_remove) buffer a change without the read.
+///
+/// An overlay is itself a storage, so overlays can be stacked. Committing an
+/// overlay whose backend is another overlay moves its changes into the
+/// buffer of the overlay beneath it.
+#[derive(Debug)]
+pub struct StorageOverlay<Type: Mappable, StorageType> {
+    storage: StorageType,
+    changes: ChangeSet<Type::Key, Type::GetValue>,
+}
+
+impl<Type, StorageType> StorageOverlay<Type, StorageType>
+where
+    Type: Mappable,
+    Type::Key: Eq + Hash + Clone,
+{
+    pub fn new(storage: StorageType) -> Self {
+        Self {
+            storage,
+            changes: ChangeSet::default(),
+        }
+    }
+
+    /// The number of keys inserted or removed since the overlay was created,
+    /// committed, or discarded.
+    pub fn len(&self) -> usize {
+        self.changes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    /// Write the buffered changes to the backend in a single batch. If the
+    /// backend returns an error, the changes remain buffered and the commit
+    /// can be retried. Whether any of the changes reached the backend depends
+    /// on its [`StorageMutateBatch`] implementation.
+    pub fn commit(&mut self) -> Result<(), StorageType::Error>
+    where
+        StorageType: StorageMutateBatch<Type>,
+        Type::GetValue: Borrow<Type::SetValue>,
+    {
+        let changes = self
+            .changes
+            .iter()
+            .map(|(key, value)| (key, value.as_ref().map(Borrow::borrow)))
+            .collect::<Vec<_>>();
+        self.storage.write_batch(&changes)?;
+        self.changes.clear();
+        Ok(())
+    }
+
+    /// Buffer an insert of the given key, without reading its previous value.
+    pub fn buffer_insert(&mut self, key: &Type::Key, value: &Type::SetValue)
+    where
+        Type::SetValue: Clone,
+        Type::GetValue: From<Type::SetValue>,
+    {
+        self.changes.insert(key.clone(), Some(value.clone().into()));
+    }
+
+    /// Buffer a removal of the given key, without reading its previous value.
+    pub fn buffer_remove(&mut self, key: &Type::Key) {
+        self.changes.insert(key.clone(), None);
+    }
+
+    /// Throw away the buffered changes.
+    pub fn discard(&mut self) {
+        self.changes.clear();
+    }
+
+    /// Return the backend, throwing away the buffered changes.
+    pub fn into_inner(self) -> StorageType {
+        self.storage
+    }
+}
+
+impl<Type, StorageType> StorageInspect<Type> for StorageOverlay<Type, StorageType>
+where
+    Type: Mappable,
+    Type::Key: Eq + Hash + Clone,
+    StorageType: StorageInspect<Type>,
+{
+    type Error = StorageType::Error;
+
+    fn get(&self, key: &Type::Key) -> Result<Option<Cow<'_, Type::GetValue>>, Self::Error> {
+        match self.changes.get(key) {
+            Some(value) => Ok(value.as_ref().map(Cow::Borrowed)),
+            None => self.storage.get(key),
+        }
+    }
+
+    fn contains_key(&self, key: &Type::Key) -> Result<bool, Self::Error> {
+        match self.changes.get(key) {
+            Some(value) => Ok(value.is_some()),
+            None => self.storage.contains_key(key),
+        }
+    }
+}
+
+impl<Type, StorageType> StorageInspectBatch<Type> for StorageOverlay<Type, StorageType>
+where
+    Type: Mappable,
+    Type::Key: Eq + Hash + Clone,
+    StorageType: StorageInspectBatch<Type>,
+{
+    /// Answer the buffered keys from the buffer, and read the remaining keys
+    /// from the backend in a single batch.
+    fn get_batch(&self, keys: &[Type::Key]) -> GetBatchResult<'_, Type::GetValue, Self::Error> {
+        let buffered = keys
+            .iter()
+            .map(|key| self.changes.get(key))
+            .collect::<Vec<_>>();
+        let missing = keys
+            .iter()
+            .zip(buffered.iter())
+            .filter(|(_, value)| value.is_none())
+            .map(|(key, _)| key.clone())
+            .collect::<Vec<_>>();
+
+        let mut fetched = self.storage.get_batch(&missing)?.into_iter();
+        let values = buffered
+            .into_iter()
+            .map(|value| match value {
+                Some(value) => value.as_ref().map(Cow::Borrowed),
+                None => fetched.next().flatten(),
+            })
+            .collect();
+        Ok(values)
+    }
+}
+
+impl<Type, StorageType> StorageMutate<Type> for StorageOverlay<Type, StorageType>
+where
+    Type: Mappable,
+    Type::Key: Eq + Hash + Clone,
+    Type::SetValue: Clone,
+    Type::GetValue: From<Type::SetValue>,
+    StorageType: StorageInspect<Type>,
+{
+    fn insert(
+        &mut self,
+        key: &Type::Key,
+        value: &Type::SetValue,
+    ) -> Result<Option<Type::GetValue>, Self::Error> {
+        let previous = self.previous(key)?;
+        self.buffer_insert(key, value);
+        Ok(previous)
+    }
+
+    fn remove(&mut self, key: &Type::Key) -> Result<Option<Type::GetValue>, Self::Error> {
+        let previous = self.previous(key)?;
+        self.buffer_remove(key);
+        Ok(previous)
+    }
+}
+
+impl<Type, StorageType> StorageMutateBatch<Type> for StorageOverlay<Type, StorageType>
+where
+    Type: Mappable,
+    Type::Key: Eq + Hash + Clone,
+    Type::SetValue: Clone,
+    Type::GetValue: From<Type::SetValue>,
+    StorageType: StorageInspect<Type>,
+{
+    /// Buffer the changes, without reading the previous values.
+    fn write_batch(
+        &mut self,
+        changes: &[(&Type::Key, Option<&Type::SetValue>)],
+    ) -> Result<(), Self::Error> {
+        for (key, value) in changes {
+            match value {
+                Some(value) => self.buffer_insert(key, value),
+                None => self.buffer_remove(key),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<Type, StorageType> StorageOverlay<Type, StorageType>
+where
+    Type: Mappable,
+    Type::Key: Eq + Hash + Clone,
+    StorageType: StorageInspect<Type>,
+{
+    /// The value of the given key before it is written: the buffered value
+    /// if the overlay has written the key, and otherwise the value in the
+    /// backend.
+    fn previous(&self, key: &Type::Key) -> Result<Option<Type::GetValue>, StorageType::Error> {
+        match self.changes.get(key) {
+            Some(value) => Ok(value.clone()),
+            None => Ok(self.storage.get(key)?.map(Cow::into_owned)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        binary,
+        common::{
+            faulty_storage::{FaultError, FaultyStorage},
+            Bytes32, InstrumentedStorage, StorageMap,
+        },
+        sparse,
+    };
+    use digest::Digest;
+    use sha2::Sha256;
+
+    #[derive(Debug)]
+    struct TestTable;
+
+    impl Mappable for TestTable {
+        type Key = u32;
+        type SetValue = u32;
+        type GetValue = Self::SetValue;
+    }
+
+    #[test]
+    fn test_reads_are_answered_from_the_buffer_first() {
+        let mut storage = StorageMap::<TestTable>::new();
+        let _ = storage.insert(&0, &0);
+        let _ = storage.insert(&1, &1);
+
+        let mut overlay = StorageOverlay::new(&mut storage);
+        assert_eq!(overlay.insert(&0, &10).unwrap(), Some(0));
+        assert_eq!(overlay.remove(&1).unwrap(), Some(1));
+        assert_eq!(overlay.insert(&2, &12).unwrap(), None);
+
+        assert_eq!(overlay.get(&0).unwrap().unwrap().into_owned(), 10);
+        assert_eq!(overlay.get(&1).unwrap(), None);
+        assert!(!overlay.contains_key(&1).unwrap());
+        assert_eq!(overlay.get(&2).unwrap().unwrap().into_owned(), 12);
+        assert_eq!(overlay.len(), 3);
+
+        // Nothing is written to the backend before the overlay is committed.
+        drop(overlay);
+        assert_eq!(storage.get(&0).unwrap().unwrap().into_owned(), 0);
+        assert_eq!(storage.get(&1).unwrap().unwrap().into_owned(), 1);
+        assert_eq!(storage.get(&2).unwrap(), None);
+    }
+
+    #[test]
+    fn test_commit_writes_the_buffered_changes_to_the_backend() {
+        let mut storage = StorageMap::<TestTable>::new();
+        let _ = storage.insert(&1, &1);
+
+        let mut overlay = StorageOverlay::new(&mut storage);
+        let _ = overlay.insert(&0, &10);
+        let _ = overlay.remove(&1);
+        overlay.commit().unwrap();
+        assert!(overlay.is_empty());
+
+        assert_eq!(storage.get(&0).unwrap().unwrap().into_owned(), 10);
+        assert_eq!(storage.get(&1).unwrap(), None);
+    }
+
+    #[test]
+    fn test_failed_commit_keeps_the_changes_buffered() {
+        let mut storage = FaultyStorage::new(StorageMap::<TestTable>::new());
+        let faults = storage.faults();
+        let mut overlay = StorageOverlay::new(&mut storage);
+        for i in 0..10 {
+            let _ = overlay.insert(&i, &i);
+        }
+
+        // The storage writes a batch one key at a time, so a failure partway
+        // leaves some of the changes in the backend.
+        faults.fail_writes_after(4);
+        assert_eq!(overlay.commit(), Err(FaultError::WriteFailed));
+        assert_eq!(overlay.len(), 10);
+        for i in 0..10 {
+            assert_eq!(overlay.get(&i).unwrap().unwrap().into_owned(), i);
+        }
+
+        faults.clear();
+        overlay.commit().unwrap();
+        assert!(overlay.is_empty());
+        for i in 0..10 {
+            assert_eq!(storage.get(&i).unwrap().unwrap().into_owned(), i);
+        }
+    }
+
+    #[derive(Default)]
+    struct BatchCountingStorage {
+        storage: StorageMap<TestTable>,
+        batches: usize,
+    }
+
+    impl StorageInspect<TestTable> for BatchCountingStorage {
+        type Error = core::convert::Infallible;
+
+        fn get(&self, key: &u32) -> Result<Option<Cow<'_, u32>>, Self::Error> {
+            self.storage.get(key)
+        }
+
+        fn contains_key(&self, key: &u32) -> Result<bool, Self::Error> {
+            self.storage.contains_key(key)
+        }
+    }
+
+    impl StorageMutate<TestTable> for BatchCountingStorage {
+        fn insert(&mut self, key: &u32, value: &u32) -> Result<Option<u32>, Self::Error> {
+            self.storage.insert(key, value)
+        }
+
+        fn remove(&mut self, key: &u32) -> Result<Option<u32>, Self::Error> {
+            self.storage.remove(key)
+        }
+    }
+
+    impl StorageMutateBatch<TestTable> for BatchCountingStorage {
+        fn write_batch(&mut self, changes: &[(&u32, Option<&u32>)]) -> Result<(), Self::Error> {
+            self.batches += 1;
+            self.storage.write_batch(changes)
+        }
+    }
+
+    #[test]
+    fn test_commit_writes_the_changes_in_a_single_batch() {
+        let mut storage = BatchCountingStorage::default();
+        let _ = storage.insert(&1, &1);
+
+        let mut overlay = StorageOverlay::new(&mut storage);
+        for i in 0..10 {
+            let _ = overlay.insert(&i, &(i + 10));
+        }
+        let _ = overlay.remove(&1);
+        overlay.commit().unwrap();
+
+        assert_eq!(storage.batches, 1);
+        assert_eq!(storage.get(&0).unwrap().unwrap().into_owned(), 10);
+        assert_eq!(storage.get(&1).unwrap(), None);
+    }
+
+    #[test]
+    fn test_writes_read_the_backend_only_for_keys_not_buffered() {
+        let storage = InstrumentedStorage::new(StorageMap::<TestTable>::new());
+        let mut overlay = StorageOverlay::new(&storage);
+        let _ = overlay.insert(&0, &10);
+        let _ = overlay.insert(&0, &11);
+        let _ = overlay.remove(&0);
+        assert_eq!(storage.metrics().gets, 1);
+
+        overlay.buffer_insert(&1, &11);
+        overlay.buffer_remove(&2);
+        assert_eq!(storage.metrics().gets, 1);
+        assert_eq!(overlay.len(), 3);
+    }
+
+    #[test]
+    fn test_discard_throws_away_the_buffered_changes() {
+        let mut storage = StorageMap::<TestTable>::new();
+        let mut overlay = StorageOverlay::new(&mut storage);
+        let _ = overlay.insert(&0, &10);
+        overlay.discard();
+        assert_eq!(overlay.get(&0).unwrap(), None);
+        overlay.commit().unwrap();
+
+        assert_eq!(storage.get(&0).unwrap(), None);
+    }
+
+    #[test]
+    fn test_committing_a_stacked_overlay_writes_to_the_overlay_beneath_it() {
+        let mut storage = StorageMap::<TestTable>::new();
+        let mut overlay = StorageOverlay::new(&mut storage);
+        let _ = overlay.insert(&0, &10);
+
+        let mut speculative = StorageOverlay::new(&mut overlay);
+        let _ = speculative.insert(&1, &11);
+        speculative.commit().unwrap();
+
+        let mut speculative = StorageOverlay::new(&mut overlay);
+        let _ = speculative.insert(&2, &12);
+        speculative.discard();
+        drop(speculative);
+
+        assert_eq!(overlay.len(), 2);
+        overlay.commit().unwrap();
+        assert_eq!(storage.get(&1).unwrap().unwrap().into_owned(), 11);
+        assert_eq!(storage.get(&2).unwrap(), None);
+    }
+
+    #[derive(Debug)]
+    struct SparseNodesTable;
+
+    impl Mappable for SparseNodesTable {
+        type Key = Bytes32;
+        type SetValue = sparse::Primitive;
+        type GetValue = Self::SetValue;
+    }
+
+    #[derive(Debug)]
+    struct BinaryNodesTable;
+
+    impl Mappable for BinaryNodesTable {
+        type Key = u64;
+        type SetValue = binary::Primitive;
+        type GetValue = Self::SetValue;
+    }
+
+    fn sum<D: AsRef<[u8]>>(data: D) -> Bytes32 {
+        Sha256::digest(data.as_ref()).into()
+    }
+
+    #[test]
+    fn test_sparse_tree_updates_are_written_on_commit() {
+        let mut storage = StorageMap::<SparseNodesTable>::new();
+        let mut overlay = StorageOverlay::new(&mut storage);
+        let mut tree = sparse::MerkleTree::new(&mut overlay);
+        for i in 0_u32..10 {
+            tree.update(&sum(i.to_be_bytes()), b"DATA").unwrap();
+        }
+        let root = tree.root();
+
+        assert!(sparse::MerkleTree::load(&mut *overlay.storage, &root).is_err());
+        overlay.commit().unwrap();
+
+        let tree = sparse::MerkleTree::load(&mut storage, &root).unwrap();
+        assert_eq!(tree.leaves().count(), 10);
+    }
+
+    #[test]
+    fn test_discarded_binary_tree_pushes_leave_the_backend_unchanged() {
+        let mut storage = StorageMap::<BinaryNodesTable>::new();
+        let mut tree = binary::MerkleTree::new(&mut storage);
+        for i in 0_u32..5 {
+            tree.push(&i.to_be_bytes()).unwrap();
+        }
+        let root = tree.root();
+
+        let mut overlay = StorageOverlay::new(&mut storage);
+        let mut tree = binary::MerkleTree::load(&mut overlay, 5).unwrap();
+        for i in 5_u32..10 {
+            tree.push(&i.to_be_bytes()).unwrap();
+        }
+        assert_ne!(tree.root(), root);
+        overlay.discard();
+
+        let tree = binary::MerkleTree::load(&mut storage, 5).unwrap();
+        assert_eq!(tree.root(), root);
+        assert!(binary::MerkleTree::load(&mut storage, 10).is_err());
+    }
+}
@@ -0,0 +1,471 @@
+use crate::{
+    common::Bytes32,
+    storage::{
+        GetBatchResult, Mappable, StorageInspect, StorageInspectBatch, StorageMutate,
+        StorageMutateBatch,
+    },
+    sum,
+};
+
+use alloc::{borrow::Cow, vec::Vec};
+use core::fmt;
+
+/// A value that can be checked against the key it is stored under.
+pub trait VerifyKey<Key> {
+    /// Returns true if the value is consistent with being stored under the
+    /// given key.
+    fn verify_key(&self, key: &Key) -> bool;
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "std", derive(thiserror::Error))]
+pub enum IntegrityError<Key, StorageError> {
+    #[cfg_attr(
+        feature = "std",
+        error("the value stored under key {0:?} does not match the key")
+    )]
+    CorruptValue(Key),
+
+    #[cfg_attr(feature = "std", error(transparent))]
+    StorageError(StorageError),
+}
+
+impl<Key, StorageError> From<StorageError> for IntegrityError<Key, StorageError> {
+    fn from(err: StorageError) -> Self {
+        IntegrityError::StorageError(err)
+    }
+}
+
+/// # Verifying Storage
+///
+/// A storage wrapper that checks every value it reads against the key it was
+/// read from, and returns [`IntegrityError::CorruptValue`] for a value that
+/// does not match its key. Corruption in the underlying storage is then
+/// reported as an error, instead of silently producing a wrong root.
+///
+/// The check is defined by the [`VerifyKey`] implementation of the value:
+///
+/// - A sparse [`Primitive`](crate::sparse::Primitive) must hash to its key,
+///   or, for a leaf, have its key as the leaf key, and have a height
+///   consistent with its prefix.
+/// - A binary [`Primitive`](crate::binary::Primitive) must have its key as
+///   its in-order index.
+///
+/// Sum tree nodes do not implement [`VerifyKey`]: a node stores its hash
+/// rather than the data it is computed from, so a node cannot be checked by
+/// itself. See [`SumVerifyingStorage`].
+#[derive(Debug)]
+pub struct VerifyingStorage<Type, StorageType> {
+    storage: StorageType,
+    phantom_table: core::marker::PhantomData<Type>,
+}
+
+impl<Type, StorageType> VerifyingStorage<Type, StorageType> {
+    pub fn new(storage: StorageType) -> Self {
+        Self {
+            storage,
+            phantom_table: Default::default(),
+        }
+    }
+
+    pub fn into_inner(self) -> StorageType {
+        self.storage
+    }
+}
+
+fn verify<'a, Key, Value>(
+    key: &Key,
+    value: Option<Cow<'a, Value>>,
+) -> Result<Option<Cow<'a, Value>>, Key>
+where
+    Key: Clone,
+    Value: VerifyKey<Key> + Clone,
+{
+    match value {
+        Some(value) if !value.verify_key(key) => Err(key.clone()),
+        value => Ok(value),
+    }
+}
+
+impl<Type, StorageType> StorageInspect<Type> for VerifyingStorage<Type, StorageType>
+where
+    Type: Mappable,
+    Type::Key: Clone + fmt::Debug,
+    Type::GetValue: VerifyKey<Type::Key>,
+    StorageType: StorageInspect<Type>,
+{
+    type Error = IntegrityError<Type::Key, StorageType::Error>;
+
+    fn get(&self, key: &Type::Key) -> Result<Option<Cow<'_, Type::GetValue>>, Self::Error> {
+        let value = self.storage.get(key)?;
+        verify(key, value).map_err(IntegrityError::CorruptValue)
+    }
+
+    fn contains_key(&self, key: &Type::Key) -> Result<bool, Self::Error> {
+        Ok(self.storage.contains_key(key)?)
+    }
+}
+
+impl<Type, StorageType> StorageInspectBatch<Type> for VerifyingStorage<Type, StorageType>
+where
+    Type: Mappable,
+    Type::Key: Clone + fmt::Debug,
+    Type::GetValue: VerifyKey<Type::Key>,
+    StorageType: StorageInspectBatch<Type>,
+{
+    fn get_batch(&self, keys: &[Type::Key]) -> GetBatchResult<'_, Type::GetValue, Self::Error> {
+        let values = self.storage.get_batch(keys)?;
+        keys.iter()
+            .zip(values)
+            .map(|(key, value)| verify(key, value).map_err(IntegrityError::CorruptValue))
+            .collect::<Result<Vec<_>, _>>()
+    }
+}
+
+impl<Type, StorageType> StorageMutate<Type> for VerifyingStorage<Type, StorageType>
+where
+    Type: Mappable,
+    Type::Key: Clone + fmt::Debug,
+    Type::GetValue: VerifyKey<Type::Key>,
+    StorageType: StorageMutate<Type>,
+{
+    fn insert(
+        &mut self,
+        key: &Type::Key,
+        value: &Type::SetValue,
+    ) -> Result<Option<Type::GetValue>, Self::Error> {
+        Ok(self.storage.insert(key, value)?)
+    }
+
+    fn remove(&mut self, key: &Type::Key) -> Result<Option<Type::GetValue>, Self::Error> {
+        Ok(self.storage.remove(key)?)
+    }
+}
+
+impl<Type, StorageType> StorageMutateBatch<Type> for VerifyingStorage<Type, StorageType>
+where
+    Type: Mappable,
+    Type::Key: Clone + fmt::Debug,
+    Type::GetValue: VerifyKey<Type::Key>,
+    StorageType: StorageMutateBatch<Type>,
+{
+    fn write_batch(
+        &mut self,
+        changes: &[(&Type::Key, Option<&Type::SetValue>)],
+    ) -> Result<(), Self::Error> {
+        Ok(self.storage.write_batch(changes)?)
+    }
+}
+
+/// # Sum Verifying Storage
+///
+/// A storage wrapper for the nodes of a Merkle sum tree that checks every
+/// node it reads against the key it was read from, and returns
+/// [`IntegrityError::CorruptValue`] for a node that does not match its key.
+///
+/// A sum tree node stores its hash rather than the data it is computed from,
+/// so an internal node is checked against its children instead: reading an
+/// internal node also reads both of its children, and the node must have the
+/// hash of its children's fees and hashes as its key, and the sum of their
+/// fees as its fee. A node with a child missing from storage cannot be
+/// checked, and is reported as corrupt. The data of a leaf is not stored, so
+/// a leaf is only checked to have its stored hash as its key.
+#[derive(Debug)]
+pub struct SumVerifyingStorage<Type, StorageType> {
+    storage: StorageType,
+    phantom_table: core::marker::PhantomData<Type>,
+}
+
+impl<Type, StorageType> SumVerifyingStorage<Type, StorageType> {
+    pub fn new(storage: StorageType) -> Self {
+        Self {
+            storage,
+            phantom_table: Default::default(),
+        }
+    }
+
+    pub fn into_inner(self) -> StorageType {
+        self.storage
+    }
+}
+
+impl<Type, StorageType> SumVerifyingStorage<Type, StorageType>
+where
+    Type: Mappable<Key = Bytes32, SetValue = sum::Node, GetValue = sum::Node>,
+    StorageType: StorageInspect<Type>,
+{
+    fn verify_node(&self, key: &Bytes32, node: &sum::Node) -> Result<bool, StorageType::Error> {
+        if node.hash() != key {
+            return Ok(false);
+        }
+        let (left_child_key, right_child_key) =
+            match (node.left_child_key(), node.right_child_key()) {
+                (None, None) => return Ok(node.is_leaf()),
+                (Some(left_child_key), Some(right_child_key)) if node.is_node() => {
+                    (left_child_key, right_child_key)
+                }
+                _ => return Ok(false),
+            };
+        let left_child = self.storage.get(&left_child_key)?;
+        let right_child = self.storage.get(&right_child_key)?;
+        let (left_child, right_child) = match (left_child, right_child) {
+            (Some(left_child), Some(right_child)) => (left_child, right_child),
+            _ => return Ok(false),
+        };
+        let hash = sum::node_sum(
+            left_child.fee(),
+            left_child.hash(),
+            right_child.fee(),
+            right_child.hash(),
+        );
+        let fee = left_child.fee().checked_add(right_child.fee());
+        Ok(hash == *key && fee == Some(node.fee()))
+    }
+}
+
+impl<Type, StorageType> StorageInspect<Type> for SumVerifyingStorage<Type, StorageType>
+where
+    Type: Mappable<Key = Bytes32, SetValue = sum::Node, GetValue = sum::Node>,
+    StorageType: StorageInspect<Type>,
+{
+    type Error = IntegrityError<Bytes32, StorageType::Error>;
+
+    fn get(&self, key: &Bytes32) -> Result<Option<Cow<'_, sum::Node>>, Self::Error> {
+        let node = self.storage.get(key)?;
+        if let Some(node) = &node {
+            if !self.verify_node(key, node)? {
+                return Err(IntegrityError::CorruptValue(*key));
+            }
+        }
+        Ok(node)
+    }
+
+    fn contains_key(&self, key: &Bytes32) -> Result<bool, Self::Error> {
+        Ok(self.storage.contains_key(key)?)
+    }
+}
+
+impl<Type, StorageType> StorageInspectBatch<Type> for SumVerifyingStorage<Type, StorageType>
+where
+    Type: Mappable<Key = Bytes32, SetValue = sum::Node, GetValue = sum::Node>,
+    StorageType: StorageInspect<Type>,
+{
+}
+
+impl<Type, StorageType> StorageMutate<Type> for SumVerifyingStorage<Type, StorageType>
+where
+    Type: Mappable<Key = Bytes32, SetValue = sum::Node, GetValue = sum::Node>,
+    StorageType: StorageMutate<Type>,
+{
+    fn insert(
+        &mut self,
+        key: &Bytes32,
+        value: &sum::Node,
+    ) -> Result<Option<sum::Node>, Self::Error> {
+        Ok(self.storage.insert(key, value)?)
+    }
+
+    fn remove(&mut self, key: &Bytes32) -> Result<Option<sum::Node>, Self::Error> {
+        Ok(self.storage.remove(key)?)
+    }
+}
+
+impl<Type, StorageType> StorageMutateBatch<Type> for SumVerifyingStorage<Type, StorageType>
+where
+    Type: Mappable<Key = Bytes32, SetValue = sum::Node, GetValue = sum::Node>,
+    StorageType: StorageMutateBatch<Type>,
+{
+    fn write_batch(
+        &mut self,
+        changes: &[(&Bytes32, Option<&sum::Node>)],
+    ) -> Result<(), Self::Error> {
+        Ok(self.storage.write_batch(changes)?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        binary,
+        common::{
+            faulty_storage::{FaultError, FaultyStorage},
+            Bytes32, ChildError, Prefix, StorageMap,
+        },
+        sparse::{self, StorageNodeError},
+    };
+    use digest::Digest;
+    use sha2::Sha256;
+
+    #[derive(Debug)]
+    struct SparseNodesTable;
+
+    impl Mappable for SparseNodesTable {
+        type Key = Bytes32;
+        type SetValue = sparse::Primitive;
+        type GetValue = Self::SetValue;
+    }
+
+    #[derive(Debug)]
+    struct BinaryNodesTable;
+
+    impl Mappable for BinaryNodesTable {
+        type Key = u64;
+        type SetValue = binary::Primitive;
+        type GetValue = Self::SetValue;
+    }
+
+    fn hash<D: AsRef<[u8]>>(data: D) -> Bytes32 {
+        Sha256::digest(data.as_ref()).into()
+    }
+
+    type VerifyingSparseStorage = VerifyingStorage<
+        SparseNodesTable,
+        FaultyStorage<SparseNodesTable, StorageMap<SparseNodesTable>>,
+    >;
+
+    #[test]
+    fn test_sparse_tree_reads_pass_through_when_the_storage_is_intact() {
+        let mut storage = VerifyingSparseStorage::new(FaultyStorage::new(StorageMap::new()));
+        let mut tree = sparse::MerkleTree::new(&mut storage);
+        for i in 0_u32..10 {
+            tree.update(&hash(i.to_be_bytes()), b"DATA").unwrap();
+        }
+        // Deleting a leaf reads the leaf by its leaf key.
+        tree.delete(&hash(0_u32.to_be_bytes())).unwrap();
+        let root = tree.root();
+
+        let tree = sparse::MerkleTree::load(&mut storage, &root).unwrap();
+        assert_eq!(tree.leaves().count(), 9);
+        assert!(tree.prove(&hash(1_u32.to_be_bytes())).is_ok());
+    }
+
+    #[test]
+    fn test_sparse_tree_read_of_a_corrupt_node_returns_a_corrupt_value_error() {
+        let mut storage = VerifyingSparseStorage::new(FaultyStorage::new(StorageMap::new()));
+        let faults = storage.storage.faults();
+        let mut tree = sparse::MerkleTree::new(&mut storage);
+        for i in 0_u32..10 {
+            tree.update(&hash(i.to_be_bytes()), b"DATA").unwrap();
+        }
+        let root = tree.root();
+        let (_, _, corrupt_key, _) = storage.get(&root).unwrap().unwrap().into_owned();
+
+        // A well-formed node, stored under a key it does not hash to.
+        let leaf = sparse::Node::create_leaf(&hash(b"KEY"), b"DATA");
+        faults.corrupt(corrupt_key, leaf.as_ref().into());
+
+        let tree = sparse::MerkleTree::load(&mut storage, &root).unwrap();
+        let err = tree
+            .prove(&hash(0_u32.to_be_bytes()))
+            .expect_err("Expected prove() to return Error; got Ok");
+        assert!(matches!(
+            err,
+            sparse::MerkleTreeError::ChildError(ChildError::Error(
+                StorageNodeError::StorageError(IntegrityError::CorruptValue(key))
+            )) if key == corrupt_key
+        ));
+    }
+
+    #[test]
+    fn test_binary_tree_read_of_a_misplaced_node_returns_a_corrupt_value_error() {
+        let mut storage =
+            VerifyingStorage::new(FaultyStorage::new(StorageMap::<BinaryNodesTable>::new()));
+        let faults = storage.storage.faults();
+        let mut tree = binary::MerkleTree::new(&mut storage);
+        for i in 0_u32..4 {
+            tree.push(&i.to_be_bytes()).unwrap();
+        }
+        assert!(tree.prove(0).is_ok());
+
+        // The leaf at index 2, read back from the key of the leaf at index 0.
+        faults.corrupt(0, (2, hash(2_u32.to_be_bytes())));
+        let err = tree
+            .prove(0)
+            .expect_err("Expected prove() to return Error; got Ok");
+        assert!(matches!(
+            err,
+            binary::MerkleTreeError::StorageError(IntegrityError::CorruptValue(0))
+        ));
+    }
+
+    #[test]
+    fn test_storage_errors_are_passed_through() {
+        let storage =
+            VerifyingStorage::new(FaultyStorage::new(StorageMap::<BinaryNodesTable>::new()));
+        storage.storage.faults().fail_reads_after(0);
+        assert!(matches!(
+            storage.get(&0),
+            Err(IntegrityError::StorageError(FaultError::ReadFailed))
+        ));
+        assert!(matches!(
+            storage.get_batch(&[0, 1]),
+            Err(IntegrityError::StorageError(FaultError::ReadFailed))
+        ));
+    }
+
+    #[test]
+    fn test_sparse_primitive_with_a_height_inconsistent_with_its_prefix_is_rejected() {
+        let leaf = sparse::Node::create_leaf(&hash(b"KEY"), b"DATA");
+        let node = sparse::Node::create_node(&leaf, &sparse::Node::create_placeholder(), 1);
+        assert!(sparse::Primitive::from(&leaf).verify_key(&leaf.hash()));
+        assert!(sparse::Primitive::from(&node).verify_key(&node.hash()));
+
+        let (_, _, bytes_lo, bytes_hi) = sparse::Primitive::from(&leaf);
+        let leaf_above_height_0 = (1, Prefix::Leaf as u8, bytes_lo, bytes_hi);
+        assert!(!leaf_above_height_0.verify_key(&leaf.hash()));
+        assert!(!leaf_above_height_0.verify_key(&hash(b"KEY")));
+
+        let (_, _, bytes_lo, bytes_hi) = sparse::Primitive::from(&node);
+        let node_at_height_0 = (0, Prefix::Node as u8, bytes_lo, bytes_hi);
+        assert!(!node_at_height_0.verify_key(&node.hash()));
+    }
+
+    #[derive(Debug)]
+    struct SumNodesTable;
+
+    impl Mappable for SumNodesTable {
+        type Key = Bytes32;
+        type SetValue = sum::Node;
+        type GetValue = Self::SetValue;
+    }
+
+    #[test]
+    fn test_sum_nodes_are_verified_against_their_children() {
+        let mut storage =
+            SumVerifyingStorage::new(FaultyStorage::new(StorageMap::<SumNodesTable>::new()));
+        let faults = storage.storage.faults();
+        let mut tree = sum::MerkleTree::new(&mut storage);
+        for i in 0_u32..4 {
+            tree.push(10, &i.to_be_bytes()).unwrap();
+        }
+        let (_, root) = tree.root().unwrap();
+        let leaf_key = *sum::Node::create_leaf(10, &2_u32.to_be_bytes()).hash();
+        assert!(storage.get(&root).unwrap().is_some());
+        assert!(storage.get(&leaf_key).unwrap().is_some());
+
+        // A leaf with another fee, stored under the key of the original leaf.
+        // The leaf does not have its hash as its key, and its parent no
+        // longer matches its children.
+        let sibling_key = *sum::Node::create_leaf(10, &3_u32.to_be_bytes()).hash();
+        let parent_key = *sum::Node::create_node(1, 10, &leaf_key, 10, &sibling_key).hash();
+        faults.corrupt(leaf_key, sum::Node::create_leaf(11, &2_u32.to_be_bytes()));
+        assert!(matches!(
+            storage.get(&leaf_key),
+            Err(IntegrityError::CorruptValue(key)) if key == leaf_key
+        ));
+        assert!(matches!(
+            storage.get(&parent_key),
+            Err(IntegrityError::CorruptValue(key)) if key == parent_key
+        ));
+        assert!(storage.get(&root).is_ok());
+
+        // A node with a child missing from storage cannot be verified.
+        faults.clear();
+        faults.drop_key(sibling_key);
+        assert!(matches!(
+            storage.get(&parent_key),
+            Err(IntegrityError::CorruptValue(key)) if key == parent_key
+        ));
+    }
+}
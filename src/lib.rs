@@ -6,6 +6,8 @@ extern crate alloc;
 
 pub mod binary;
 pub mod common;
+#[cfg(feature = "std")]
+pub mod snapshot;
 pub mod sparse;
 pub mod storage;
 pub mod sum;
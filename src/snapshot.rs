@@ -0,0 +1,673 @@
+//! # Snapshots
+//!
+//! A snapshot is a portable copy of a binary or sparse Merkle tree, written
+//! as a stream of bytes that can be read back into any storage backend. A
+//! snapshot has the following layout, with integers in big-endian order:
+//!
+//! | Allocation    | Data                                         |
+//! |---------------|----------------------------------------------|
+//! | `00 - 04`     | Magic bytes (`FMSN`)                         |
+//! | `04 - 05`     | Version (1 byte, `0x01`)                     |
+//! | `05 - 06`     | Tree type (1 byte, see [`TreeType`])         |
+//! | `06 - 07`     | Hash function (1 byte, see [`HashFunction`]) |
+//! | `07 - 39`     | Root (32 bytes)                              |
+//! | `39 - 47`     | Leaves count (8 bytes)                       |
+//! | `47 - 55`     | Nodes count (8 bytes)                        |
+//! | `55 - ..`     | Nodes, in canonical order                    |
+//! | Last 32 bytes | Checksum (32 bytes)                          |
+//!
+//! The checksum is the SHA-256 hash of all the preceding bytes.
+//!
+//! **Binary tree nodes** (40 bytes) are the stored nodes of the tree, in the
+//! order they are written by pushing the leaves: each leaf is followed by the
+//! nodes joined by pushing it. Each node has the [`Encoding`] of a
+//! [`binary::Primitive`].
+//!
+//! **Sparse tree nodes** (69 bytes) are the nodes reachable from the root, in
+//! the pre-order produced by [`export`](crate::sparse::export). Each node has
+//! the [`Encoding`] of a [`sparse::Primitive`].
+//!
+//! Reading a snapshot verifies every node as it is read, and verifies the root
+//! in the header against a root the caller trusts, so a snapshot from an
+//! untrusted source can be read into storage. The checksum only detects
+//! corruption, as anyone can recompute it. The checksum is verified after
+//! the nodes are written; if reading fails, the nodes read so far remain in
+//! storage. To read a snapshot all-or-nothing, read it into a
+//! [`StorageOverlay`](crate::common::StorageOverlay) and commit the overlay
+//! on success.
+
+use crate::{
+    binary,
+    common::{Bytes32, Encoding, Position, Prefix},
+    sparse,
+    storage::{Mappable, StorageInspect, StorageMutate},
+};
+
+use digest::Digest;
+use sha2::Sha256;
+use std::{
+    fmt,
+    io::{self, Read, Write},
+};
+
+const MAGIC: [u8; 4] = *b"FMSN";
+const VERSION: u8 = 1;
+const CHECKSUM_SIZE: usize = 32;
+const BINARY_NODE_SIZE: usize = <binary::Primitive as Encoding>::SIZE;
+const SPARSE_NODE_SIZE: usize = <sparse::Primitive as Encoding>::SIZE;
+
+/// The number of nodes read from storage at a time when writing a sparse
+/// tree.
+const EXPORT_CHUNK_SIZE: usize = 1024;
+
+const BINARY: u8 = 0x00;
+const SPARSE: u8 = 0x01;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum TreeType {
+    Binary = BINARY,
+    Sparse = SPARSE,
+}
+
+impl TryFrom<u8> for TreeType {
+    type Error = u8;
+
+    fn try_from(byte: u8) -> Result<Self, Self::Error> {
+        match byte {
+            BINARY => Ok(TreeType::Binary),
+            SPARSE => Ok(TreeType::Sparse),
+            _ => Err(byte),
+        }
+    }
+}
+
+const SHA256: u8 = 0x00;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum HashFunction {
+    Sha256 = SHA256,
+}
+
+impl TryFrom<u8> for HashFunction {
+    type Error = u8;
+
+    fn try_from(byte: u8) -> Result<Self, Self::Error> {
+        match byte {
+            SHA256 => Ok(HashFunction::Sha256),
+            _ => Err(byte),
+        }
+    }
+}
+
+/// The header of a snapshot, describing the tree it holds.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SnapshotHeader {
+    pub tree_type: TreeType,
+    pub hash_function: HashFunction,
+    pub root: Bytes32,
+    pub leaves_count: u64,
+    pub nodes_count: u64,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum SnapshotError<StorageError> {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+
+    #[error("the data is not a snapshot")]
+    InvalidMagic,
+
+    #[error("snapshot version {0} is not supported")]
+    UnsupportedVersion(u8),
+
+    #[error("tree type {0} is not valid")]
+    UnknownTreeType(u8),
+
+    #[error("hash function {0} is not valid")]
+    UnknownHashFunction(u8),
+
+    #[error("the snapshot holds a tree of type {0:?}")]
+    UnexpectedTreeType(TreeType),
+
+    #[error("the snapshot holds a tree with root {}", hex::encode(.0))]
+    UnexpectedRoot(Bytes32),
+
+    #[error("node {0} of the snapshot is not the expected node")]
+    InvalidNode(u64),
+
+    #[error("the nodes of the snapshot do not match its header")]
+    HeaderMismatch,
+
+    #[error("the checksum of the snapshot does not match its contents")]
+    ChecksumMismatch,
+
+    #[error(transparent)]
+    BinaryTreeError(binary::MerkleTreeError<StorageError>),
+
+    #[error(transparent)]
+    SparseTreeError(sparse::MerkleTreeError<StorageError>),
+
+    #[error(transparent)]
+    StorageError(StorageError),
+}
+
+/// A writer that hashes the bytes written through it.
+struct ChecksumWriter<W> {
+    writer: W,
+    hash: Sha256,
+}
+
+impl<W: Write> ChecksumWriter<W> {
+    fn new(writer: W) -> Self {
+        Self {
+            writer,
+            hash: Sha256::new(),
+        }
+    }
+
+    fn write_all(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.hash.update(bytes);
+        self.writer.write_all(bytes)
+    }
+
+    fn finish(mut self) -> io::Result<()> {
+        let checksum = self.hash.finalize();
+        self.writer.write_all(&checksum)
+    }
+}
+
+/// A reader that hashes the bytes read through it.
+struct ChecksumReader<R> {
+    reader: R,
+    hash: Sha256,
+}
+
+impl<R: Read> ChecksumReader<R> {
+    fn new(reader: R) -> Self {
+        Self {
+            reader,
+            hash: Sha256::new(),
+        }
+    }
+
+    fn read_array<const N: usize>(&mut self) -> io::Result<[u8; N]> {
+        let mut bytes = [0; N];
+        self.reader.read_exact(&mut bytes)?;
+        self.hash.update(bytes);
+        Ok(bytes)
+    }
+
+    fn read_u64(&mut self) -> io::Result<u64> {
+        self.read_array().map(u64::from_be_bytes)
+    }
+
+    /// Read the checksum, and return true if it matches the bytes read.
+    fn finish(mut self) -> io::Result<bool> {
+        let mut checksum = [0; CHECKSUM_SIZE];
+        self.reader.read_exact(&mut checksum)?;
+        Ok(self.hash.finalize()[..] == checksum)
+    }
+}
+
+fn write_header<W: Write>(
+    writer: &mut ChecksumWriter<W>,
+    header: &SnapshotHeader,
+) -> io::Result<()> {
+    writer.write_all(&MAGIC)?;
+    writer.write_all(&[VERSION, header.tree_type as u8, header.hash_function as u8])?;
+    writer.write_all(&header.root)?;
+    writer.write_all(&header.leaves_count.to_be_bytes())?;
+    writer.write_all(&header.nodes_count.to_be_bytes())
+}
+
+fn read_header<R: Read, StorageError>(
+    reader: &mut ChecksumReader<R>,
+    tree_type: TreeType,
+) -> Result<SnapshotHeader, SnapshotError<StorageError>> {
+    if reader.read_array()? != MAGIC {
+        return Err(SnapshotError::InvalidMagic);
+    }
+    let [version, tree_type_byte, hash_function] = reader.read_array()?;
+    if version != VERSION {
+        return Err(SnapshotError::UnsupportedVersion(version));
+    }
+    let header = SnapshotHeader {
+        tree_type: TreeType::try_from(tree_type_byte).map_err(SnapshotError::UnknownTreeType)?,
+        hash_function: HashFunction::try_from(hash_function)
+            .map_err(SnapshotError::UnknownHashFunction)?,
+        root: reader.read_array()?,
+        leaves_count: reader.read_u64()?,
+        nodes_count: reader.read_u64()?,
+    };
+    if header.tree_type != tree_type {
+        return Err(SnapshotError::UnexpectedTreeType(header.tree_type));
+    }
+    Ok(header)
+}
+
+/// The positions of the stored nodes of a binary tree with the given number
+/// of leaves, in the order they are written by pushing the leaves. Pushing
+/// the leaf with index `i` joins as many subtrees as there are trailing ones
+/// in `i`.
+fn binary_positions(leaves_count: u64) -> impl Iterator<Item = Position> {
+    (0..leaves_count).flat_map(|index| {
+        let leaf = Position::from_leaf_index(index);
+        let joins = index.trailing_ones() as usize;
+        core::iter::successors(Some(leaf), |position| Some(position.parent())).take(joins + 1)
+    })
+}
+
+/// Write a snapshot of the binary Merkle tree with the given number of
+/// leaves. Returns the header of the snapshot.
+pub fn write_binary<TableType, StorageType, StorageError, W>(
+    storage: &StorageType,
+    leaves_count: u64,
+    writer: W,
+) -> Result<SnapshotHeader, SnapshotError<StorageError>>
+where
+    TableType: Mappable<Key = u64, SetValue = binary::Primitive, GetValue = binary::Primitive>,
+    StorageType: StorageInspect<TableType, Error = StorageError>,
+    W: Write,
+{
+    let tree = binary::MerkleTree::<TableType, _>::load(storage, leaves_count)
+        .map_err(SnapshotError::BinaryTreeError)?;
+    let header = SnapshotHeader {
+        tree_type: TreeType::Binary,
+        hash_function: HashFunction::Sha256,
+        root: tree.root(),
+        leaves_count,
+        nodes_count: 2 * leaves_count - leaves_count.count_ones() as u64,
+    };
+
+    let mut writer = ChecksumWriter::new(writer);
+    write_header(&mut writer, &header)?;
+    for position in binary_positions(leaves_count) {
+        let key = position.in_order_index();
+        let primitive = storage
+            .get(&key)
+            .map_err(SnapshotError::StorageError)?
+            .ok_or(SnapshotError::BinaryTreeError(
+                binary::MerkleTreeError::LoadError(key),
+            ))?;
+        writer.write_all(&primitive.to_bytes())?;
+    }
+    writer.finish()?;
+
+    Ok(header)
+}
+
+/// Read a snapshot of the binary Merkle tree with root `expected_root` into
+/// the given storage, and return the tree. A snapshot with another root in its
+/// header is rejected before any node is read. Each node is verified against
+/// its position and, for an internal node, against its children; the root of
+/// the tree is verified against the header.
+pub fn read_binary<TableType, StorageType, StorageError, R>(
+    mut storage: StorageType,
+    expected_root: &Bytes32,
+    reader: R,
+) -> Result<binary::MerkleTree<TableType, StorageType>, SnapshotError<StorageError>>
+where
+    TableType: Mappable<Key = u64, SetValue = binary::Primitive, GetValue = binary::Primitive>,
+    StorageType: StorageMutate<TableType, Error = StorageError>,
+    R: Read,
+{
+    let mut reader = ChecksumReader::new(reader);
+    let header = read_header(&mut reader, TreeType::Binary)?;
+    if header.root != *expected_root {
+        return Err(SnapshotError::UnexpectedRoot(header.root));
+    }
+
+    let mut subtrees: Vec<Bytes32> = Vec::new();
+    let mut positions = binary_positions(header.leaves_count);
+    for n in 0..header.nodes_count {
+        let bytes: [u8; BINARY_NODE_SIZE] = reader.read_array()?;
+        let (index, hash) =
+            binary::Primitive::from_bytes(&bytes).map_err(|_| SnapshotError::InvalidNode(n))?;
+        let position = positions.next().ok_or(SnapshotError::HeaderMismatch)?;
+        if index != position.in_order_index() {
+            return Err(SnapshotError::InvalidNode(n));
+        }
+        if position.is_node() {
+            let right_child = subtrees.pop().ok_or(SnapshotError::InvalidNode(n))?;
+            let left_child = subtrees.pop().ok_or(SnapshotError::InvalidNode(n))?;
+            if binary::node_sum(&left_child, &right_child) != hash {
+                return Err(SnapshotError::InvalidNode(n));
+            }
+        }
+        subtrees.push(hash);
+        storage
+            .insert(&index, &(index, hash))
+            .map_err(SnapshotError::StorageError)?;
+    }
+    if positions.next().is_some() {
+        return Err(SnapshotError::HeaderMismatch);
+    }
+    if !reader.finish()? {
+        return Err(SnapshotError::ChecksumMismatch);
+    }
+
+    let tree = binary::MerkleTree::load(storage, header.leaves_count)
+        .map_err(SnapshotError::BinaryTreeError)?;
+    if tree.root() != header.root {
+        return Err(SnapshotError::HeaderMismatch);
+    }
+    Ok(tree)
+}
+
+/// Write a snapshot of the sparse Merkle tree with the given root. Returns the
+/// header of the snapshot.
+///
+/// The header is written before the nodes, and holds the number of nodes and
+/// leaves of the tree, so the tree is read twice: once to count its nodes,
+/// and once to write them.
+pub fn write_sparse<TableType, StorageType, StorageError, W>(
+    storage: &StorageType,
+    root: &Bytes32,
+    writer: W,
+) -> Result<SnapshotHeader, SnapshotError<StorageError>>
+where
+    TableType: Mappable<Key = Bytes32, SetValue = sparse::Primitive, GetValue = sparse::Primitive>,
+    StorageType: StorageInspect<TableType, Error = StorageError>,
+    W: Write,
+{
+    let mut header = SnapshotHeader {
+        tree_type: TreeType::Sparse,
+        hash_function: HashFunction::Sha256,
+        root: *root,
+        leaves_count: 0,
+        nodes_count: 0,
+    };
+    for chunk in sparse::export::<TableType, _>(storage, root, EXPORT_CHUNK_SIZE) {
+        let chunk = chunk.map_err(SnapshotError::SparseTreeError)?;
+        header.nodes_count += chunk.len() as u64;
+        header.leaves_count += chunk
+            .iter()
+            .filter(|(_, prefix, _, _)| *prefix == Prefix::Leaf as u8)
+            .count() as u64;
+    }
+
+    let mut writer = ChecksumWriter::new(writer);
+    write_header(&mut writer, &header)?;
+    for chunk in sparse::export::<TableType, _>(storage, root, EXPORT_CHUNK_SIZE) {
+        let chunk = chunk.map_err(SnapshotError::SparseTreeError)?;
+        for primitive in chunk {
+            writer.write_all(&primitive.to_bytes())?;
+        }
+    }
+    writer.finish()?;
+
+    Ok(header)
+}
+
+/// Read a snapshot of the sparse Merkle tree with root `expected_root` into
+/// the given storage, and return the tree. A snapshot with another root in its
+/// header is rejected before any node is read. Each node is verified against
+/// the hash held by its parent, as by an [`Importer`](sparse::Importer),
+/// starting with the root.
+pub fn read_sparse<TableType, StorageType, StorageError, R>(
+    storage: StorageType,
+    expected_root: &Bytes32,
+    reader: R,
+) -> Result<sparse::MerkleTree<TableType, StorageType>, SnapshotError<StorageError>>
+where
+    TableType: Mappable<Key = Bytes32, SetValue = sparse::Primitive, GetValue = sparse::Primitive>,
+    StorageType: StorageMutate<TableType, Error = StorageError>,
+    StorageError: fmt::Debug + Clone + 'static,
+    R: Read,
+{
+    let mut reader = ChecksumReader::new(reader);
+    let header = read_header(&mut reader, TreeType::Sparse)?;
+    if header.root != *expected_root {
+        return Err(SnapshotError::UnexpectedRoot(header.root));
+    }
+
+    let mut importer = sparse::Importer::<TableType, _>::new(storage, expected_root);
+    let mut leaves_count = 0;
+    for n in 0..header.nodes_count {
+        let bytes: [u8; SPARSE_NODE_SIZE] = reader.read_array()?;
+        let primitive =
+            sparse::Primitive::from_bytes(&bytes).map_err(|_| SnapshotError::InvalidNode(n))?;
+        importer.import(&[primitive]).map_err(|err| match err {
+            sparse::ImportError::StorageError(err) => SnapshotError::StorageError(err),
+            _ => SnapshotError::InvalidNode(n),
+        })?;
+        if primitive.1 == Prefix::Leaf as u8 {
+            leaves_count += 1;
+        }
+    }
+    if !importer.is_complete() || leaves_count != header.leaves_count {
+        return Err(SnapshotError::HeaderMismatch);
+    }
+    if !reader.finish()? {
+        return Err(SnapshotError::ChecksumMismatch);
+    }
+
+    importer.finish().map_err(|err| match err {
+        sparse::ImportError::StorageError(err) => SnapshotError::StorageError(err),
+        sparse::ImportError::MerkleTreeError(err) => SnapshotError::SparseTreeError(err),
+        _ => SnapshotError::HeaderMismatch,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::common::StorageMap;
+    use fuel_merkle_test_helpers::TEST_DATA;
+    use std::convert::Infallible;
+
+    #[derive(Debug)]
+    struct BinaryTable;
+
+    impl Mappable for BinaryTable {
+        type Key = u64;
+        type SetValue = binary::Primitive;
+        type GetValue = Self::SetValue;
+    }
+
+    #[derive(Debug)]
+    struct SparseTable;
+
+    impl Mappable for SparseTable {
+        type Key = Bytes32;
+        type SetValue = sparse::Primitive;
+        type GetValue = Self::SetValue;
+    }
+
+    fn key(i: u32) -> Bytes32 {
+        Sha256::digest(i.to_be_bytes()).into()
+    }
+
+    fn binary_snapshot(leaves_count: usize) -> (Vec<u8>, Bytes32) {
+        let mut storage = StorageMap::<BinaryTable>::new();
+        let mut tree = binary::MerkleTree::new(&mut storage);
+        for datum in TEST_DATA[0..leaves_count].iter() {
+            tree.push(datum).unwrap();
+        }
+        let root = tree.root();
+
+        let mut snapshot = Vec::new();
+        let header = write_binary(&storage, leaves_count as u64, &mut snapshot).unwrap();
+        assert_eq!(header.root, root);
+        (snapshot, root)
+    }
+
+    fn sparse_snapshot(leaves_count: u32) -> (Vec<u8>, Bytes32) {
+        let mut storage = StorageMap::<SparseTable>::new();
+        let mut tree = sparse::MerkleTree::new(&mut storage);
+        for i in 0..leaves_count {
+            tree.update(&key(i), b"DATA").unwrap();
+        }
+        let root = tree.root();
+
+        let mut snapshot = Vec::new();
+        let header = write_sparse(&storage, &root, &mut snapshot).unwrap();
+        assert_eq!(header.leaves_count, leaves_count as u64);
+        (snapshot, root)
+    }
+
+    #[test]
+    fn test_binary_snapshot_round_trip() {
+        for leaves_count in 0..TEST_DATA.len() {
+            let (snapshot, root) = binary_snapshot(leaves_count);
+            let nodes_count = 2 * leaves_count - leaves_count.count_ones() as usize;
+            assert_eq!(
+                snapshot.len(),
+                55 + nodes_count * BINARY_NODE_SIZE + CHECKSUM_SIZE
+            );
+
+            let mut storage = StorageMap::<BinaryTable>::new();
+            let tree = read_binary(&mut storage, &root, snapshot.as_slice()).unwrap();
+            assert_eq!(tree.root(), root);
+            if leaves_count > 0 {
+                assert!(tree.prove(0).is_ok());
+            }
+        }
+    }
+
+    #[test]
+    fn test_binary_snapshot_is_written_in_push_order() {
+        let positions = binary_positions(7)
+            .map(|position| position.in_order_index())
+            .collect::<Vec<_>>();
+        assert_eq!(positions, [0, 2, 1, 4, 6, 5, 3, 8, 10, 9, 12]);
+    }
+
+    #[test]
+    fn test_sparse_snapshot_round_trip() {
+        for leaves_count in [0, 1, 2, 10, 100] {
+            let (snapshot, root) = sparse_snapshot(leaves_count);
+
+            let mut storage = StorageMap::<SparseTable>::new();
+            let mut tree = read_sparse(&mut storage, &root, snapshot.as_slice()).unwrap();
+            assert_eq!(tree.root(), root);
+            assert_eq!(tree.leaves().count(), leaves_count as usize);
+            tree.update(&key(leaves_count), b"DATA").unwrap();
+        }
+    }
+
+    #[test]
+    fn test_read_rejects_a_corrupt_checksum() {
+        let (mut snapshot, root) = sparse_snapshot(10);
+        let last = snapshot.len() - 1;
+        snapshot[last] ^= 1;
+
+        let mut storage = StorageMap::<SparseTable>::new();
+        let err = read_sparse(&mut storage, &root, snapshot.as_slice())
+            .expect_err("Expected read_sparse() to return Error; got Ok");
+        assert!(matches!(err, SnapshotError::ChecksumMismatch));
+    }
+
+    #[test]
+    fn test_read_rejects_a_corrupt_node() {
+        // Flip a bit of the hash of the last binary node, and of the data of
+        // the last sparse node.
+        let (mut snapshot, root) = binary_snapshot(8);
+        let index = snapshot.len() - CHECKSUM_SIZE - 1;
+        snapshot[index] ^= 1;
+        let mut storage = StorageMap::<BinaryTable>::new();
+        let err = read_binary(&mut storage, &root, snapshot.as_slice())
+            .expect_err("Expected read_binary() to return Error; got Ok");
+        assert!(matches!(err, SnapshotError::InvalidNode(14)));
+
+        let (mut snapshot, root) = sparse_snapshot(10);
+        let index = snapshot.len() - CHECKSUM_SIZE - 1;
+        snapshot[index] ^= 1;
+        let mut storage = StorageMap::<SparseTable>::new();
+        let err = read_sparse(&mut storage, &root, snapshot.as_slice())
+            .expect_err("Expected read_sparse() to return Error; got Ok");
+        assert!(matches!(err, SnapshotError::InvalidNode(_)));
+    }
+
+    #[test]
+    fn test_read_rejects_a_sparse_node_at_a_forged_height() {
+        // The height of a node does not contribute to its hash, and the
+        // checksum is not authenticated, so a forged height must be detected
+        // by the importer.
+        let (mut snapshot, root) = sparse_snapshot(100);
+        let offset = 55 + SPARSE_NODE_SIZE;
+        let height = u32::from_be_bytes(snapshot[offset..offset + 4].try_into().unwrap());
+        snapshot[offset..offset + 4].copy_from_slice(&(height + 7).to_be_bytes());
+        let checksum_index = snapshot.len() - CHECKSUM_SIZE;
+        let checksum = Sha256::digest(&snapshot[..checksum_index]);
+        snapshot[checksum_index..].copy_from_slice(&checksum);
+
+        let mut storage = StorageMap::<SparseTable>::new();
+        let err = read_sparse(&mut storage, &root, snapshot.as_slice())
+            .expect_err("Expected read_sparse() to return Error; got Ok");
+        assert!(matches!(err, SnapshotError::InvalidNode(1)));
+    }
+
+    #[test]
+    fn test_read_rejects_a_binary_leaf_not_matching_the_root() {
+        // A leaf can only be verified against the root in the header.
+        let (mut snapshot, root) = binary_snapshot(7);
+        let index = snapshot.len() - CHECKSUM_SIZE - 1;
+        snapshot[index] ^= 1;
+        let checksum_index = snapshot.len() - CHECKSUM_SIZE;
+        let checksum = Sha256::digest(&snapshot[..checksum_index]);
+        snapshot[checksum_index..].copy_from_slice(&checksum);
+
+        let mut storage = StorageMap::<BinaryTable>::new();
+        let err = read_binary(&mut storage, &root, snapshot.as_slice())
+            .expect_err("Expected read_binary() to return Error; got Ok");
+        assert!(matches!(err, SnapshotError::HeaderMismatch));
+    }
+
+    #[test]
+    fn test_read_rejects_a_snapshot_of_another_tree_type() {
+        let (snapshot, root) = binary_snapshot(4);
+        let mut storage = StorageMap::<SparseTable>::new();
+        let err = read_sparse(&mut storage, &root, snapshot.as_slice())
+            .expect_err("Expected read_sparse() to return Error; got Ok");
+        assert!(matches!(
+            err,
+            SnapshotError::UnexpectedTreeType(TreeType::Binary)
+        ));
+    }
+
+    #[test]
+    fn test_read_rejects_an_invalid_header() {
+        let (snapshot, root) = binary_snapshot(4);
+        let mut storage = StorageMap::<BinaryTable>::new();
+
+        let mut invalid = snapshot.clone();
+        invalid[0] = b'X';
+        let err = read_binary::<_, _, Infallible, _>(&mut storage, &root, invalid.as_slice())
+            .expect_err("Expected read_binary() to return Error; got Ok");
+        assert!(matches!(err, SnapshotError::InvalidMagic));
+
+        let mut invalid = snapshot.clone();
+        invalid[4] = 2;
+        let err = read_binary::<_, _, Infallible, _>(&mut storage, &root, invalid.as_slice())
+            .expect_err("Expected read_binary() to return Error; got Ok");
+        assert!(matches!(err, SnapshotError::UnsupportedVersion(2)));
+
+        let mut invalid = snapshot;
+        invalid.truncate(60);
+        let err = read_binary::<_, _, Infallible, _>(&mut storage, &root, invalid.as_slice())
+            .expect_err("Expected read_binary() to return Error; got Ok");
+        assert!(matches!(err, SnapshotError::Io(_)));
+    }
+
+    #[test]
+    fn test_read_rejects_a_snapshot_of_another_root() {
+        let (snapshot, root) = sparse_snapshot(10);
+        let (_, other_root) = sparse_snapshot(11);
+        let mut storage = StorageMap::<SparseTable>::new();
+        let err = read_sparse(&mut storage, &other_root, snapshot.as_slice())
+            .expect_err("Expected read_sparse() to return Error; got Ok");
+        assert!(matches!(err, SnapshotError::UnexpectedRoot(header_root) if header_root == root));
+        assert!(!storage.contains_key(&root).unwrap());
+
+        let (snapshot, root) = binary_snapshot(4);
+        let (_, other_root) = binary_snapshot(5);
+        let mut storage = StorageMap::<BinaryTable>::new();
+        let err = read_binary(&mut storage, &other_root, snapshot.as_slice())
+            .expect_err("Expected read_binary() to return Error; got Ok");
+        assert!(matches!(err, SnapshotError::UnexpectedRoot(header_root) if header_root == root));
+        assert!(!storage.contains_key(&0).unwrap());
+    }
+}
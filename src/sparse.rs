@@ -1,11 +1,25 @@
+mod checkpoint;
+mod diff;
 mod hash;
+mod integrity;
+mod leaves_iterator;
 mod merkle_tree;
 mod node;
 mod primitive;
+mod proof;
+mod sync;
+mod witness;
 
 pub(crate) use hash::zero_sum;
 pub(crate) use node::{Node, StorageNode, StorageNodeError};
 
-pub use merkle_tree::{MerkleTree, MerkleTreeError};
+pub use checkpoint::CheckpointStorage;
+pub use diff::{diff, Change, DiffIter};
+pub use integrity::{check, check_with_leaves_table};
+pub use leaves_iterator::LeavesIter;
+pub use merkle_tree::{root_from_set, MerkleTree, MerkleTreeError};
 pub use primitive::Primitive;
+pub use proof::{MultiProof, Proof, SubtreeProof};
+pub use sync::{export, ExportIter, ImportError, Importer};
+pub use witness::{Operation, Witness};
 pub mod in_memory;
@@ -0,0 +1,435 @@
+use crate::{
+    common::{Bytes32, ChangeSet},
+    sparse::Primitive,
+    storage::{GetBatchResult, Mappable, StorageInspect, StorageInspectBatch, StorageMutate},
+};
+
+use alloc::{borrow::Cow, vec::Vec};
+use core::marker::PhantomData;
+
+/// The keys written to each table since a checkpoint was opened, mapped to
+/// their new values, or to `None` for removed keys.
+#[derive(Debug, Default)]
+struct Overlay {
+    nodes: ChangeSet<Bytes32, Primitive>,
+    leaves: ChangeSet<Bytes32, Primitive>,
+}
+
+impl Overlay {
+    fn merge(&mut self, other: Overlay) {
+        self.nodes.merge(other.nodes);
+        self.leaves.merge(other.leaves);
+    }
+}
+
+/// # Checkpoint Storage
+///
+/// The storage of a sparse Merkle tree, together with a stack of overlays
+/// that buffer the tree's writes while checkpoints are open. See
+/// [`MerkleTree::checkpoint`](crate::sparse::MerkleTree::checkpoint).
+///
+/// Each open checkpoint has an overlay mapping the keys written since the
+/// checkpoint was opened to their new values, or to `None` for removed keys.
+/// Writes go to the overlay of the most recent checkpoint, and reads consult
+/// the overlays from the most recent to the oldest before the underlying
+/// storage. Without open checkpoints, reads and writes go directly to the
+/// underlying storage.
+///
+/// The nodes table is accessed through the storage traits; the leaves table
+/// is accessed through the `*_leaf` methods, and has overlays of its own, so
+/// that the two tables may be the same table or different tables.
+#[derive(Debug)]
+pub struct CheckpointStorage<TableType, StorageType, LeavesTableType = TableType> {
+    storage: StorageType,
+    overlays: Vec<Overlay>,
+    phantom_table: PhantomData<(TableType, LeavesTableType)>,
+}
+
+impl<TableType, StorageType, LeavesTableType>
+    CheckpointStorage<TableType, StorageType, LeavesTableType>
+{
+    pub(crate) fn new(storage: StorageType) -> Self {
+        Self {
+            storage,
+            overlays: Vec::new(),
+            phantom_table: Default::default(),
+        }
+    }
+
+    pub(crate) fn push_overlay(&mut self) {
+        self.overlays.push(Overlay::default());
+    }
+
+    pub(crate) fn discard_overlay(&mut self) {
+        self.overlays.pop();
+    }
+
+    /// Merge the most recent overlay into the overlay beneath it, or, if it is
+    /// the only overlay, write it to the underlying storage. The overlay is
+    /// removed only once it is written: if the underlying storage returns an
+    /// error, the overlay remains with the changes not yet written.
+    pub(crate) fn commit_overlay<StorageError>(&mut self) -> Result<(), StorageError>
+    where
+        TableType: Mappable<Key = Bytes32, SetValue = Primitive, GetValue = Primitive>,
+        LeavesTableType: Mappable<Key = Bytes32, SetValue = Primitive, GetValue = Primitive>,
+        StorageType: StorageMutate<TableType, Error = StorageError>
+            + StorageMutate<LeavesTableType, Error = StorageError>,
+    {
+        match self.overlays.as_mut_slice() {
+            [] => {}
+            [overlay] => {
+                overlay.nodes.write_to::<TableType, _>(&mut self.storage)?;
+                overlay
+                    .leaves
+                    .write_to::<LeavesTableType, _>(&mut self.storage)?;
+                self.overlays.pop();
+            }
+            [.., parent, overlay] => {
+                parent.merge(core::mem::take(overlay));
+                self.overlays.pop();
+            }
+        }
+        Ok(())
+    }
+
+    /// Get the leaf stored under the given leaf key in the leaves table.
+    pub(crate) fn get_leaf(
+        &self,
+        key: &Bytes32,
+    ) -> Result<Option<Primitive>, <StorageType as StorageInspect<LeavesTableType>>::Error>
+    where
+        LeavesTableType: Mappable<Key = Bytes32, SetValue = Primitive, GetValue = Primitive>,
+        StorageType: StorageInspect<LeavesTableType>,
+    {
+        for overlay in self.overlays.iter().rev() {
+            if let Some(value) = overlay.leaves.get(key) {
+                return Ok(*value);
+            }
+        }
+        let leaf = StorageInspect::<LeavesTableType>::get(&self.storage, key)?;
+        Ok(leaf.map(Cow::into_owned))
+    }
+
+    /// Store the given leaf under the given leaf key in the leaves table.
+    pub(crate) fn insert_leaf(
+        &mut self,
+        key: &Bytes32,
+        leaf: &Primitive,
+    ) -> Result<(), <StorageType as StorageInspect<LeavesTableType>>::Error>
+    where
+        LeavesTableType: Mappable<Key = Bytes32, SetValue = Primitive, GetValue = Primitive>,
+        StorageType: StorageMutate<LeavesTableType>,
+    {
+        match self.overlays.last_mut() {
+            Some(overlay) => {
+                overlay.leaves.insert(*key, Some(*leaf));
+            }
+            None => {
+                StorageMutate::<LeavesTableType>::insert(&mut self.storage, key, leaf)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove the leaf stored under the given leaf key from the leaves table.
+    pub(crate) fn remove_leaf(
+        &mut self,
+        key: &Bytes32,
+    ) -> Result<(), <StorageType as StorageInspect<LeavesTableType>>::Error>
+    where
+        LeavesTableType: Mappable<Key = Bytes32, SetValue = Primitive, GetValue = Primitive>,
+        StorageType: StorageMutate<LeavesTableType>,
+    {
+        match self.overlays.last_mut() {
+            Some(overlay) => {
+                overlay.leaves.insert(*key, None);
+            }
+            None => {
+                StorageMutate::<LeavesTableType>::remove(&mut self.storage, key)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<TableType, StorageType, LeavesTableType> StorageInspect<TableType>
+    for CheckpointStorage<TableType, StorageType, LeavesTableType>
+where
+    TableType: Mappable<Key = Bytes32, SetValue = Primitive, GetValue = Primitive>,
+    StorageType: StorageInspect<TableType>,
+{
+    type Error = StorageType::Error;
+
+    fn get(&self, key: &Bytes32) -> Result<Option<Cow<'_, Primitive>>, Self::Error> {
+        for overlay in self.overlays.iter().rev() {
+            if let Some(value) = overlay.nodes.get(key) {
+                return Ok(value.as_ref().map(Cow::Borrowed));
+            }
+        }
+        StorageInspect::<TableType>::get(&self.storage, key)
+    }
+
+    fn contains_key(&self, key: &Bytes32) -> Result<bool, Self::Error> {
+        for overlay in self.overlays.iter().rev() {
+            if let Some(value) = overlay.nodes.get(key) {
+                return Ok(value.is_some());
+            }
+        }
+        StorageInspect::<TableType>::contains_key(&self.storage, key)
+    }
+}
+
+impl<TableType, StorageType, LeavesTableType> StorageInspectBatch<TableType>
+    for CheckpointStorage<TableType, StorageType, LeavesTableType>
+where
+    TableType: Mappable<Key = Bytes32, SetValue = Primitive, GetValue = Primitive>,
+    StorageType: StorageInspectBatch<TableType>,
+{
+    fn get_batch(&self, keys: &[Bytes32]) -> GetBatchResult<'_, Primitive, Self::Error> {
+        let buffered = keys
+            .iter()
+            .map(|key| {
+                self.overlays
+                    .iter()
+                    .rev()
+                    .find_map(|overlay| overlay.nodes.get(key))
+            })
+            .collect::<Vec<_>>();
+        let missing = keys
+            .iter()
+            .zip(buffered.iter())
+            .filter(|(_, value)| value.is_none())
+            .map(|(key, _)| *key)
+            .collect::<Vec<_>>();
+
+        let mut fetched =
+            StorageInspectBatch::<TableType>::get_batch(&self.storage, &missing)?.into_iter();
+        let values = buffered
+            .into_iter()
+            .map(|value| match value {
+                Some(value) => value.as_ref().map(Cow::Borrowed),
+                None => fetched.next().flatten(),
+            })
+            .collect();
+        Ok(values)
+    }
+}
+
+impl<TableType, StorageType, LeavesTableType> StorageMutate<TableType>
+    for CheckpointStorage<TableType, StorageType, LeavesTableType>
+where
+    TableType: Mappable<Key = Bytes32, SetValue = Primitive, GetValue = Primitive>,
+    StorageType: StorageMutate<TableType>,
+{
+    fn insert(
+        &mut self,
+        key: &Bytes32,
+        value: &Primitive,
+    ) -> Result<Option<Primitive>, Self::Error> {
+        if self.overlays.is_empty() {
+            return StorageMutate::<TableType>::insert(&mut self.storage, key, value);
+        }
+        let previous = self.get(key)?.map(Cow::into_owned);
+        if let Some(overlay) = self.overlays.last_mut() {
+            overlay.nodes.insert(*key, Some(*value));
+        }
+        Ok(previous)
+    }
+
+    fn remove(&mut self, key: &Bytes32) -> Result<Option<Primitive>, Self::Error> {
+        if self.overlays.is_empty() {
+            return StorageMutate::<TableType>::remove(&mut self.storage, key);
+        }
+        let previous = self.get(key)?.map(Cow::into_owned);
+        if let Some(overlay) = self.overlays.last_mut() {
+            overlay.nodes.insert(*key, None);
+        }
+        Ok(previous)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        common::{
+            faulty_storage::{FaultError, FaultyStorage},
+            Bytes32, StorageMap,
+        },
+        sparse::{hash::sum, MerkleTree, MerkleTreeError, Primitive},
+        storage::{Mappable, StorageInspect, StorageMutate},
+    };
+    use alloc::{borrow::Cow, collections::BTreeMap};
+    use core::convert::Infallible;
+
+    #[derive(Debug)]
+    struct TestTable;
+
+    impl Mappable for TestTable {
+        type Key = Bytes32;
+        type SetValue = Primitive;
+        type GetValue = Self::SetValue;
+    }
+
+    /// A storage that can be cloned and compared, to verify that a reverted
+    /// checkpoint leaves no trace in storage.
+    #[derive(Debug, Clone, Default, PartialEq, Eq)]
+    struct TestStorage {
+        map: BTreeMap<Bytes32, Primitive>,
+    }
+
+    impl StorageInspect<TestTable> for TestStorage {
+        type Error = Infallible;
+
+        fn get(&self, key: &Bytes32) -> Result<Option<Cow<'_, Primitive>>, Self::Error> {
+            Ok(self.map.get(key).map(Cow::Borrowed))
+        }
+
+        fn contains_key(&self, key: &Bytes32) -> Result<bool, Self::Error> {
+            Ok(self.map.contains_key(key))
+        }
+    }
+
+    impl StorageMutate<TestTable> for TestStorage {
+        fn insert(
+            &mut self,
+            key: &Bytes32,
+            value: &Primitive,
+        ) -> Result<Option<Primitive>, Self::Error> {
+            Ok(self.map.insert(*key, *value))
+        }
+
+        fn remove(&mut self, key: &Bytes32) -> Result<Option<Primitive>, Self::Error> {
+            Ok(self.map.remove(key))
+        }
+    }
+
+    fn key(i: u32) -> Bytes32 {
+        sum(i.to_be_bytes())
+    }
+
+    #[test]
+    fn test_revert_restores_the_root_and_leaves_storage_unchanged() {
+        let mut storage = TestStorage::default();
+        let mut tree = MerkleTree::<TestTable, _>::new(&mut storage);
+        for i in 0_u32..10 {
+            tree.update(&key(i), b"DATA").unwrap();
+        }
+        let root = tree.root();
+        let expected_storage = storage.clone();
+
+        let mut tree = MerkleTree::<TestTable, _>::load(&mut storage, &root).unwrap();
+        tree.checkpoint();
+        for i in 5_u32..15 {
+            tree.update(&key(i), b"CHANGE").unwrap();
+        }
+        tree.delete(&key(0)).unwrap();
+        assert_ne!(tree.root(), root);
+        tree.revert();
+        assert_eq!(tree.root(), root);
+
+        assert_eq!(storage, expected_storage);
+    }
+
+    #[test]
+    fn test_commit_writes_the_buffered_nodes_to_storage() {
+        let mut storage = TestStorage::default();
+        let mut tree = MerkleTree::<TestTable, _>::new(&mut storage);
+        for i in 0_u32..10 {
+            tree.update(&key(i), b"DATA").unwrap();
+        }
+
+        tree.checkpoint();
+        for i in 5_u32..15 {
+            tree.update(&key(i), b"CHANGE").unwrap();
+        }
+        tree.delete(&key(0)).unwrap();
+
+        // Reads within the checkpoint see the buffered nodes.
+        assert_eq!(tree.leaves().count(), 14);
+        assert_eq!(tree.get(&key(5)).unwrap(), Some(sum(b"CHANGE")));
+
+        tree.commit().unwrap();
+        let root = tree.root();
+
+        // The committed tree is stored exactly as if it had been updated
+        // without a checkpoint.
+        let mut expected_storage = TestStorage::default();
+        let mut expected_tree = MerkleTree::<TestTable, _>::new(&mut expected_storage);
+        for i in 0_u32..10 {
+            expected_tree.update(&key(i), b"DATA").unwrap();
+        }
+        for i in 5_u32..15 {
+            expected_tree.update(&key(i), b"CHANGE").unwrap();
+        }
+        expected_tree.delete(&key(0)).unwrap();
+        assert_eq!(root, expected_tree.root());
+        assert_eq!(storage, expected_storage);
+    }
+
+    #[test]
+    fn test_failed_commit_keeps_the_checkpoint_open() {
+        let mut storage = FaultyStorage::new(TestStorage::default());
+        let faults = storage.faults();
+        let mut tree = MerkleTree::<TestTable, _>::new(&mut storage);
+        tree.checkpoint();
+        for i in 0_u32..10 {
+            tree.update(&key(i), b"DATA").unwrap();
+        }
+        let root = tree.root();
+
+        faults.fail_writes_after(5);
+        assert!(matches!(
+            tree.commit(),
+            Err(MerkleTreeError::StorageError(FaultError::WriteFailed))
+        ));
+
+        // The nodes not yet written are still buffered, and the commit can be
+        // retried.
+        assert_eq!(tree.root(), root);
+        assert_eq!(tree.leaves().count(), 10);
+        faults.clear();
+        tree.commit().unwrap();
+
+        let tree = MerkleTree::<TestTable, _>::load(&mut storage, &root).unwrap();
+        assert_eq!(tree.leaves().count(), 10);
+        for i in 0_u32..10 {
+            assert_eq!(tree.get(&key(i)).unwrap(), Some(sum(b"DATA")));
+        }
+    }
+
+    #[test]
+    fn test_nested_checkpoints_commit_and_revert_independently() {
+        let mut storage = StorageMap::<TestTable>::new();
+        let mut tree = MerkleTree::new(&mut storage);
+        tree.update(&key(0), b"DATA").unwrap();
+        let root_0 = tree.root();
+
+        tree.checkpoint();
+        tree.update(&key(1), b"DATA").unwrap();
+        let root_1 = tree.root();
+
+        // A reverted inner checkpoint restores the root of the outer one.
+        tree.checkpoint();
+        tree.update(&key(2), b"DATA").unwrap();
+        tree.revert();
+        assert_eq!(tree.root(), root_1);
+
+        // A committed inner checkpoint is still reverted with the outer one.
+        tree.checkpoint();
+        tree.update(&key(3), b"DATA").unwrap();
+        tree.commit().unwrap();
+        assert_eq!(tree.leaves().count(), 3);
+        tree.revert();
+        assert_eq!(tree.root(), root_0);
+        assert_eq!(tree.leaves().count(), 1);
+        assert_eq!(tree.get(&key(1)).unwrap(), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "no open checkpoint")]
+    fn test_revert_without_a_checkpoint_panics() {
+        let mut storage = StorageMap::<TestTable>::new();
+        let mut tree = MerkleTree::new(&mut storage);
+        tree.revert();
+    }
+}
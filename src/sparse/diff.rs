@@ -0,0 +1,401 @@
+use crate::{
+    common::{Bytes32, Node as NodeTrait, ParentNode as ParentNodeTrait},
+    sparse::{merkle_tree::load_root_node, LeavesIter, MerkleTreeError, Primitive, StorageNode},
+    storage::{Mappable, SingleReads, StorageInspect},
+};
+
+use alloc::{vec, vec::Vec};
+use core::{cmp::Ordering, fmt};
+
+/// A change to a single leaf between two versions of a sparse Merkle tree.
+/// Leaves are identified by their key and described by their data hash.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Change {
+    /// A leaf with the given key and data hash is present in the new tree
+    /// only.
+    Added(
+        #[cfg_attr(feature = "serde", serde(with = "crate::common::serde::bytes32"))] Bytes32,
+        #[cfg_attr(feature = "serde", serde(with = "crate::common::serde::bytes32"))] Bytes32,
+    ),
+    /// A leaf with the given key and data hash is present in the old tree
+    /// only.
+    Removed(
+        #[cfg_attr(feature = "serde", serde(with = "crate::common::serde::bytes32"))] Bytes32,
+        #[cfg_attr(feature = "serde", serde(with = "crate::common::serde::bytes32"))] Bytes32,
+    ),
+    /// A leaf with the given key is present in both trees, with the old data
+    /// hash and the new data hash respectively.
+    Changed(
+        #[cfg_attr(feature = "serde", serde(with = "crate::common::serde::bytes32"))] Bytes32,
+        #[cfg_attr(feature = "serde", serde(with = "crate::common::serde::bytes32"))] Bytes32,
+        #[cfg_attr(feature = "serde", serde(with = "crate::common::serde::bytes32"))] Bytes32,
+    ),
+}
+
+impl Change {
+    pub fn key(&self) -> &Bytes32 {
+        match self {
+            Change::Added(key, _) => key,
+            Change::Removed(key, _) => key,
+            Change::Changed(key, _, _) => key,
+        }
+    }
+}
+
+enum Pending<'storage, TableType, StorageType> {
+    /// The old and the new subtree at the same position in the tree.
+    Subtrees(
+        StorageNode<'storage, TableType, StorageType, SingleReads>,
+        StorageNode<'storage, TableType, StorageType, SingleReads>,
+    ),
+    /// The leaves of the old and the new subtree at the same position in the
+    /// tree, to be compared leaf by leaf. Each iterator is paired with its
+    /// next leaf.
+    Leaves(
+        LeavesIter<'storage, TableType, StorageType>,
+        Option<(Bytes32, Bytes32)>,
+        LeavesIter<'storage, TableType, StorageType>,
+        Option<(Bytes32, Bytes32)>,
+    ),
+}
+
+/// # Diff Iterator
+///
+/// An iterator over the changes between two sparse Merkle trees sharing the
+/// same storage, yielding a [`Change`] for every key that was added, removed,
+/// or changed, in ascending key order. See [`diff`].
+///
+/// The iterator descends both trees simultaneously, comparing the nodes found
+/// at the same position in each tree. Nodes with equal hashes have equal
+/// subtrees, and are skipped without being traversed. When both nodes are
+/// internal nodes, the iterator descends to their children. Otherwise, at
+/// least one of the subtrees holds at most one leaf, and the leaves of both
+/// subtrees are compared in key order.
+///
+/// If a node cannot be loaded from storage, the iterator yields the error and
+/// terminates.
+pub struct DiffIter<'storage, TableType, StorageType> {
+    storage: &'storage StorageType,
+    stack: Vec<Pending<'storage, TableType, StorageType>>,
+}
+
+/// Compute the changes between the sparse Merkle trees with roots `old_root`
+/// and `new_root`. Both trees must be held in `storage`. The changes are
+/// computed lazily by the returned [`DiffIter`].
+pub fn diff<'storage, TableType, StorageType>(
+    storage: &'storage StorageType,
+    old_root: &Bytes32,
+    new_root: &Bytes32,
+) -> Result<DiffIter<'storage, TableType, StorageType>, MerkleTreeError<StorageType::Error>>
+where
+    TableType: Mappable<Key = Bytes32, SetValue = Primitive, GetValue = Primitive>,
+    StorageType: StorageInspect<TableType>,
+{
+    let old_root_node = StorageNode::new(storage, load_root_node(storage, old_root)?);
+    let new_root_node = StorageNode::new(storage, load_root_node(storage, new_root)?);
+    let iter = DiffIter {
+        storage,
+        stack: vec![Pending::Subtrees(old_root_node, new_root_node)],
+    };
+    Ok(iter)
+}
+
+impl<'storage, TableType, StorageType> DiffIter<'storage, TableType, StorageType>
+where
+    TableType: Mappable<Key = Bytes32, SetValue = Primitive, GetValue = Primitive>,
+    StorageType: StorageInspect<TableType>,
+    StorageType::Error: fmt::Debug + Clone,
+{
+    fn leaves(
+        &self,
+        storage_node: StorageNode<'storage, TableType, StorageType, SingleReads>,
+    ) -> LeavesIter<'storage, TableType, StorageType> {
+        LeavesIter::new(self.storage, storage_node.into_node(), None, 0)
+    }
+
+    fn next_change(&mut self) -> Option<Result<Change, MerkleTreeError<StorageType::Error>>> {
+        while let Some(pending) = self.stack.pop() {
+            match pending {
+                Pending::Subtrees(old_node, new_node) => {
+                    if old_node.hash() == new_node.hash() {
+                        continue;
+                    }
+                    if old_node.is_node() && new_node.is_node() {
+                        // Only the children with different hashes are loaded
+                        // and compared.
+                        let right_children = (old_node.right_child_key()
+                            != new_node.right_child_key())
+                        .then(|| Ok((old_node.right_child()?, new_node.right_child()?)))
+                        .transpose();
+                        let left_children = (old_node.left_child_key()
+                            != new_node.left_child_key())
+                        .then(|| Ok((old_node.left_child()?, new_node.left_child()?)))
+                        .transpose();
+                        match (left_children, right_children) {
+                            (Ok(left_children), Ok(right_children)) => {
+                                if let Some((old_right, new_right)) = right_children {
+                                    self.stack.push(Pending::Subtrees(old_right, new_right));
+                                }
+                                if let Some((old_left, new_left)) = left_children {
+                                    self.stack.push(Pending::Subtrees(old_left, new_left));
+                                }
+                            }
+                            (Err(err), _) | (_, Err(err)) => {
+                                return Some(Err(MerkleTreeError::ChildError(err)))
+                            }
+                        }
+                    } else {
+                        let mut old_leaves = self.leaves(old_node);
+                        let mut new_leaves = self.leaves(new_node);
+                        let old_leaf = match old_leaves.next().transpose() {
+                            Ok(old_leaf) => old_leaf,
+                            Err(err) => return Some(Err(err)),
+                        };
+                        let new_leaf = match new_leaves.next().transpose() {
+                            Ok(new_leaf) => new_leaf,
+                            Err(err) => return Some(Err(err)),
+                        };
+                        self.stack
+                            .push(Pending::Leaves(old_leaves, old_leaf, new_leaves, new_leaf));
+                    }
+                }
+                Pending::Leaves(mut old_leaves, old_leaf, mut new_leaves, new_leaf) => {
+                    let order = match (old_leaf, new_leaf) {
+                        (None, None) => continue,
+                        (Some(_), None) => Ordering::Less,
+                        (None, Some(_)) => Ordering::Greater,
+                        (Some((old_key, _)), Some((new_key, _))) => old_key.cmp(&new_key),
+                    };
+                    let advance_old = order != Ordering::Greater;
+                    let advance_new = order != Ordering::Less;
+                    let change = match (old_leaf, new_leaf) {
+                        (Some((key, data)), _) if order == Ordering::Less => {
+                            Some(Change::Removed(key, data))
+                        }
+                        (_, Some((key, data))) if order == Ordering::Greater => {
+                            Some(Change::Added(key, data))
+                        }
+                        (Some((key, old_data)), Some((_, new_data))) if old_data != new_data => {
+                            Some(Change::Changed(key, old_data, new_data))
+                        }
+                        _ => None,
+                    };
+
+                    let old_leaf = if advance_old {
+                        match old_leaves.next().transpose() {
+                            Ok(old_leaf) => old_leaf,
+                            Err(err) => return Some(Err(err)),
+                        }
+                    } else {
+                        old_leaf
+                    };
+                    let new_leaf = if advance_new {
+                        match new_leaves.next().transpose() {
+                            Ok(new_leaf) => new_leaf,
+                            Err(err) => return Some(Err(err)),
+                        }
+                    } else {
+                        new_leaf
+                    };
+                    self.stack
+                        .push(Pending::Leaves(old_leaves, old_leaf, new_leaves, new_leaf));
+
+                    if let Some(change) = change {
+                        return Some(Ok(change));
+                    }
+                }
+            }
+        }
+
+        None
+    }
+}
+
+impl<'storage, TableType, StorageType> Iterator for DiffIter<'storage, TableType, StorageType>
+where
+    TableType: Mappable<Key = Bytes32, SetValue = Primitive, GetValue = Primitive>,
+    StorageType: StorageInspect<TableType>,
+    StorageType::Error: fmt::Debug + Clone,
+{
+    type Item = Result<Change, MerkleTreeError<StorageType::Error>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let change = self.next_change();
+        if let Some(Err(_)) = change {
+            self.stack.clear();
+        }
+        change
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        common::{Bit, Bytes32, Msb, Prefix, StorageMap},
+        sparse::{diff, hash::sum, zero_sum, Change, MerkleTree, MerkleTreeError, Node, Primitive},
+    };
+    use alloc::vec::Vec;
+    use fuel_storage::{Mappable, StorageInspect, StorageMutate};
+
+    #[derive(Debug)]
+    struct TestTable;
+
+    impl Mappable for TestTable {
+        type Key = Bytes32;
+        type SetValue = Primitive;
+        type GetValue = Self::SetValue;
+    }
+
+    fn key(i: u32) -> Bytes32 {
+        sum(i.to_be_bytes())
+    }
+
+    fn copy_path(
+        from: &StorageMap<TestTable>,
+        to: &mut StorageMap<TestTable>,
+        root: &Bytes32,
+        key: &Bytes32,
+    ) {
+        let mut current = *root;
+        while let Some(primitive) = from.get(&current).unwrap() {
+            let primitive = primitive.into_owned();
+            to.insert(&current, &primitive).unwrap();
+            let (height, prefix, left_key, right_key) = primitive;
+            if prefix == Prefix::Leaf as u8 {
+                break;
+            }
+            let depth = Node::max_height() - height as usize;
+            current = match key.get_bit_at_index_from_msb(depth) {
+                Some(Bit::_0) => left_key,
+                _ => right_key,
+            };
+        }
+    }
+
+    fn diff_changes(
+        storage: &StorageMap<TestTable>,
+        old_root: &Bytes32,
+        new_root: &Bytes32,
+    ) -> Vec<Change> {
+        diff(storage, old_root, new_root)
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_diff_of_equal_roots_is_empty() {
+        let mut storage = StorageMap::<TestTable>::new();
+        let mut tree = MerkleTree::new(&mut storage);
+        for i in 0_u32..10 {
+            tree.update(&key(i), b"DATA").unwrap();
+        }
+        let root = tree.root();
+
+        assert_eq!(diff_changes(&storage, &root, &root), []);
+        assert_eq!(diff_changes(&storage, zero_sum(), zero_sum()), []);
+    }
+
+    #[test]
+    fn test_diff_from_the_empty_root_adds_every_leaf() {
+        let mut storage = StorageMap::<TestTable>::new();
+        let mut tree = MerkleTree::new(&mut storage);
+        for i in 0_u32..10 {
+            tree.update(&key(i), b"DATA").unwrap();
+        }
+        let root = tree.root();
+
+        let mut expected_changes = (0_u32..10)
+            .map(|i| Change::Added(key(i), sum(b"DATA")))
+            .collect::<Vec<_>>();
+        expected_changes.sort_by_key(|change| *change.key());
+        assert_eq!(diff_changes(&storage, zero_sum(), &root), expected_changes);
+
+        let mut expected_changes = (0_u32..10)
+            .map(|i| Change::Removed(key(i), sum(b"DATA")))
+            .collect::<Vec<_>>();
+        expected_changes.sort_by_key(|change| *change.key());
+        assert_eq!(diff_changes(&storage, &root, zero_sum()), expected_changes);
+    }
+
+    #[test]
+    fn test_diff_returns_added_removed_and_changed_leaves_in_key_order() {
+        // Deleting leaves removes nodes of the old tree from storage, so the
+        // new tree is built separately from the old tree.
+        let mut storage = StorageMap::<TestTable>::new();
+        let old_set = (0_u32..50).map(|i| (key(i), b"DATA".to_vec()));
+        let old_root = MerkleTree::from_set(&mut storage, old_set).unwrap().root();
+        let new_set = (0_u32..10)
+            .chain(30..60)
+            .map(|i| (key(i), b"DATA".to_vec()))
+            .chain((20_u32..30).map(|i| (key(i), b"CHANGE".to_vec())));
+        let new_root = MerkleTree::from_set(&mut storage, new_set).unwrap().root();
+
+        let mut expected_changes = (10_u32..20)
+            .map(|i| Change::Removed(key(i), sum(b"DATA")))
+            .chain((20_u32..30).map(|i| Change::Changed(key(i), sum(b"DATA"), sum(b"CHANGE"))))
+            .chain((50_u32..60).map(|i| Change::Added(key(i), sum(b"DATA"))))
+            .collect::<Vec<_>>();
+        expected_changes.sort_by_key(|change| *change.key());
+        assert_eq!(
+            diff_changes(&storage, &old_root, &new_root),
+            expected_changes
+        );
+    }
+
+    #[test]
+    fn test_diff_between_single_leaf_and_many_leaves() {
+        let mut storage = StorageMap::<TestTable>::new();
+        let mut tree = MerkleTree::new(&mut storage);
+        tree.update(&key(0), b"DATA").unwrap();
+        let old_root = tree.root();
+        tree.update(&key(0), b"CHANGE").unwrap();
+        tree.update(&key(1), b"DATA").unwrap();
+        tree.update(&key(2), b"DATA").unwrap();
+        let new_root = tree.root();
+
+        let mut expected_changes = [
+            Change::Changed(key(0), sum(b"DATA"), sum(b"CHANGE")),
+            Change::Added(key(1), sum(b"DATA")),
+            Change::Added(key(2), sum(b"DATA")),
+        ];
+        expected_changes.sort_by_key(|change| *change.key());
+        assert_eq!(
+            diff_changes(&storage, &old_root, &new_root),
+            expected_changes
+        );
+    }
+
+    #[test]
+    fn test_diff_skips_subtrees_with_equal_hashes() {
+        let mut storage = StorageMap::<TestTable>::new();
+        let mut tree = MerkleTree::new(&mut storage);
+        for i in 0_u32..50 {
+            tree.update(&key(i), b"DATA").unwrap();
+        }
+        let old_root = tree.root();
+        tree.update(&key(0), b"CHANGE").unwrap();
+        let new_root = tree.root();
+
+        // Copy only the nodes on the path to the changed leaf. The diff must
+        // not load any of the other nodes.
+        let mut path_storage = StorageMap::<TestTable>::new();
+        copy_path(&storage, &mut path_storage, &old_root, &key(0));
+        copy_path(&storage, &mut path_storage, &new_root, &key(0));
+
+        assert_eq!(
+            diff_changes(&path_storage, &old_root, &new_root),
+            [Change::Changed(key(0), sum(b"DATA"), sum(b"CHANGE"))]
+        );
+    }
+
+    #[test]
+    fn test_diff_returns_an_error_if_a_root_is_not_in_storage() {
+        let storage = StorageMap::<TestTable>::new();
+        let err = diff(&storage, zero_sum(), &sum(b"ROOT"))
+            .err()
+            .expect("Expected diff() to return Error; got Ok");
+        assert!(matches!(err, MerkleTreeError::LoadError(_)));
+    }
+}
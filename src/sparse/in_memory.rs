@@ -1,6 +1,6 @@
 use crate::{
     common::{Bytes32, StorageMap},
-    sparse::{self, Primitive},
+    sparse::{self, MultiProof, Primitive, Proof},
 };
 use fuel_storage::Mappable;
 
@@ -29,6 +29,17 @@ impl MerkleTree {
         }
     }
 
+    pub fn from_set<B, I, D>(set: I) -> Self
+    where
+        I: IntoIterator<Item = (B, D)>,
+        B: Into<Bytes32>,
+        D: AsRef<[u8]>,
+    {
+        let tree = SparseMerkleTree::from_set(Storage::new(), set)
+            .expect("Expected `from_set` to have no storage errors");
+        Self { tree }
+    }
+
     pub fn update(&mut self, key: &Bytes32, data: &[u8]) {
         let _ = self.tree.update(key, data);
     }
@@ -40,6 +51,18 @@ impl MerkleTree {
     pub fn root(&self) -> Bytes32 {
         self.tree.root()
     }
+
+    pub fn prove(&self, key: &Bytes32) -> Proof {
+        self.tree
+            .prove(key)
+            .expect("Expected `prove` to have no storage errors")
+    }
+
+    pub fn prove_many(&self, keys: &[Bytes32]) -> MultiProof {
+        self.tree
+            .prove_many(keys)
+            .expect("Expected `prove_many` to have no storage errors")
+    }
 }
 
 impl Default for MerkleTree {
@@ -97,6 +120,19 @@ mod test {
         assert_eq!(hex::encode(root), expected_root);
     }
 
+    #[test]
+    fn test_from_set() {
+        let tree = MerkleTree::from_set([
+            (sum(b"\x00\x00\x00\x00"), b"DATA"),
+            (sum(b"\x00\x00\x00\x01"), b"DATA"),
+            (sum(b"\x00\x00\x00\x02"), b"DATA"),
+        ]);
+
+        let root = tree.root();
+        let expected_root = "52295e42d8de2505fdc0cc825ff9fead419cbcf540d8b30c7c4b9c9b94c268b7";
+        assert_eq!(hex::encode(root), expected_root);
+    }
+
     #[test]
     fn test_update_1_delete_1() {
         let mut tree = MerkleTree::new();
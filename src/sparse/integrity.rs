@@ -0,0 +1,390 @@
+use crate::{
+    common::{Bytes32, IntegrityIssue, IntegrityReport, Msb},
+    sparse::{zero_sum, Node, Primitive},
+    storage::{Mappable, StorageInspect},
+};
+
+use alloc::{vec, vec::Vec};
+
+/// Check the integrity of the sparse Merkle tree with the given root, with
+/// its leaves stored by key in the same table as its nodes. See
+/// [`check_with_leaves_table`].
+pub fn check<TableType, StorageType>(
+    storage: &StorageType,
+    root: &Bytes32,
+) -> Result<IntegrityReport<Bytes32>, StorageType::Error>
+where
+    TableType: Mappable<Key = Bytes32, SetValue = Primitive, GetValue = Primitive>,
+    StorageType: StorageInspect<TableType>,
+{
+    check_with_leaves_table::<TableType, TableType, _, _>(storage, root)
+}
+
+/// Check the integrity of the sparse Merkle tree with the given root, walking
+/// every node from the root. The check reports:
+///
+/// - nodes referenced by their parents but not found in storage;
+/// - nodes that cannot be decoded, or that do not hash to the key they are
+///   referenced by;
+/// - internal nodes not one level below their parents, and leaves not at
+///   height 0;
+/// - leaves on a side of an ancestor that does not match their leaf key;
+/// - leaves not stored by leaf key in `LeavesTableType`.
+///
+/// A storage error stops the check and is returned.
+pub fn check_with_leaves_table<TableType, LeavesTableType, StorageType, StorageError>(
+    storage: &StorageType,
+    root: &Bytes32,
+) -> Result<IntegrityReport<Bytes32>, StorageError>
+where
+    TableType: Mappable<Key = Bytes32, SetValue = Primitive, GetValue = Primitive>,
+    LeavesTableType: Mappable<Key = Bytes32, SetValue = Primitive, GetValue = Primitive>,
+    StorageType: StorageInspect<TableType, Error = StorageError>
+        + StorageInspect<LeavesTableType, Error = StorageError>,
+{
+    let mut report = IntegrityReport::default();
+    if root == zero_sum() {
+        return Ok(report);
+    }
+
+    // Each entry holds the key of a node, the height expected of the node if
+    // it is an internal node, and the path from the root to the node: the
+    // bits of the path so far, and the number of bits.
+    let mut stack: Vec<(Bytes32, u32, Bytes32, usize)> =
+        vec![(*root, Node::max_height() as u32, [0; 32], 0)];
+    while let Some((key, expected_height, path, depth)) = stack.pop() {
+        report.reach(&key);
+        let primitive = match StorageInspect::<TableType>::get(storage, &key)? {
+            Some(primitive) => primitive.into_owned(),
+            None => {
+                report.report(IntegrityIssue::MissingNode(key));
+                continue;
+            }
+        };
+        report.nodes_checked += 1;
+        let node = match Node::try_from(primitive) {
+            Ok(node) if !node.is_placeholder() => node,
+            _ => {
+                report.report(IntegrityIssue::MalformedNode(key));
+                continue;
+            }
+        };
+        if node.hash() != key {
+            report.report(IntegrityIssue::HashMismatch(key));
+        }
+
+        if node.is_leaf() {
+            report.leaves_checked += 1;
+            check_leaf::<LeavesTableType, _>(storage, &mut report, &node, &key, &path, depth)?;
+            continue;
+        }
+
+        if node.height() != expected_height {
+            report.report(IntegrityIssue::HeightMismatch {
+                key,
+                expected: expected_height,
+                found: node.height(),
+            });
+        }
+        if expected_height == 0 {
+            report.report(IntegrityIssue::MalformedNode(key));
+            continue;
+        }
+        let mut right_path = path;
+        right_path[depth / 8] |= 1 << (7 - depth % 8);
+        for (child_key, child_path) in [
+            (*node.right_child_key(), right_path),
+            (*node.left_child_key(), path),
+        ] {
+            if child_key != *zero_sum() {
+                stack.push((child_key, expected_height - 1, child_path, depth + 1));
+            }
+        }
+    }
+
+    Ok(report)
+}
+
+fn check_leaf<LeavesTableType, StorageType>(
+    storage: &StorageType,
+    report: &mut IntegrityReport<Bytes32>,
+    leaf: &Node,
+    key: &Bytes32,
+    path: &Bytes32,
+    depth: usize,
+) -> Result<(), StorageType::Error>
+where
+    LeavesTableType: Mappable<Key = Bytes32, SetValue = Primitive, GetValue = Primitive>,
+    StorageType: StorageInspect<LeavesTableType>,
+{
+    if leaf.height() != 0 {
+        report.report(IntegrityIssue::HeightMismatch {
+            key: *key,
+            expected: 0,
+            found: leaf.height(),
+        });
+    }
+    let leaf_key = leaf.leaf_key();
+    if leaf_key.common_prefix_count(path) < depth {
+        report.report(IntegrityIssue::MisplacedNode(*key));
+    }
+
+    report.reach(leaf_key);
+    let stored_leaf = storage
+        .get(leaf_key)?
+        .and_then(|primitive| Node::try_from(primitive.into_owned()).ok());
+    if stored_leaf.map(|stored_leaf| stored_leaf.hash()) != Some(leaf.hash()) {
+        report.report(IntegrityIssue::MissingLeafKey(*leaf_key));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        common::{Bytes32, IntegrityIssue, StorageMap},
+        sparse::{
+            self, check, check_with_leaves_table, hash::sum, zero_sum, MerkleTree, Node, Primitive,
+        },
+        storage::{Mappable, StorageInspect, StorageMutate},
+    };
+    use alloc::vec::Vec;
+
+    #[derive(Debug)]
+    struct TestTable;
+
+    impl Mappable for TestTable {
+        type Key = Bytes32;
+        type SetValue = Primitive;
+        type GetValue = Self::SetValue;
+    }
+
+    fn storage_with_leaves(n: u32) -> (StorageMap<TestTable>, Bytes32) {
+        let mut storage = StorageMap::<TestTable>::new();
+        let mut tree = MerkleTree::new(&mut storage);
+        for i in 0..n {
+            tree.update(&sum(i.to_be_bytes()), b"DATA").unwrap();
+        }
+        let root = tree.root();
+        (storage, root)
+    }
+
+    fn stored_keys(storage: &StorageMap<TestTable>, root: &Bytes32) -> Vec<Bytes32> {
+        // The nodes of the tree, and the leaves by key.
+        let mut keys = sparse::export::<TestTable, _>(storage, root, 16)
+            .flat_map(|chunk| chunk.unwrap())
+            .map(|primitive| Node::try_from(primitive).unwrap())
+            .flat_map(|node| {
+                let mut keys = Vec::from([node.hash()]);
+                if node.is_leaf() {
+                    keys.push(*node.leaf_key());
+                }
+                keys
+            })
+            .collect::<Vec<_>>();
+        keys.dedup();
+        keys
+    }
+
+    #[test]
+    fn test_check_of_a_valid_tree_reports_no_issues() {
+        let mut storage = StorageMap::<TestTable>::new();
+        let mut tree = MerkleTree::new(&mut storage);
+        for i in 0_u32..100 {
+            tree.update(&sum(i.to_be_bytes()), b"DATA").unwrap();
+        }
+        for i in (0_u32..100).step_by(3) {
+            tree.delete(&sum(i.to_be_bytes())).unwrap();
+        }
+        let root = tree.root();
+
+        let report = check::<TestTable, _>(&storage, &root).unwrap();
+        assert!(report.is_ok(), "{:?}", report.issues);
+        assert_eq!(report.leaves_checked, 66);
+        let nodes_count = sparse::export::<TestTable, _>(&storage, &root, 16)
+            .map(|chunk| chunk.unwrap().len() as u64)
+            .sum::<u64>();
+        assert_eq!(report.nodes_checked, nodes_count);
+    }
+
+    #[test]
+    fn test_check_of_a_tree_built_from_a_set_reports_no_issues() {
+        let mut storage = StorageMap::<TestTable>::new();
+        let set = (0_u32..50).map(|i| (sum(i.to_be_bytes()), b"DATA"));
+        let tree = MerkleTree::from_set(&mut storage, set).unwrap();
+        let root = tree.root();
+
+        let report = check::<TestTable, _>(&storage, &root).unwrap();
+        assert!(report.is_ok(), "{:?}", report.issues);
+        assert_eq!(report.leaves_checked, 50);
+    }
+
+    #[test]
+    fn test_check_of_the_empty_tree_reports_no_issues() {
+        let storage = StorageMap::<TestTable>::new();
+        let report = check::<TestTable, _>(&storage, zero_sum()).unwrap();
+        assert!(report.is_ok());
+        assert_eq!(report.nodes_checked, 0);
+    }
+
+    #[test]
+    fn test_check_reports_a_missing_node() {
+        let (mut storage, root) = storage_with_leaves(10);
+        let (_, _, left_child_key, _) = storage.get(&root).unwrap().unwrap().into_owned();
+        storage.remove(&left_child_key).unwrap();
+
+        let report = check::<TestTable, _>(&storage, &root).unwrap();
+        assert_eq!(report.issues, [IntegrityIssue::MissingNode(left_child_key)]);
+    }
+
+    #[test]
+    fn test_check_reports_a_node_that_does_not_hash_to_its_key() {
+        let (mut storage, root) = storage_with_leaves(10);
+        let (_, _, left_child_key, _) = storage.get(&root).unwrap().unwrap().into_owned();
+        let (height, prefix, lo, _) = storage.get(&left_child_key).unwrap().unwrap().into_owned();
+        storage
+            .insert(&left_child_key, &(height, prefix, lo, sum(b"CORRUPT")))
+            .unwrap();
+
+        let report = check::<TestTable, _>(&storage, &root).unwrap();
+        assert!(report
+            .issues
+            .contains(&IntegrityIssue::HashMismatch(left_child_key)));
+    }
+
+    #[test]
+    fn test_check_reports_a_node_with_a_wrong_height() {
+        let (mut storage, root) = storage_with_leaves(10);
+        let (height, prefix, lo, hi) = storage.get(&root).unwrap().unwrap().into_owned();
+        storage
+            .insert(&root, &(height - 1, prefix, lo, hi))
+            .unwrap();
+
+        let report = check::<TestTable, _>(&storage, &root).unwrap();
+        assert_eq!(
+            report.issues,
+            [IntegrityIssue::HeightMismatch {
+                key: root,
+                expected: height,
+                found: height - 1
+            }]
+        );
+    }
+
+    #[test]
+    fn test_check_reports_a_leaf_on_the_wrong_side_of_its_parent() {
+        // Two leaves with keys differing in the first bit are the children of
+        // the root.
+        let mut storage = StorageMap::<TestTable>::new();
+        let mut tree = MerkleTree::new(&mut storage);
+        let mut key = [0; 32];
+        tree.update(&key, b"DATA").unwrap();
+        key[0] = 0x80;
+        tree.update(&key, b"DATA").unwrap();
+        let root = tree.root();
+        let (height, prefix, lo, hi) = storage.get(&root).unwrap().unwrap().into_owned();
+        let swapped = Node::new(height, prefix.try_into().unwrap(), hi, lo);
+        storage
+            .insert(&swapped.hash(), &swapped.as_ref().into())
+            .unwrap();
+
+        let report = check::<TestTable, _>(&storage, &swapped.hash()).unwrap();
+        assert_eq!(
+            report.issues,
+            [
+                IntegrityIssue::MisplacedNode(hi),
+                IntegrityIssue::MisplacedNode(lo)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_check_reports_a_leaf_not_stored_by_key() {
+        let (mut storage, root) = storage_with_leaves(10);
+        let leaf_key = sum(0_u32.to_be_bytes());
+        storage.remove(&leaf_key).unwrap();
+
+        let report = check::<TestTable, _>(&storage, &root).unwrap();
+        assert_eq!(report.issues, [IntegrityIssue::MissingLeafKey(leaf_key)]);
+    }
+
+    #[test]
+    fn test_check_finds_unreachable_nodes() {
+        let (mut storage, root) = storage_with_leaves(10);
+        let mut keys = stored_keys(&storage, &root);
+
+        let orphan = Node::create_leaf(&sum(b"ORPHAN"), b"DATA");
+        storage
+            .insert(&orphan.hash(), &orphan.as_ref().into())
+            .unwrap();
+        keys.push(orphan.hash());
+
+        let mut report = check::<TestTable, _>(&storage, &root).unwrap();
+        report.find_unreachable(keys);
+        assert_eq!(report.issues, [IntegrityIssue::Unreachable(orphan.hash())]);
+    }
+
+    #[derive(Debug)]
+    struct LeavesTable;
+
+    impl Mappable for LeavesTable {
+        type Key = Bytes32;
+        type SetValue = Primitive;
+        type GetValue = Self::SetValue;
+    }
+
+    #[derive(Default)]
+    struct SplitStorage {
+        nodes: StorageMap<TestTable>,
+        leaves: StorageMap<LeavesTable>,
+    }
+
+    impl StorageInspect<TestTable> for SplitStorage {
+        type Error = core::convert::Infallible;
+
+        fn get(
+            &self,
+            key: &Bytes32,
+        ) -> Result<Option<alloc::borrow::Cow<'_, Primitive>>, Self::Error> {
+            self.nodes.get(key)
+        }
+
+        fn contains_key(&self, key: &Bytes32) -> Result<bool, Self::Error> {
+            self.nodes.contains_key(key)
+        }
+    }
+
+    impl StorageInspect<LeavesTable> for SplitStorage {
+        type Error = core::convert::Infallible;
+
+        fn get(
+            &self,
+            key: &Bytes32,
+        ) -> Result<Option<alloc::borrow::Cow<'_, Primitive>>, Self::Error> {
+            self.leaves.get(key)
+        }
+
+        fn contains_key(&self, key: &Bytes32) -> Result<bool, Self::Error> {
+            self.leaves.contains_key(key)
+        }
+    }
+
+    #[test]
+    fn test_check_with_leaves_table_reads_leaves_from_the_leaves_table() {
+        let mut storage = SplitStorage::default();
+        let mut tree = MerkleTree::new(&mut storage.nodes);
+        for i in 0_u32..10 {
+            tree.update(&sum(i.to_be_bytes()), b"DATA").unwrap();
+        }
+        let root = tree.root();
+        for i in 0_u32..10 {
+            let key = sum(i.to_be_bytes());
+            let leaf = storage.nodes.remove(&key).unwrap().unwrap();
+            storage.leaves.insert(&key, &leaf).unwrap();
+        }
+
+        let report =
+            check_with_leaves_table::<TestTable, LeavesTable, _, _>(&storage, &root).unwrap();
+        assert!(report.is_ok(), "{:?}", report.issues);
+    }
+}
@@ -0,0 +1,332 @@
+use crate::{
+    common::{Bit, Bytes32, Msb, Node as NodeTrait, ParentNode as ParentNodeTrait},
+    sparse::{proof::has_prefix, MerkleTreeError, Node, Primitive, StorageNode},
+    storage::{Mappable, ReadStrategy, SingleReads, StorageInspect},
+};
+
+use alloc::{vec, vec::Vec};
+use core::fmt;
+
+/// # Leaves Iterator
+///
+/// An iterator over the leaves of a sparse Merkle tree, yielding the key and
+/// the data hash of each leaf in ascending key order. The tree does not store
+/// the original leaf data, only its hash; the data hash is therefore the
+/// value yielded for each leaf.
+///
+/// The iterator performs a depth-first traversal of the tree, starting at the
+/// root and descending to the left child before the right child of each
+/// internal node. Because the path to a leaf is read from the bits of its key,
+/// starting with the most significant bit, this traversal visits the leaves in
+/// the order of their keys.
+///
+/// The iterator can optionally start at a given key, in which case only the
+/// leaves with keys greater than or equal to the start key are yielded. This
+/// allows the leaves to be retrieved in pages: the next page starts at the
+/// successor of the last key of the previous page. Subtrees holding only keys
+/// less than the start key are not traversed.
+///
+/// The iterator can also be limited to the leaves with keys beginning with a
+/// given prefix. The prefix determines both the start key, which is the prefix
+/// followed by zero bits, and the end of the iteration, which occurs at the
+/// first leaf with a key outside of the prefix.
+///
+/// The children of each internal node are read together, according to
+/// `Reads`.
+///
+/// If a node cannot be loaded from storage, the iterator yields the error and
+/// terminates.
+pub struct LeavesIter<'storage, TableType, StorageType, Reads = SingleReads> {
+    start: Option<Bytes32>,
+    // The number of leading bits of the start key that every yielded key must
+    // share.
+    prefix_len: usize,
+    // Each pending subtree is paired with a flag indicating whether the path
+    // to the subtree is equal to the path of the start key. Only these
+    // subtrees can contain keys less than the start key.
+    stack: Vec<(StorageNode<'storage, TableType, StorageType, Reads>, bool)>,
+}
+
+impl<'storage, TableType, StorageType, Reads> LeavesIter<'storage, TableType, StorageType, Reads> {
+    pub(crate) fn new(
+        storage: &'storage StorageType,
+        root_node: Node,
+        start: Option<Bytes32>,
+        prefix_len: usize,
+    ) -> Self {
+        debug_assert!(start.is_some() || prefix_len == 0);
+        let root = StorageNode::new(storage, root_node);
+        Self {
+            start,
+            prefix_len,
+            stack: vec![(root, start.is_some())],
+        }
+    }
+}
+
+impl<'storage, TableType, StorageType, Reads> Iterator
+    for LeavesIter<'storage, TableType, StorageType, Reads>
+where
+    TableType: Mappable<Key = Bytes32, SetValue = Primitive, GetValue = Primitive>,
+    StorageType: StorageInspect<TableType>,
+    Reads: ReadStrategy<TableType, StorageType>,
+    StorageType::Error: fmt::Debug + Clone,
+{
+    type Item = Result<(Bytes32, Bytes32), MerkleTreeError<StorageType::Error>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some((storage_node, is_bounded)) = self.stack.pop() {
+            if storage_node.is_leaf() {
+                let node = storage_node.into_node();
+                let is_before_start = matches!(self.start, Some(start) if *node.leaf_key() < start);
+                if node.is_placeholder() || is_before_start {
+                    continue;
+                }
+                let is_after_prefix = matches!(self.start, Some(start) if !has_prefix(node.leaf_key(), &start, self.prefix_len));
+                if is_after_prefix {
+                    // Leaves are visited in key order; no subsequent leaf can
+                    // have the prefix.
+                    self.stack.clear();
+                    return None;
+                }
+                return Some(Ok((*node.leaf_key(), *node.leaf_data())));
+            }
+
+            let children = match storage_node.children() {
+                (Ok(left_child), Ok(right_child)) => Ok((left_child, right_child)),
+                (Err(err), _) | (_, Err(err)) => Err(err),
+            };
+            let (left_child, right_child) = match children {
+                Ok(children) => children,
+                Err(err) => {
+                    self.stack.clear();
+                    return Some(Err(MerkleTreeError::ChildError(err)));
+                }
+            };
+
+            // The bit of the start key at this node's depth determines which
+            // of its children can hold keys greater than or equal to the start
+            // key. If the bit is 1, every key in the left subtree is less than
+            // the start key, and the left subtree is skipped.
+            let depth = Node::max_height() - storage_node.height() as usize;
+            let start_bit = self
+                .start
+                .filter(|_| is_bounded)
+                .and_then(|start| start.get_bit_at_index_from_msb(depth));
+            match start_bit {
+                Some(Bit::_1) => {
+                    self.stack.push((right_child, true));
+                }
+                Some(Bit::_0) => {
+                    self.stack.push((right_child, false));
+                    self.stack.push((left_child, true));
+                }
+                None => {
+                    self.stack.push((right_child, false));
+                    self.stack.push((left_child, false));
+                }
+            }
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::common::Msb;
+    use crate::{
+        common::{Bytes32, StorageMap},
+        sparse::{hash::sum, MerkleTree, MerkleTreeError, Primitive},
+    };
+    use alloc::vec::Vec;
+    use fuel_storage::{Mappable, StorageMutate};
+
+    #[derive(Debug)]
+    struct TestTable;
+
+    impl Mappable for TestTable {
+        type Key = Bytes32;
+        type SetValue = Primitive;
+        type GetValue = Self::SetValue;
+    }
+
+    fn sorted_keys(range: core::ops::Range<u32>) -> Vec<Bytes32> {
+        let mut keys = range.map(|i| sum(i.to_be_bytes())).collect::<Vec<_>>();
+        keys.sort();
+        keys
+    }
+
+    #[test]
+    fn test_leaves_returns_nothing_for_the_empty_tree() {
+        let mut storage = StorageMap::<TestTable>::new();
+        let tree = MerkleTree::new(&mut storage);
+
+        assert_eq!(tree.leaves().count(), 0);
+    }
+
+    #[test]
+    fn test_leaves_returns_the_root_leaf_for_a_tree_with_1_leaf() {
+        let mut storage = StorageMap::<TestTable>::new();
+        let mut tree = MerkleTree::new(&mut storage);
+        tree.update(&sum(b"\x00\x00\x00\x00"), b"DATA").unwrap();
+
+        let leaves = tree.leaves().collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(leaves, [(sum(b"\x00\x00\x00\x00"), sum(b"DATA"))]);
+    }
+
+    #[test]
+    fn test_leaves_returns_all_leaves_in_key_order() {
+        let mut storage = StorageMap::<TestTable>::new();
+        let mut tree = MerkleTree::new(&mut storage);
+        for i in 0_u32..100 {
+            tree.update(&sum(i.to_be_bytes()), &i.to_be_bytes())
+                .unwrap();
+        }
+        for i in 50_u32..60 {
+            tree.delete(&sum(i.to_be_bytes())).unwrap();
+        }
+
+        let leaves = tree.leaves().collect::<Result<Vec<_>, _>>().unwrap();
+        let mut expected_leaves = (0_u32..50)
+            .chain(60..100)
+            .map(|i| (sum(i.to_be_bytes()), sum(i.to_be_bytes())))
+            .collect::<Vec<_>>();
+        expected_leaves.sort();
+        assert_eq!(leaves, expected_leaves);
+    }
+
+    #[test]
+    fn test_leaves_from_returns_leaves_starting_at_the_given_key() {
+        let mut storage = StorageMap::<TestTable>::new();
+        let mut tree = MerkleTree::new(&mut storage);
+        for i in 0_u32..100 {
+            tree.update(&sum(i.to_be_bytes()), b"DATA").unwrap();
+        }
+
+        let keys = sorted_keys(0..100);
+        for start in [0, 1, 37, 99] {
+            let leaves = tree
+                .leaves_from(&keys[start])
+                .map(|leaf| leaf.unwrap().0)
+                .collect::<Vec<_>>();
+            assert_eq!(leaves, keys[start..]);
+        }
+    }
+
+    #[test]
+    fn test_leaves_from_a_key_not_in_the_tree_returns_the_following_leaves() {
+        let mut storage = StorageMap::<TestTable>::new();
+        let mut tree = MerkleTree::new(&mut storage);
+        for i in 0_u32..10 {
+            tree.update(&sum(i.to_be_bytes()), b"DATA").unwrap();
+        }
+
+        let keys = sorted_keys(0..10);
+        let mut start = keys[4];
+        start[31] = start[31].wrapping_add(1);
+        let leaves = tree
+            .leaves_from(&start)
+            .map(|leaf| leaf.unwrap().0)
+            .collect::<Vec<_>>();
+        assert_eq!(leaves, keys[5..]);
+
+        let leaves = tree.leaves_from(&[0xff; 32]).count();
+        assert_eq!(leaves, 0);
+    }
+
+    #[test]
+    fn test_leaves_pages_return_every_leaf_exactly_once() {
+        let mut storage = StorageMap::<TestTable>::new();
+        let mut tree = MerkleTree::new(&mut storage);
+        for i in 0_u32..50 {
+            tree.update(&sum(i.to_be_bytes()), b"DATA").unwrap();
+        }
+
+        let mut leaves = Vec::new();
+        let mut start = [0; 32];
+        loop {
+            let page = tree
+                .leaves_from(&start)
+                .take(7)
+                .map(|leaf| leaf.unwrap().0)
+                .collect::<Vec<_>>();
+            match page.last() {
+                // The next page starts at the successor of the last key.
+                Some(last) => start = *last,
+                None => break,
+            }
+            for byte in start.iter_mut().rev() {
+                let (next, overflow) = byte.overflowing_add(1);
+                *byte = next;
+                if !overflow {
+                    break;
+                }
+            }
+            leaves.extend(page);
+        }
+
+        assert_eq!(leaves, sorted_keys(0..50));
+    }
+
+    #[test]
+    fn test_leaves_returns_an_error_and_terminates_if_a_node_is_missing() {
+        let mut storage = StorageMap::<TestTable>::new();
+        let mut tree = MerkleTree::new(&mut storage);
+        for i in 0_u32..10 {
+            tree.update(&sum(i.to_be_bytes()), b"DATA").unwrap();
+        }
+        let root = tree.root();
+
+        // Remove the first non-placeholder child of the root from storage.
+        let (_, _, left_key, right_key) = storage.remove(&root).unwrap().unwrap();
+        storage
+            .insert(&root, &(256, 1, left_key, right_key))
+            .unwrap();
+        let child_key = if left_key == [0; 32] {
+            right_key
+        } else {
+            left_key
+        };
+        storage.remove(&child_key).unwrap();
+
+        let tree = MerkleTree::load(&mut storage, &root).unwrap();
+        let mut leaves = tree.leaves();
+        let err = leaves
+            .next()
+            .unwrap()
+            .expect_err("Expected next() to return Error; got Ok");
+        assert!(matches!(err, MerkleTreeError::ChildError(_)));
+        assert!(leaves.next().is_none());
+    }
+
+    #[test]
+    fn test_leaves_with_prefix_returns_only_the_leaves_with_the_prefix() {
+        let mut storage = StorageMap::<TestTable>::new();
+        let mut tree = MerkleTree::new(&mut storage);
+        for i in 0_u32..100 {
+            tree.update(&sum(i.to_be_bytes()), b"DATA").unwrap();
+        }
+
+        let keys = sorted_keys(0..100);
+        for prefix_len in [0, 1, 3, 8] {
+            let mut leaves = Vec::new();
+            for byte in 0..(1_u16 << prefix_len) {
+                // Place the prefix bits at the start of the key.
+                let mut prefix = [0; 32];
+                prefix[0] = ((byte << (8 - prefix_len)) & 0xff) as u8;
+
+                let page = tree
+                    .leaves_with_prefix(&prefix, prefix_len)
+                    .unwrap()
+                    .map(|leaf| leaf.unwrap().0)
+                    .collect::<Vec<_>>();
+                assert!(page
+                    .iter()
+                    .all(|key| key.common_prefix_count(&prefix) >= prefix_len));
+                leaves.extend(page);
+            }
+            assert_eq!(leaves, keys);
+        }
+    }
+}
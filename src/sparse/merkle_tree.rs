@@ -1,20 +1,44 @@
 use crate::{
-    common::{error::DeserializeError, AsPathIterator, Bytes32, ChildError},
-    sparse::{primitive::Primitive, zero_sum, Node, StorageNode, StorageNodeError},
-    storage::{Mappable, StorageMutate},
+    common::{
+        error::DeserializeError, AsPathIterator, Bit, Bytes32, ChildError, Msb, Node as NodeTrait,
+        ParentNode as ParentNodeTrait,
+    },
+    sparse::{
+        checkpoint::CheckpointStorage,
+        primitive::Primitive,
+        proof::{has_prefix, MultiProof, Proof},
+        witness::Recorder,
+        zero_sum, LeavesIter, Node, StorageNode, StorageNodeError, SubtreeProof, Witness,
+    },
+    storage::{
+        BatchReads, Mappable, ReadStrategy, SingleReads, StorageInspect, StorageInspectBatch,
+        StorageMutate,
+    },
 };
 
-use alloc::{string::String, vec::Vec};
-use core::{cmp, fmt, iter, marker::PhantomData};
+use alloc::vec::Vec;
+use core::{cmp, convert::Infallible, fmt, iter, marker::PhantomData};
 
 #[derive(Debug, Clone)]
 #[cfg_attr(feature = "std", derive(thiserror::Error))]
 pub enum MerkleTreeError<StorageError> {
     #[cfg_attr(
         feature = "std",
-        error("cannot load node with key {0}; the key is not found in storage")
+        error("cannot load node with key {}; the key is not found in storage", hex::encode(.0))
     )]
-    LoadError(String),
+    LoadError(Bytes32),
+
+    #[cfg_attr(
+        feature = "std",
+        error("the prefix length {0} exceeds the height of the tree")
+    )]
+    InvalidPrefixLength(usize),
+
+    #[cfg_attr(
+        feature = "std",
+        error("the node stored with root {} does not hash to the root", hex::encode(.0))
+    )]
+    CorruptRootError(Bytes32),
 
     #[cfg_attr(feature = "std", error(transparent))]
     StorageError(StorageError),
@@ -32,23 +56,42 @@ impl<StorageError> From<StorageError> for MerkleTreeError<StorageError> {
     }
 }
 
+/// # Sparse Merkle Tree
+///
+/// The tree stores its nodes in `TableType`, keyed by hash, and its leaves in
+/// `LeavesTableType`, keyed by leaf key. Leaves are also stored in
+/// `TableType` under their hashes, as the children of internal nodes. By
+/// default both are stored in the same table; a separate leaves table keeps
+/// the lookup of leaves by key apart from the traversal of the tree.
+///
+/// Walking a path reads both children of each node together, according to
+/// `Reads`. By default, the children are read one at a time; for a storage
+/// implementing [`StorageInspectBatch`], [`with_batch_reads`](Self::with_batch_reads)
+/// reads them in a single batch.
 #[derive(Debug)]
-pub struct MerkleTree<TableType, StorageType> {
+pub struct MerkleTree<TableType, StorageType, LeavesTableType = TableType, Reads = SingleReads> {
     root_node: Node,
-    storage: StorageType,
-    phantom_table: PhantomData<TableType>,
+    storage: CheckpointStorage<TableType, StorageType, LeavesTableType>,
+    checkpoints: Vec<Node>,
+    recorder: Option<Recorder>,
+    phantom_table: PhantomData<(TableType, Reads)>,
 }
 
-impl<TableType, StorageType, StorageError> MerkleTree<TableType, StorageType>
+impl<TableType, StorageType, LeavesTableType, StorageError>
+    MerkleTree<TableType, StorageType, LeavesTableType>
 where
     TableType: Mappable<Key = Bytes32, SetValue = Primitive, GetValue = Primitive>,
-    StorageType: StorageMutate<TableType, Error = StorageError>,
+    LeavesTableType: Mappable<Key = Bytes32, SetValue = Primitive, GetValue = Primitive>,
+    StorageType: StorageInspect<TableType, Error = StorageError>
+        + StorageInspect<LeavesTableType, Error = StorageError>,
     StorageError: fmt::Debug + Clone + 'static,
 {
     pub fn new(storage: StorageType) -> Self {
         Self {
             root_node: Node::create_placeholder(),
-            storage,
+            storage: CheckpointStorage::new(storage),
+            checkpoints: Vec::new(),
+            recorder: None,
             phantom_table: Default::default(),
         }
     }
@@ -57,20 +100,325 @@ where
         storage: StorageType,
         root: &Bytes32,
     ) -> Result<Self, MerkleTreeError<StorageError>> {
-        let primitive = storage
-            .get(root)?
-            .ok_or_else(|| MerkleTreeError::LoadError(hex::encode(root)))?
-            .into_owned();
         let tree = Self {
-            root_node: primitive
-                .try_into()
-                .map_err(MerkleTreeError::DeserializeError)?,
-            storage,
+            root_node: load_root_node::<TableType, _>(&storage, root)?,
+            storage: CheckpointStorage::new(storage),
+            checkpoints: Vec::new(),
+            recorder: None,
             phantom_table: Default::default(),
         };
         Ok(tree)
     }
+}
+
+impl<TableType, StorageType, LeavesTableType> MerkleTree<TableType, StorageType, LeavesTableType>
+where
+    TableType: Mappable<Key = Bytes32, SetValue = Primitive, GetValue = Primitive>,
+    StorageType: StorageInspectBatch<TableType>,
+{
+    /// Read the children of each node on a path with
+    /// [`StorageInspectBatch::get_batch`], instead of one at a time.
+    pub fn with_batch_reads(
+        self,
+    ) -> MerkleTree<TableType, StorageType, LeavesTableType, BatchReads> {
+        MerkleTree {
+            root_node: self.root_node,
+            storage: self.storage,
+            checkpoints: self.checkpoints,
+            recorder: self.recorder,
+            phantom_table: Default::default(),
+        }
+    }
+}
+
+impl<TableType, StorageType, LeavesTableType, Reads, StorageError>
+    MerkleTree<TableType, StorageType, LeavesTableType, Reads>
+where
+    TableType: Mappable<Key = Bytes32, SetValue = Primitive, GetValue = Primitive>,
+    LeavesTableType: Mappable<Key = Bytes32, SetValue = Primitive, GetValue = Primitive>,
+    StorageType: StorageInspect<TableType, Error = StorageError>
+        + StorageInspect<LeavesTableType, Error = StorageError>,
+    StorageError: fmt::Debug + Clone + 'static,
+    Reads: ReadStrategy<TableType, CheckpointStorage<TableType, StorageType, LeavesTableType>>,
+{
+    pub fn root(&self) -> Bytes32 {
+        self.root_node().hash()
+    }
+
+    /// Get the data hash of the leaf with the given key, or `None` if the key
+    /// is not in the tree.
+    pub fn get(&self, key: &Bytes32) -> Result<Option<Bytes32>, MerkleTreeError<StorageError>> {
+        let (node, _) = self.prefix_path(key, Node::max_height())?;
+        let data = if !node.is_placeholder() && node.leaf_key() == key {
+            Some(*node.leaf_data())
+        } else {
+            None
+        };
+        Ok(data)
+    }
+
+    /// Iterate over the leaves of the tree in ascending key order, yielding
+    /// the key and data hash of each leaf. See [`LeavesIter`].
+    pub fn leaves(
+        &self,
+    ) -> LeavesIter<'_, TableType, CheckpointStorage<TableType, StorageType, LeavesTableType>, Reads>
+    {
+        LeavesIter::new(&self.storage, self.root_node().clone(), None, 0)
+    }
+
+    /// Iterate over the leaves of the tree in ascending key order, starting at
+    /// the leaf with the given key or, if the key is not in the tree, the leaf
+    /// with the next greater key. See [`LeavesIter`].
+    pub fn leaves_from(
+        &self,
+        key: &Bytes32,
+    ) -> LeavesIter<'_, TableType, CheckpointStorage<TableType, StorageType, LeavesTableType>, Reads>
+    {
+        LeavesIter::new(&self.storage, self.root_node().clone(), Some(*key), 0)
+    }
+
+    /// Iterate over the leaves of the tree with keys beginning with the first
+    /// `prefix_len` bits of `prefix`, in ascending key order. See
+    /// [`LeavesIter`].
+    #[allow(clippy::type_complexity)]
+    pub fn leaves_with_prefix(
+        &self,
+        prefix: &Bytes32,
+        prefix_len: usize,
+    ) -> Result<
+        LeavesIter<
+            '_,
+            TableType,
+            CheckpointStorage<TableType, StorageType, LeavesTableType>,
+            Reads,
+        >,
+        MerkleTreeError<StorageError>,
+    > {
+        check_prefix_len(prefix_len)?;
+        let start = truncate_prefix(prefix, prefix_len);
+        let leaves = LeavesIter::new(
+            &self.storage,
+            self.root_node().clone(),
+            Some(start),
+            prefix_len,
+        );
+        Ok(leaves)
+    }
+
+    /// Get the root of the subtree holding every leaf with a key beginning
+    /// with the first `prefix_len` bits of `prefix`. The subtree root of an
+    /// empty prefix is the root of the tree. See [`SubtreeProof`].
+    pub fn subtree_root(
+        &self,
+        prefix: &Bytes32,
+        prefix_len: usize,
+    ) -> Result<Bytes32, MerkleTreeError<StorageError>> {
+        let (node, _) = self.prefix_path(prefix, prefix_len)?;
+        let subtree_root = if node.is_leaf() && !has_prefix(node.leaf_key(), prefix, prefix_len) {
+            // The path ends at a leaf outside of the subtree; the subtree is
+            // empty.
+            *zero_sum()
+        } else {
+            node.hash()
+        };
+        Ok(subtree_root)
+    }
+
+    /// Generate a proof that ties the root of the subtree with the given
+    /// prefix to the root of the tree. See [`SubtreeProof`].
+    pub fn subtree_proof(
+        &self,
+        prefix: &Bytes32,
+        prefix_len: usize,
+    ) -> Result<SubtreeProof, MerkleTreeError<StorageError>> {
+        let (node, side_nodes) = self.prefix_path(prefix, prefix_len)?;
+        let leaf = if node.is_leaf() && !node.is_placeholder() {
+            Some(node.as_ref().into())
+        } else {
+            None
+        };
+        let side_nodes = side_nodes.iter().map(Node::hash).collect();
+        let proof = SubtreeProof::new(
+            truncate_prefix(prefix, prefix_len),
+            prefix_len,
+            leaf,
+            side_nodes,
+        );
+        Ok(proof)
+    }
+
+    /// Generate a proof of the given key, proving either its inclusion in or
+    /// its exclusion from the tree. See [`Proof`].
+    pub fn prove(&self, key: &Bytes32) -> Result<Proof, MerkleTreeError<StorageError>> {
+        let (node, side_nodes) = self.prefix_path(key, Node::max_height())?;
+        let leaf = if node.is_placeholder() {
+            None
+        } else {
+            Some(node.as_ref().into())
+        };
+        let sibling = side_nodes
+            .first()
+            .filter(|sibling| !sibling.is_placeholder())
+            .map(|sibling| sibling.as_ref().into());
+        let side_nodes = side_nodes.iter().map(Node::hash).collect();
+        Ok(Proof::new(leaf, sibling, side_nodes))
+    }
+
+    /// Generate a single proof of the given keys, proving the inclusion or
+    /// exclusion of each key, with the side nodes shared by their paths
+    /// included once. See [`MultiProof`].
+    pub fn prove_many(
+        &self,
+        keys: &[Bytes32],
+    ) -> Result<MultiProof, MerkleTreeError<StorageError>> {
+        let mut keys = keys.to_vec();
+        keys.sort();
+        keys.dedup();
+
+        let mut terminals = Vec::new();
+        let mut side_nodes = Vec::new();
+        if !keys.is_empty() {
+            let root = StorageNode::<_, _, Reads>::new(&self.storage, self.root_node().clone());
+            Self::collect_multiproof(root, &keys, 0, &mut terminals, &mut side_nodes)?;
+        }
+        Ok(MultiProof::new(terminals, side_nodes))
+    }
+
+    // PRIVATE
+
+    fn root_node(&self) -> &Node {
+        &self.root_node
+    }
+
+    /// Descend from `node`, found at `depth` on the paths of the given sorted
+    /// keys, collecting the terminals and side nodes of a [`MultiProof`].
+    fn collect_multiproof(
+        node: StorageNode<
+            '_,
+            TableType,
+            CheckpointStorage<TableType, StorageType, LeavesTableType>,
+            Reads,
+        >,
+        keys: &[Bytes32],
+        depth: usize,
+        terminals: &mut Vec<(usize, Option<Primitive>)>,
+        side_nodes: &mut Vec<Bytes32>,
+    ) -> Result<(), MerkleTreeError<StorageError>> {
+        if node.is_leaf() {
+            let node = node.into_node();
+            let leaf = if node.is_placeholder() {
+                None
+            } else {
+                Some(node.as_ref().into())
+            };
+            terminals.push((depth, leaf));
+            return Ok(());
+        }
+
+        let split =
+            keys.partition_point(|key| key.get_bit_at_index_from_msb(depth) == Some(Bit::_0));
+        let (left_keys, right_keys) = keys.split_at(split);
+        if !left_keys.is_empty() {
+            if right_keys.is_empty() {
+                side_nodes.push(*node.right_child_key());
+            }
+            let left_child = node.left_child().map_err(MerkleTreeError::ChildError)?;
+            Self::collect_multiproof(left_child, left_keys, depth + 1, terminals, side_nodes)?;
+        }
+        if !right_keys.is_empty() {
+            if left_keys.is_empty() {
+                side_nodes.push(*node.left_child_key());
+            }
+            let right_child = node.right_child().map_err(MerkleTreeError::ChildError)?;
+            Self::collect_multiproof(right_child, right_keys, depth + 1, terminals, side_nodes)?;
+        }
+        Ok(())
+    }
+
+    /// Descend from the root according to the first `prefix_len` bits of
+    /// `prefix`. Returns the node at which the descent ends, and the side
+    /// nodes along the path, ordered from the node to the root. The descent
+    /// ends at the depth of the prefix, or earlier if it reaches a leaf or a
+    /// placeholder.
+    fn prefix_path(
+        &self,
+        prefix: &Bytes32,
+        prefix_len: usize,
+    ) -> Result<(Node, Vec<Node>), MerkleTreeError<StorageError>> {
+        check_prefix_len(prefix_len)?;
+
+        let mut current = StorageNode::<_, _, Reads>::new(&self.storage, self.root_node().clone());
+        let mut side_nodes = Vec::new();
+        for depth in 0..prefix_len {
+            if current.is_leaf() {
+                break;
+            }
+            let (left_child, right_child) = current.children();
+            let (path_node, side_node) = match prefix.get_bit_at_index_from_msb(depth) {
+                Some(Bit::_0) => (left_child, right_child),
+                _ => (right_child, left_child),
+            };
+            side_nodes.push(side_node.map_err(MerkleTreeError::ChildError)?.into_node());
+            current = path_node.map_err(MerkleTreeError::ChildError)?;
+        }
+        side_nodes.reverse();
+
+        Ok((current.into_node(), side_nodes))
+    }
+}
+
+impl<TableType, StorageType, LeavesTableType, StorageError>
+    MerkleTree<TableType, StorageType, LeavesTableType>
+where
+    TableType: Mappable<Key = Bytes32, SetValue = Primitive, GetValue = Primitive>,
+    LeavesTableType: Mappable<Key = Bytes32, SetValue = Primitive, GetValue = Primitive>,
+    StorageType: StorageMutate<TableType, Error = StorageError>
+        + StorageMutate<LeavesTableType, Error = StorageError>,
+    StorageError: fmt::Debug + Clone + 'static,
+{
+    /// Build a sparse Merkle tree from a set of key-value pairs. The resulting
+    /// tree is identical to the tree produced by calling
+    /// [`update`](Self::update) for each pair in turn: later pairs overwrite
+    /// earlier pairs with the same key, and pairs with empty data are treated
+    /// as deletions. Unlike sequential updates, the tree is built bottom-up in
+    /// a single pass over the sorted keys, and each node is written to storage
+    /// exactly once.
+    pub fn from_set<B, I, D>(
+        mut storage: StorageType,
+        set: I,
+    ) -> Result<Self, MerkleTreeError<StorageError>>
+    where
+        I: IntoIterator<Item = (B, D)>,
+        B: Into<Bytes32>,
+        D: AsRef<[u8]>,
+    {
+        let leaves = sorted_leaves(set);
+        for leaf in leaves.iter() {
+            StorageMutate::<TableType>::insert(&mut storage, &leaf.hash(), &leaf.into())?;
+            StorageMutate::<LeavesTableType>::insert(&mut storage, leaf.leaf_key(), &leaf.into())?;
+        }
+
+        let root_node = build_subtree(&leaves, 0, &mut |node| {
+            StorageMutate::<TableType>::insert(&mut storage, &node.hash(), &node.into())?;
+            Ok::<_, StorageError>(())
+        })?;
+
+        let mut tree = Self::new(storage);
+        tree.set_root_node(root_node);
+        Ok(tree)
+    }
+}
 
+impl<TableType, StorageType, LeavesTableType, Reads, StorageError>
+    MerkleTree<TableType, StorageType, LeavesTableType, Reads>
+where
+    TableType: Mappable<Key = Bytes32, SetValue = Primitive, GetValue = Primitive>,
+    LeavesTableType: Mappable<Key = Bytes32, SetValue = Primitive, GetValue = Primitive>,
+    StorageType: StorageMutate<TableType, Error = StorageError>
+        + StorageMutate<LeavesTableType, Error = StorageError>,
+    StorageError: fmt::Debug + Clone + 'static,
+    Reads: ReadStrategy<TableType, CheckpointStorage<TableType, StorageType, LeavesTableType>>,
+{
     pub fn update(
         &mut self,
         key: &Bytes32,
@@ -84,10 +432,9 @@ where
         }
 
         let leaf_node = Node::create_leaf(key, data);
+        self.insert_node(&leaf_node)?;
         self.storage
-            .insert(&leaf_node.hash(), &leaf_node.as_ref().into())?;
-        self.storage
-            .insert(leaf_node.leaf_key(), &leaf_node.as_ref().into())?;
+            .insert_leaf(leaf_node.leaf_key(), &leaf_node.as_ref().into())?;
 
         if self.root_node().is_placeholder() {
             self.set_root_node(leaf_node);
@@ -106,27 +453,108 @@ where
             return Ok(());
         }
 
-        if let Some(primitive) = self.storage.get(key)? {
-            let primitive = primitive.into_owned();
+        if let Some(primitive) = self.storage.get_leaf(key)? {
             let leaf_node: Node = primitive
                 .try_into()
                 .map_err(MerkleTreeError::DeserializeError)?;
             let (path_nodes, side_nodes): (Vec<Node>, Vec<Node>) =
                 self.path_set(leaf_node.clone())?;
-            self.delete_with_path_set(&leaf_node, path_nodes.as_slice(), side_nodes.as_slice())?;
+            // The leaf stored under the key may be stale if the key was
+            // already deleted; the key is in the tree only if its path ends at
+            // a leaf with the key.
+            if path_nodes[0].leaf_key() == key {
+                self.delete_with_path_set(
+                    &leaf_node,
+                    path_nodes.as_slice(),
+                    side_nodes.as_slice(),
+                )?;
+            } else {
+                self.storage.remove_leaf(key)?;
+            }
         }
 
         Ok(())
     }
 
-    pub fn root(&self) -> Bytes32 {
-        self.root_node().hash()
+    /// Open a checkpoint. Until the checkpoint is committed or reverted, the
+    /// nodes written by the tree are buffered in an overlay instead of being
+    /// written to storage, and reads see the buffered nodes. Checkpoints can
+    /// be nested; each is committed or reverted independently of the
+    /// checkpoints enclosing it. See [`CheckpointStorage`].
+    pub fn checkpoint(&mut self) {
+        self.checkpoints.push(self.root_node().clone());
+        self.storage.push_overlay();
+    }
+
+    /// Commit the most recent checkpoint. If the checkpoint is nested, its
+    /// buffered nodes are merged into the overlay of the enclosing checkpoint,
+    /// and are reverted if the enclosing checkpoint is reverted. Otherwise,
+    /// the buffered nodes are written to storage.
+    ///
+    /// If writing to storage fails, the checkpoint remains open with the
+    /// nodes not yet written, and the commit can be retried. Reverting the
+    /// checkpoint instead restores its root, but leaves the nodes already
+    /// written in storage.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there is no open checkpoint.
+    pub fn commit(&mut self) -> Result<(), MerkleTreeError<StorageError>> {
+        assert!(!self.checkpoints.is_empty(), "no open checkpoint to commit");
+        self.storage.commit_overlay()?;
+        self.checkpoints.pop();
+        Ok(())
+    }
+
+    /// Revert the most recent checkpoint, discarding the nodes buffered since
+    /// it was opened and restoring the root at that time. Storage is left
+    /// exactly as it was when the checkpoint was opened.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there is no open checkpoint.
+    pub fn revert(&mut self) {
+        let root_node = self
+            .checkpoints
+            .pop()
+            .expect("no open checkpoint to revert");
+        self.storage.discard_overlay();
+        self.root_node = root_node;
+    }
+
+    /// Start recording the nodes read by [`update`](Self::update) and
+    /// [`delete`](Self::delete). The recorded nodes form a [`Witness`] of the
+    /// transition from the current root to the root at the time recording
+    /// stops. A recording already in progress is discarded.
+    pub fn start_recording(&mut self) {
+        self.recorder = Some(Recorder::default());
+    }
+
+    /// Stop recording and return the witness of the operations applied since
+    /// recording started, or `None` if the tree is not recording.
+    pub fn stop_recording(&mut self) -> Option<Witness> {
+        self.recorder.take().map(Recorder::into_witness)
+    }
+
+    /// Store the given leaf under its key. Used when the tree is replayed from
+    /// a witness, which holds nodes under their hashes only.
+    pub(crate) fn insert_leaf_key(
+        &mut self,
+        key: &Bytes32,
+        leaf: &Primitive,
+    ) -> Result<(), MerkleTreeError<StorageError>> {
+        self.storage.insert_leaf(key, leaf)?;
+        Ok(())
     }
 
     // PRIVATE
 
-    fn root_node(&self) -> &Node {
-        &self.root_node
+    fn insert_node(&mut self, node: &Node) -> Result<(), StorageError> {
+        self.storage.insert(&node.hash(), &node.as_ref().into())?;
+        if let Some(recorder) = self.recorder.as_mut() {
+            recorder.record_write(node);
+        }
+        Ok(())
     }
 
     fn set_root_node(&mut self, node: Node) {
@@ -135,12 +563,12 @@ where
     }
 
     fn path_set(
-        &self,
+        &mut self,
         leaf_node: Node,
     ) -> Result<(Vec<Node>, Vec<Node>), MerkleTreeError<StorageError>> {
         let root_node = self.root_node().clone();
-        let root_storage_node = StorageNode::new(&self.storage, root_node);
-        let leaf_storage_node = StorageNode::new(&self.storage, leaf_node);
+        let root_storage_node = StorageNode::<_, _, Reads>::new(&self.storage, root_node);
+        let leaf_storage_node = StorageNode::<_, _, Reads>::new(&self.storage, leaf_node);
         let (mut path_nodes, mut side_nodes): (Vec<Node>, Vec<Node>) = root_storage_node
             .as_path_iter(&leaf_storage_node)
             .map(|(path_node, side_node)| {
@@ -152,6 +580,12 @@ where
             .collect::<Result<Vec<_>, MerkleTreeError<StorageError>>>()?
             .into_iter()
             .unzip();
+        if let Some(recorder) = self.recorder.as_mut() {
+            recorder.record_read(&self.root_node);
+            for node in path_nodes.iter().chain(side_nodes.iter()) {
+                recorder.record_read(node);
+            }
+        }
         path_nodes.reverse();
         side_nodes.reverse();
         side_nodes.pop(); // The last element in the side nodes list is the
@@ -196,8 +630,7 @@ where
             // Merge leaves
             if !actual_leaf_node.is_placeholder() {
                 current_node = Node::create_node_on_path(path, &current_node, actual_leaf_node);
-                self.storage
-                    .insert(&current_node.hash(), &current_node.as_ref().into())?;
+                self.insert_node(&current_node)?;
             }
 
             // Merge placeholders
@@ -207,16 +640,14 @@ where
             let placeholders = iter::repeat(Node::create_placeholder()).take(placeholders_count);
             for placeholder in placeholders {
                 current_node = Node::create_node_on_path(path, &current_node, &placeholder);
-                self.storage
-                    .insert(&current_node.hash(), &current_node.as_ref().into())?;
+                self.insert_node(&current_node)?;
             }
         }
 
         // Merge side nodes
         for side_node in side_nodes {
             current_node = Node::create_node_on_path(path, &current_node, side_node);
-            self.storage
-                .insert(&current_node.hash(), &current_node.as_ref().into())?;
+            self.insert_node(&current_node)?;
         }
 
         self.set_root_node(current_node);
@@ -230,10 +661,6 @@ where
         path_nodes: &[Node],
         side_nodes: &[Node],
     ) -> Result<(), StorageError> {
-        for node in path_nodes {
-            self.storage.remove(&node.hash())?;
-        }
-
         let path = requested_leaf_node.leaf_key();
         let mut side_nodes_iter = side_nodes.iter();
 
@@ -271,8 +698,7 @@ where
                     side_nodes_iter.find(|side_node| !side_node.is_placeholder())
                 {
                     current_node = Node::create_node_on_path(path, &current_node, side_node);
-                    self.storage
-                        .insert(&current_node.hash(), &current_node.as_ref().into())?;
+                    self.insert_node(&current_node)?;
                 }
             }
         }
@@ -280,23 +706,162 @@ where
         // Merge side nodes
         for side_node in side_nodes_iter {
             current_node = Node::create_node_on_path(path, &current_node, side_node);
-            self.storage
-                .insert(&current_node.hash(), &current_node.as_ref().into())?;
+            self.insert_node(&current_node)?;
         }
 
+        // The leaf is removed from the leaves before the new root is set, so
+        // that a storage error leaves the tree unchanged.
+        self.storage.remove_leaf(path)?;
         self.set_root_node(current_node);
 
+        // Once the new root is set, the delete is complete, and the nodes on
+        // the path of the deleted leaf are no longer reachable. Removing them
+        // is best-effort: a node that cannot be removed is left behind in
+        // storage, unreachable, instead of failing the delete.
+        for node in path_nodes {
+            let _ = self.storage.remove(&node.hash());
+        }
+
         Ok(())
     }
 }
 
+/// Load the root node of the tree with the given root. The zero root is the
+/// root of the empty tree, represented by a placeholder that is not stored.
+pub(crate) fn load_root_node<TableType, StorageType>(
+    storage: &StorageType,
+    root: &Bytes32,
+) -> Result<Node, MerkleTreeError<StorageType::Error>>
+where
+    TableType: Mappable<Key = Bytes32, SetValue = Primitive, GetValue = Primitive>,
+    StorageType: StorageInspect<TableType>,
+{
+    if root == zero_sum() {
+        return Ok(Node::create_placeholder());
+    }
+    let node: Node = storage
+        .get(root)?
+        .ok_or(MerkleTreeError::LoadError(*root))?
+        .into_owned()
+        .try_into()
+        .map_err(MerkleTreeError::DeserializeError)?;
+    if node.hash() != *root {
+        return Err(MerkleTreeError::CorruptRootError(*root));
+    }
+    Ok(node)
+}
+
+/// Returns an error if a prefix of `prefix_len` bits is longer than a key.
+fn check_prefix_len<StorageError>(prefix_len: usize) -> Result<(), MerkleTreeError<StorageError>> {
+    if prefix_len > Node::max_height() {
+        return Err(MerkleTreeError::InvalidPrefixLength(prefix_len));
+    }
+    Ok(())
+}
+
+/// Returns the first `prefix_len` bits of `prefix`, followed by zero bits.
+/// The prefix length must not exceed the height of the tree.
+fn truncate_prefix(prefix: &Bytes32, prefix_len: usize) -> Bytes32 {
+    let mut truncated = [0; 32];
+    let whole_bytes = prefix_len / 8;
+    truncated[..whole_bytes].copy_from_slice(&prefix[..whole_bytes]);
+    let remaining_bits = prefix_len % 8;
+    if remaining_bits > 0 {
+        truncated[whole_bytes] = prefix[whole_bytes] & (0xff << (8 - remaining_bits));
+    }
+    truncated
+}
+
+/// Calculate the root of the sparse Merkle tree formed by a set of key-value
+/// pairs, without writing any nodes to storage. The set is interpreted in the
+/// same way as by [`MerkleTree::from_set`].
+pub fn root_from_set<B, I, D>(set: I) -> Bytes32
+where
+    I: IntoIterator<Item = (B, D)>,
+    B: Into<Bytes32>,
+    D: AsRef<[u8]>,
+{
+    let leaves = sorted_leaves(set);
+    let root_node = build_subtree(&leaves, 0, &mut |_| Ok::<_, Infallible>(()))
+        .expect("Expected build_subtree() to be infallible");
+    root_node.hash()
+}
+
+/// Create the leaves for a set of key-value pairs, sorted by leaf key. When a
+/// key appears more than once, only its last value is kept; keys whose last
+/// value is empty are omitted.
+fn sorted_leaves<B, I, D>(set: I) -> Vec<Node>
+where
+    I: IntoIterator<Item = (B, D)>,
+    B: Into<Bytes32>,
+    D: AsRef<[u8]>,
+{
+    let mut entries = set
+        .into_iter()
+        .map(|(key, data)| (key.into(), data))
+        .collect::<Vec<(Bytes32, D)>>();
+
+    // A stable sort preserves the insertion order of duplicate keys, so the
+    // last entry of each run of equal keys is the most recent value.
+    entries.sort_by_key(|(key, _)| *key);
+
+    let mut leaves = Vec::with_capacity(entries.len());
+    let mut entries = entries.into_iter().peekable();
+    while let Some((key, data)) = entries.next() {
+        let is_overwritten = matches!(entries.peek(), Some((next_key, _)) if *next_key == key);
+        if !is_overwritten && !data.as_ref().is_empty() {
+            leaves.push(Node::create_leaf(&key, data.as_ref()));
+        }
+    }
+    leaves
+}
+
+/// Build the subtree at the given depth containing the given leaves, which
+/// must be sorted by leaf key and share a common path of at least `depth`
+/// bits. Every internal node created is passed to `store`.
+///
+/// A subtree with no leaves is a placeholder, and a subtree with a single leaf
+/// is represented by the leaf itself. Otherwise, the leaves are split on the
+/// bit at `depth` into the left and right subtrees, which are joined by a new
+/// internal node.
+fn build_subtree<F, E>(leaves: &[Node], depth: usize, store: &mut F) -> Result<Node, E>
+where
+    F: FnMut(&Node) -> Result<(), E>,
+{
+    match leaves {
+        [] => Ok(Node::create_placeholder()),
+        [leaf] => Ok(leaf.clone()),
+        _ => {
+            let split = leaves.partition_point(|leaf| {
+                leaf.leaf_key().get_bit_at_index_from_msb(depth) == Some(Bit::_0)
+            });
+            let left_child = build_subtree(&leaves[..split], depth + 1, store)?;
+            let right_child = build_subtree(&leaves[split..], depth + 1, store)?;
+            let height = (Node::max_height() - depth) as u32;
+            let node = Node::create_node(&left_child, &right_child, height);
+            store(&node)?;
+            Ok(node)
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
+    use crate::storage::{
+        GetBatchResult, Mappable, StorageInspect, StorageInspectBatch, StorageMutate,
+    };
     use crate::{
-        common::{Bytes32, StorageMap},
-        sparse::{hash::sum, MerkleTree, MerkleTreeError, Primitive},
+        common::{
+            faulty_storage::{FaultError, FaultyStorage},
+            Bytes32, ChildError, StorageMap,
+        },
+        sparse::{
+            hash::sum, root_from_set, zero_sum, MerkleTree, MerkleTreeError, Node, Primitive,
+            StorageNodeError,
+        },
     };
-    use fuel_storage::Mappable;
+    use alloc::{borrow::Cow, vec::Vec};
+    use core::{cell::Cell, convert::Infallible};
     use hex;
 
     #[derive(Debug)]
@@ -558,6 +1123,21 @@ mod test {
         assert_eq!(hex::encode(root), expected_root);
     }
 
+    #[test]
+    fn test_delete_deleted_key() {
+        let mut storage = StorageMap::<TestTable>::new();
+        let mut tree = MerkleTree::new(&mut storage);
+
+        tree.update(&sum(b"\x00\x00\x00\x00"), b"DATA").unwrap();
+        tree.update(&sum(b"\x00\x00\x00\x01"), b"DATA").unwrap();
+        tree.delete(&sum(b"\x00\x00\x00\x01")).unwrap();
+        tree.delete(&sum(b"\x00\x00\x00\x01")).unwrap();
+
+        let root = tree.root();
+        let expected_root = "39f36a7cb4dfb1b46f03d044265df6a491dffc1034121bc1071a34ddce9bb14b";
+        assert_eq!(hex::encode(root), expected_root);
+    }
+
     #[test]
     fn test_interleaved_update_delete() {
         let mut storage = StorageMap::<TestTable>::new();
@@ -675,6 +1255,61 @@ mod test {
         assert_eq!(root, expected_root);
     }
 
+    #[test]
+    fn test_load_over_read_only_storage_serves_queries() {
+        let mut storage = StorageMap::<TestTable>::new();
+        let mut tree = MerkleTree::new(&mut storage);
+        for i in 0_u32..10 {
+            tree.update(&sum(i.to_be_bytes()), b"DATA").unwrap();
+        }
+        let root = tree.root();
+
+        // A shared reference to the storage implements `StorageInspect` but
+        // not `StorageMutate`.
+        let tree = MerkleTree::load(&storage, &root).unwrap();
+        assert_eq!(tree.root(), root);
+        assert_eq!(tree.leaves().count(), 10);
+
+        let key = sum(0_u32.to_be_bytes());
+        assert_eq!(tree.get(&key).unwrap(), Some(sum(b"DATA")));
+        assert!(tree
+            .prove(&key)
+            .unwrap()
+            .verify_inclusion(&root, &key, b"DATA"));
+    }
+
+    #[test]
+    fn test_get_returns_the_data_hash_of_keys_in_the_tree() {
+        let mut storage = StorageMap::<TestTable>::new();
+        let mut tree = MerkleTree::new(&mut storage);
+        for i in 0_u32..10 {
+            tree.update(&sum(i.to_be_bytes()), &i.to_be_bytes())
+                .unwrap();
+        }
+        tree.delete(&sum(0_u32.to_be_bytes())).unwrap();
+
+        for i in 1_u32..10 {
+            let data = tree.get(&sum(i.to_be_bytes())).unwrap();
+            assert_eq!(data, Some(sum(i.to_be_bytes())));
+        }
+        assert_eq!(tree.get(&sum(0_u32.to_be_bytes())).unwrap(), None);
+        assert_eq!(tree.get(&sum(10_u32.to_be_bytes())).unwrap(), None);
+        assert_eq!(tree.get(&[0; 32]).unwrap(), None);
+    }
+
+    #[test]
+    fn test_load_returns_the_empty_tree_for_the_zero_root() {
+        let mut storage = StorageMap::<TestTable>::new();
+
+        let mut tree = MerkleTree::load(&mut storage, zero_sum()).unwrap();
+        assert_eq!(tree.root(), *zero_sum());
+
+        tree.update(&sum(b"\x00\x00\x00\x00"), b"DATA").unwrap();
+        let root = tree.root();
+        let expected_root = "39f36a7cb4dfb1b46f03d044265df6a491dffc1034121bc1071a34ddce9bb14b";
+        assert_eq!(hex::encode(root), expected_root);
+    }
+
     #[test]
     fn test_load_returns_a_load_error_if_the_storage_is_not_valid_for_the_root() {
         let mut storage = StorageMap::<TestTable>::new();
@@ -691,7 +1326,27 @@ mod test {
         let root = &sum(b"\xff\xff\xff\xff");
         let err = MerkleTree::load(&mut storage, root)
             .expect_err("Expected load() to return Error; got Ok");
-        assert!(matches!(err, MerkleTreeError::LoadError(_)));
+        assert!(matches!(err, MerkleTreeError::LoadError(key) if key == *root));
+    }
+
+    #[test]
+    fn test_load_returns_a_corrupt_root_error_if_the_root_node_does_not_hash_to_the_root() {
+        use fuel_storage::StorageMutate;
+
+        let mut storage = StorageMap::<TestTable>::new();
+
+        let mut tree = MerkleTree::new(&mut storage);
+        tree.update(&sum(b"\x00\x00\x00\x00"), b"DATA").unwrap();
+        tree.update(&sum(b"\x00\x00\x00\x01"), b"DATA").unwrap();
+        let root = tree.root();
+
+        // Overwrite the root key-value with a valid node that is not the root.
+        let leaf = Node::create_leaf(&sum(b"\x00\x00\x00\x00"), b"DATA");
+        storage.insert(&root, &leaf.as_ref().into()).unwrap();
+
+        let err = MerkleTree::load(&mut storage, &root)
+            .expect_err("Expected load() to return Error; got Ok");
+        assert!(matches!(err, MerkleTreeError::CorruptRootError(key) if key == root));
     }
 
     #[test]
@@ -717,4 +1372,462 @@ mod test {
             .expect_err("Expected load() to return Error; got Ok");
         assert!(matches!(err, MerkleTreeError::DeserializeError(_)));
     }
+
+    #[test]
+    fn test_from_set_returns_the_same_tree_as_sequential_updates() {
+        let set = (0_u32..100).map(|i| (sum(i.to_be_bytes()), b"DATA"));
+
+        let mut storage = StorageMap::<TestTable>::new();
+        let tree = MerkleTree::from_set(&mut storage, set).unwrap();
+
+        let root = tree.root();
+        let expected_root = "82bf747d455a55e2f7044a03536fc43f1f55d43b855e72c0110c986707a23e4d";
+        assert_eq!(hex::encode(root), expected_root);
+    }
+
+    #[test]
+    fn test_from_set_with_empty_set_returns_the_empty_tree() {
+        let set = Vec::<(Bytes32, &[u8])>::new();
+
+        let mut storage = StorageMap::<TestTable>::new();
+        let tree = MerkleTree::from_set(&mut storage, set).unwrap();
+
+        let root = tree.root();
+        let expected_root = "0000000000000000000000000000000000000000000000000000000000000000";
+        assert_eq!(hex::encode(root), expected_root);
+    }
+
+    #[test]
+    fn test_from_set_with_1_leaf_returns_the_leaf_as_root() {
+        let set = [(sum(b"\x00\x00\x00\x00"), b"DATA")];
+
+        let mut storage = StorageMap::<TestTable>::new();
+        let tree = MerkleTree::from_set(&mut storage, set).unwrap();
+
+        let root = tree.root();
+        let expected_root = "39f36a7cb4dfb1b46f03d044265df6a491dffc1034121bc1071a34ddce9bb14b";
+        assert_eq!(hex::encode(root), expected_root);
+    }
+
+    #[test]
+    fn test_from_set_keeps_the_last_value_of_repeated_keys() {
+        let set: [(Bytes32, &[u8]); 3] = [
+            (sum(b"\x00\x00\x00\x00"), b"DATA"),
+            (sum(b"\x00\x00\x00\x01"), b""),
+            (sum(b"\x00\x00\x00\x00"), b"CHANGE"),
+        ];
+
+        let mut storage = StorageMap::<TestTable>::new();
+        let tree = MerkleTree::from_set(&mut storage, set).unwrap();
+
+        let root = tree.root();
+        let expected_root = "dd97174c80e5e5aa3a31c61b05e279c1495c8a07b2a08bca5dbc9fb9774f9457";
+        assert_eq!(hex::encode(root), expected_root);
+    }
+
+    #[test]
+    fn test_from_set_treats_empty_data_as_a_delete() {
+        let set: [(Bytes32, &[u8]); 3] = [
+            (sum(b"\x00\x00\x00\x00"), b"DATA"),
+            (sum(b"\x00\x00\x00\x01"), b"DATA"),
+            (sum(b"\x00\x00\x00\x01"), b""),
+        ];
+
+        let mut storage = StorageMap::<TestTable>::new();
+        let tree = MerkleTree::from_set(&mut storage, set).unwrap();
+
+        let root = tree.root();
+        let expected_root = "39f36a7cb4dfb1b46f03d044265df6a491dffc1034121bc1071a34ddce9bb14b";
+        assert_eq!(hex::encode(root), expected_root);
+    }
+
+    #[test]
+    fn test_from_set_builds_a_tree_that_can_be_loaded_and_updated() {
+        let mut storage = StorageMap::<TestTable>::new();
+
+        let set = (0_u32..5).map(|i| (sum(i.to_be_bytes()), b"DATA"));
+        let root = MerkleTree::from_set(&mut storage, set).unwrap().root();
+
+        let mut tree = MerkleTree::load(&mut storage, &root).unwrap();
+        for i in 5_u32..10 {
+            tree.update(&sum(i.to_be_bytes()), b"DATA").unwrap();
+        }
+        for i in 5_u32..10 {
+            tree.delete(&sum(i.to_be_bytes())).unwrap();
+        }
+        tree.delete(&sum(0_u32.to_be_bytes())).unwrap();
+        tree.update(&sum(0_u32.to_be_bytes()), b"DATA").unwrap();
+
+        let root = tree.root();
+        let expected_root = "108f731f2414e33ae57e584dc26bd276db07874436b2264ca6e520c658185c6b";
+        assert_eq!(hex::encode(root), expected_root);
+    }
+
+    #[test]
+    fn test_root_from_set_returns_the_same_root_as_from_set() {
+        let set = (0_u32..10).map(|i| (sum(i.to_be_bytes()), b"DATA"));
+
+        let root = root_from_set(set);
+        let expected_root = "21ca4917e99da99a61de93deaf88c400d4c082991cb95779e444d43dd13e8849";
+        assert_eq!(hex::encode(root), expected_root);
+    }
+
+    #[derive(Debug)]
+    struct LeavesTable;
+
+    impl Mappable for LeavesTable {
+        type Key = Bytes32;
+        type SetValue = Primitive;
+        type GetValue = Self::SetValue;
+    }
+
+    /// A storage holding the nodes and the leaves of a tree in separate
+    /// tables.
+    #[derive(Default)]
+    struct SplitStorage {
+        nodes: StorageMap<TestTable>,
+        leaves: StorageMap<LeavesTable>,
+    }
+
+    impl StorageInspect<TestTable> for SplitStorage {
+        type Error = Infallible;
+
+        fn get(&self, key: &Bytes32) -> Result<Option<Cow<'_, Primitive>>, Self::Error> {
+            self.nodes.get(key)
+        }
+
+        fn contains_key(&self, key: &Bytes32) -> Result<bool, Self::Error> {
+            self.nodes.contains_key(key)
+        }
+    }
+
+    impl StorageMutate<TestTable> for SplitStorage {
+        fn insert(
+            &mut self,
+            key: &Bytes32,
+            value: &Primitive,
+        ) -> Result<Option<Primitive>, Self::Error> {
+            self.nodes.insert(key, value)
+        }
+
+        fn remove(&mut self, key: &Bytes32) -> Result<Option<Primitive>, Self::Error> {
+            self.nodes.remove(key)
+        }
+    }
+
+    impl StorageInspect<LeavesTable> for SplitStorage {
+        type Error = Infallible;
+
+        fn get(&self, key: &Bytes32) -> Result<Option<Cow<'_, Primitive>>, Self::Error> {
+            self.leaves.get(key)
+        }
+
+        fn contains_key(&self, key: &Bytes32) -> Result<bool, Self::Error> {
+            self.leaves.contains_key(key)
+        }
+    }
+
+    impl StorageMutate<LeavesTable> for SplitStorage {
+        fn insert(
+            &mut self,
+            key: &Bytes32,
+            value: &Primitive,
+        ) -> Result<Option<Primitive>, Self::Error> {
+            self.leaves.insert(key, value)
+        }
+
+        fn remove(&mut self, key: &Bytes32) -> Result<Option<Primitive>, Self::Error> {
+            self.leaves.remove(key)
+        }
+    }
+
+    type SplitTree<'a> = MerkleTree<TestTable, &'a mut SplitStorage, LeavesTable>;
+
+    #[test]
+    fn test_leaves_are_stored_by_key_in_the_leaves_table() {
+        let mut storage = SplitStorage::default();
+        let mut tree = SplitTree::new(&mut storage);
+        for i in 0_u32..10 {
+            tree.update(&sum(i.to_be_bytes()), b"DATA").unwrap();
+        }
+        tree.delete(&sum(0_u32.to_be_bytes())).unwrap();
+        tree.checkpoint();
+        tree.update(&sum(10_u32.to_be_bytes()), b"DATA").unwrap();
+        tree.delete(&sum(1_u32.to_be_bytes())).unwrap();
+        tree.commit().unwrap();
+        let root = tree.root();
+
+        let mut expected_storage = StorageMap::<TestTable>::new();
+        let mut expected_tree = MerkleTree::new(&mut expected_storage);
+        for i in 2_u32..11 {
+            expected_tree
+                .update(&sum(i.to_be_bytes()), b"DATA")
+                .unwrap();
+        }
+        assert_eq!(root, expected_tree.root());
+
+        // The leaves table holds exactly the leaves of the tree, and the nodes
+        // table holds no leaf under its key.
+        for i in 0_u32..11 {
+            let key = sum(i.to_be_bytes());
+            let leaf = StorageInspect::<LeavesTable>::get(&storage, &key).unwrap();
+            assert_eq!(leaf.is_some(), i >= 2);
+            assert!(!StorageInspect::<TestTable>::contains_key(&storage, &key).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_from_set_stores_leaves_in_the_leaves_table() {
+        let mut storage = SplitStorage::default();
+        let set = (0_u32..10).map(|i| (sum(i.to_be_bytes()), b"DATA"));
+        let root = SplitTree::from_set(&mut storage, set).unwrap().root();
+        for i in 0_u32..10 {
+            assert!(storage.leaves.contains_key(&sum(i.to_be_bytes())).unwrap());
+        }
+
+        let mut tree = SplitTree::load(&mut storage, &root).unwrap();
+        for i in 0_u32..10 {
+            tree.delete(&sum(i.to_be_bytes())).unwrap();
+        }
+        assert_eq!(tree.root(), *zero_sum());
+        for i in 0_u32..10 {
+            assert!(!storage.leaves.contains_key(&sum(i.to_be_bytes())).unwrap());
+        }
+    }
+
+    /// A storage that counts the reads of single keys and the batch reads.
+    #[derive(Default)]
+    struct BatchCountingStorage {
+        map: StorageMap<TestTable>,
+        gets: Cell<usize>,
+        batches: Cell<usize>,
+    }
+
+    impl StorageInspect<TestTable> for BatchCountingStorage {
+        type Error = Infallible;
+
+        fn get(&self, key: &Bytes32) -> Result<Option<Cow<'_, Primitive>>, Self::Error> {
+            self.gets.set(self.gets.get() + 1);
+            self.map.get(key)
+        }
+
+        fn contains_key(&self, key: &Bytes32) -> Result<bool, Self::Error> {
+            self.map.contains_key(key)
+        }
+    }
+
+    impl StorageInspectBatch<TestTable> for BatchCountingStorage {
+        fn get_batch(&self, keys: &[Bytes32]) -> GetBatchResult<'_, Primitive, Self::Error> {
+            self.batches.set(self.batches.get() + 1);
+            keys.iter().map(|key| self.map.get(key)).collect()
+        }
+    }
+
+    impl StorageMutate<TestTable> for BatchCountingStorage {
+        fn insert(
+            &mut self,
+            key: &Bytes32,
+            value: &Primitive,
+        ) -> Result<Option<Primitive>, Self::Error> {
+            self.map.insert(key, value)
+        }
+
+        fn remove(&mut self, key: &Bytes32) -> Result<Option<Primitive>, Self::Error> {
+            self.map.remove(key)
+        }
+    }
+
+    #[test]
+    fn test_path_walks_with_batch_reads_read_both_children_in_a_single_batch() {
+        let mut storage = BatchCountingStorage::default();
+        let mut tree = MerkleTree::new(&mut storage);
+        for i in 0_u32..10 {
+            tree.update(&sum(i.to_be_bytes()), b"DATA").unwrap();
+        }
+        let root = tree.root();
+
+        // By default, the children are read one at a time.
+        let tree = MerkleTree::load(&storage, &root).unwrap();
+        storage.batches.set(0);
+        tree.prove(&sum(0_u32.to_be_bytes())).unwrap();
+        assert_eq!(storage.batches.get(), 0);
+
+        let tree = MerkleTree::load(&storage, &root)
+            .unwrap()
+            .with_batch_reads();
+        storage.gets.set(0);
+        let proof = tree.prove(&sum(0_u32.to_be_bytes())).unwrap();
+        assert!(proof.verify_inclusion(&root, &sum(0_u32.to_be_bytes()), b"DATA"));
+        assert_eq!(storage.gets.get(), 0);
+        assert!(storage.batches.get() > 0);
+
+        assert_eq!(tree.leaves().count(), 10);
+        assert_eq!(storage.gets.get(), 0);
+    }
+
+    type FaultyTree<'a> =
+        MerkleTree<TestTable, &'a mut FaultyStorage<TestTable, StorageMap<TestTable>>>;
+
+    fn faulty_storage_with_leaves(
+        n: u32,
+    ) -> (FaultyStorage<TestTable, StorageMap<TestTable>>, Bytes32) {
+        let mut storage = FaultyStorage::new(StorageMap::<TestTable>::new());
+        let mut tree = FaultyTree::new(&mut storage);
+        for i in 0..n {
+            tree.update(&sum(i.to_be_bytes()), b"DATA").unwrap();
+        }
+        let root = tree.root();
+        (storage, root)
+    }
+
+    #[test]
+    fn test_update_with_failing_writes_keeps_the_tree_consistent() {
+        let mut expected_storage = StorageMap::<TestTable>::new();
+        let mut expected_tree = MerkleTree::new(&mut expected_storage);
+        for i in 0_u32..11 {
+            expected_tree
+                .update(&sum(i.to_be_bytes()), b"DATA")
+                .unwrap();
+        }
+        let expected_root = expected_tree.root();
+
+        for writes in 0..8 {
+            let (mut storage, root) = faulty_storage_with_leaves(10);
+            let faults = storage.faults();
+            let mut tree = FaultyTree::load(&mut storage, &root).unwrap();
+
+            faults.fail_writes_after(writes);
+            let result = tree.update(&sum(10_u32.to_be_bytes()), b"DATA");
+            faults.clear();
+            if result.is_err() {
+                assert!(matches!(
+                    result,
+                    Err(MerkleTreeError::StorageError(FaultError::WriteFailed))
+                ));
+                assert_eq!(tree.root(), root);
+                assert_eq!(tree.leaves().count(), 10);
+                tree.update(&sum(10_u32.to_be_bytes()), b"DATA").unwrap();
+            }
+            assert_eq!(tree.root(), expected_root);
+        }
+    }
+
+    #[test]
+    fn test_delete_with_failing_writes_keeps_the_tree_consistent() {
+        let mut expected_storage = StorageMap::<TestTable>::new();
+        let mut expected_tree = MerkleTree::new(&mut expected_storage);
+        for i in 1_u32..10 {
+            expected_tree
+                .update(&sum(i.to_be_bytes()), b"DATA")
+                .unwrap();
+        }
+        let expected_root = expected_tree.root();
+        let key = sum(0_u32.to_be_bytes());
+
+        for writes in 0..12 {
+            let (mut storage, root) = faulty_storage_with_leaves(10);
+            let faults = storage.faults();
+            let mut tree = FaultyTree::load(&mut storage, &root).unwrap();
+
+            faults.fail_writes_after(writes);
+            let result = tree.delete(&key);
+            faults.clear();
+            if result.is_err() {
+                // A failed delete leaves the tree unchanged.
+                assert!(matches!(
+                    result,
+                    Err(MerkleTreeError::StorageError(FaultError::WriteFailed))
+                ));
+                assert_eq!(tree.root(), root);
+                assert_eq!(tree.leaves().count(), 10);
+                assert!(tree.storage.get_leaf(&key).unwrap().is_some());
+                tree.delete(&key).unwrap();
+            }
+            assert_eq!(tree.root(), expected_root);
+            assert_eq!(tree.leaves().count(), 9);
+            assert!(tree.storage.get_leaf(&key).unwrap().is_none());
+        }
+    }
+
+    #[test]
+    fn test_prove_with_failing_reads_returns_a_storage_error() {
+        let (mut storage, root) = faulty_storage_with_leaves(10);
+        let faults = storage.faults();
+        let tree = FaultyTree::load(&mut storage, &root).unwrap();
+
+        faults.fail_reads_after(2);
+        let err = tree
+            .prove(&sum(0_u32.to_be_bytes()))
+            .expect_err("Expected prove() to return Error; got Ok");
+        assert!(matches!(
+            err,
+            MerkleTreeError::ChildError(ChildError::Error(StorageNodeError::StorageError(
+                FaultError::ReadFailed
+            )))
+        ));
+
+        faults.clear();
+        assert!(tree.prove(&sum(0_u32.to_be_bytes())).is_ok());
+    }
+
+    #[test]
+    fn test_update_with_a_missing_node_returns_a_child_not_found_error() {
+        let (mut storage, root) = faulty_storage_with_leaves(10);
+        let faults = storage.faults();
+        let mut tree = FaultyTree::load(&mut storage, &root).unwrap();
+
+        let root_node = tree.root_node().clone();
+        let missing_key = *root_node.left_child_key();
+        faults.drop_key(missing_key);
+        let err = tree
+            .update(&sum(10_u32.to_be_bytes()), b"DATA")
+            .expect_err("Expected update() to return Error; got Ok");
+        assert!(matches!(
+            err,
+            MerkleTreeError::ChildError(ChildError::ChildNotFound(key)) if key == missing_key
+        ));
+        assert_eq!(tree.root(), root);
+    }
+
+    #[test]
+    fn test_load_with_a_corrupt_root_returns_an_error() {
+        let (mut storage, root) = faulty_storage_with_leaves(10);
+        let faults = storage.faults();
+
+        let leaf: Primitive = Node::create_leaf(&sum(b"KEY"), b"DATA").as_ref().into();
+        faults.corrupt(root, leaf);
+        assert!(matches!(
+            FaultyTree::load(&mut storage, &root),
+            Err(MerkleTreeError::CorruptRootError(_))
+        ));
+
+        let (height, _, lo, hi) = leaf;
+        faults.corrupt(root, (height, 0xff, lo, hi));
+        assert!(matches!(
+            FaultyTree::load(&mut storage, &root),
+            Err(MerkleTreeError::DeserializeError(_))
+        ));
+    }
+
+    #[test]
+    fn test_prefix_queries_with_a_prefix_longer_than_a_key_return_an_invalid_prefix_length_error() {
+        let mut storage = StorageMap::<TestTable>::new();
+        let mut tree = MerkleTree::new(&mut storage);
+        tree.update(&sum(b"\x00\x00\x00\x00"), b"DATA").unwrap();
+        let prefix = [0xff; 32];
+
+        assert!(tree.subtree_root(&prefix, 256).is_ok());
+        assert!(matches!(
+            tree.subtree_root(&prefix, 257),
+            Err(MerkleTreeError::InvalidPrefixLength(257))
+        ));
+        assert!(matches!(
+            tree.subtree_proof(&prefix, 257),
+            Err(MerkleTreeError::InvalidPrefixLength(257))
+        ));
+        assert!(matches!(
+            tree.leaves_with_prefix(&prefix, 257),
+            Err(MerkleTreeError::InvalidPrefixLength(257))
+        ));
+    }
 }
@@ -8,9 +8,10 @@ use crate::{
         hash::{sum, sum_all},
         zero_sum, Primitive,
     },
-    storage::{Mappable, StorageInspect},
+    storage::{Mappable, ReadStrategy, StorageInspect},
 };
 
+use alloc::{borrow::Cow, vec::Vec};
 use core::marker::PhantomData;
 use core::{cmp, fmt};
 
@@ -20,6 +21,10 @@ pub(crate) struct Node {
     prefix: Prefix,
     bytes_lo: Bytes32,
     bytes_hi: Bytes32,
+    // The hash is calculated once, when the node is constructed. The fields
+    // above are never modified after construction, so the hash remains valid
+    // for the lifetime of the node.
+    hash: Bytes32,
 }
 
 impl Default for Node {
@@ -29,6 +34,7 @@ impl Default for Node {
             prefix: Default::default(),
             bytes_lo: *zero_sum(),
             bytes_hi: *zero_sum(),
+            hash: *zero_sum(),
         }
     }
 }
@@ -44,25 +50,16 @@ impl Node {
             prefix,
             bytes_lo,
             bytes_hi,
+            hash: Self::calculate_hash(prefix, &bytes_lo, &bytes_hi),
         }
     }
 
     pub fn create_leaf(key: &Bytes32, data: &[u8]) -> Self {
-        Self {
-            height: 0u32,
-            prefix: Prefix::Leaf,
-            bytes_lo: *key,
-            bytes_hi: sum(data),
-        }
+        Self::new(0u32, Prefix::Leaf, *key, sum(data))
     }
 
     pub fn create_node(left_child: &Node, right_child: &Node, height: u32) -> Self {
-        Self {
-            height,
-            prefix: Prefix::Node,
-            bytes_lo: left_child.hash(),
-            bytes_hi: right_child.hash(),
-        }
+        Self::new(height, Prefix::Node, left_child.hash(), right_child.hash())
     }
 
     pub fn create_node_on_path(path: &dyn Path, path_node: &Node, side_node: &Node) -> Self {
@@ -159,15 +156,31 @@ impl Node {
         *self.bytes_lo() == *zero_sum() && *self.bytes_hi() == *zero_sum()
     }
 
+    /// Returns true if the node is at a valid height for a node reached by
+    /// descending from the root, where the root is at the maximum height and
+    /// each internal node is one level below its parent. `expected_height` is
+    /// the height of an internal node at the position of this node. Leaves
+    /// are at height 0.
+    pub fn is_at_height(&self, expected_height: u32) -> bool {
+        if self.is_leaf() {
+            self.height() == 0
+        } else {
+            self.height() == expected_height && expected_height > 0
+        }
+    }
+
     pub fn hash(&self) -> Bytes32 {
-        if self.is_placeholder() {
+        self.hash
+    }
+
+    // PRIVATE
+
+    fn calculate_hash(prefix: Prefix, bytes_lo: &Bytes32, bytes_hi: &Bytes32) -> Bytes32 {
+        if *bytes_lo == *zero_sum() && *bytes_hi == *zero_sum() {
+            // The placeholder hash.
             *zero_sum()
         } else {
-            let data = [
-                self.prefix.as_ref(),
-                self.bytes_lo.as_ref(),
-                self.bytes_hi.as_ref(),
-            ];
+            let data = [prefix.as_ref(), bytes_lo.as_ref(), bytes_hi.as_ref()];
             sum_all(data)
         }
     }
@@ -219,13 +232,15 @@ impl fmt::Debug for Node {
     }
 }
 
-pub(crate) struct StorageNode<'storage, TableType, StorageType> {
+/// A node together with the storage its children are read from. Both
+/// children of a node are read together, according to `Reads`.
+pub(crate) struct StorageNode<'storage, TableType, StorageType, Reads> {
     storage: &'storage StorageType,
     node: Node,
-    phantom_table: PhantomData<TableType>,
+    phantom_table: PhantomData<(TableType, Reads)>,
 }
 
-impl<TableType, StorageType> Clone for StorageNode<'_, TableType, StorageType> {
+impl<TableType, StorageType, Reads> Clone for StorageNode<'_, TableType, StorageType, Reads> {
     fn clone(&self) -> Self {
         Self {
             storage: self.storage,
@@ -235,7 +250,7 @@ impl<TableType, StorageType> Clone for StorageNode<'_, TableType, StorageType> {
     }
 }
 
-impl<'s, TableType, StorageType> StorageNode<'s, TableType, StorageType> {
+impl<'s, TableType, StorageType, Reads> StorageNode<'s, TableType, StorageType, Reads> {
     pub fn new(storage: &'s StorageType, node: Node) -> Self {
         Self {
             node,
@@ -245,17 +260,25 @@ impl<'s, TableType, StorageType> StorageNode<'s, TableType, StorageType> {
     }
 }
 
-impl<TableType, StorageType> StorageNode<'_, TableType, StorageType> {
+impl<TableType, StorageType, Reads> StorageNode<'_, TableType, StorageType, Reads> {
     pub fn hash(&self) -> Bytes32 {
         self.node.hash()
     }
 
+    pub fn left_child_key(&self) -> &Bytes32 {
+        self.node.left_child_key()
+    }
+
+    pub fn right_child_key(&self) -> &Bytes32 {
+        self.node.right_child_key()
+    }
+
     pub fn into_node(self) -> Node {
         self.node
     }
 }
 
-impl<TableType, StorageType> NodeTrait for StorageNode<'_, TableType, StorageType> {
+impl<TableType, StorageType, Reads> NodeTrait for StorageNode<'_, TableType, StorageType, Reads> {
     type Key = Bytes32;
 
     fn height(&self) -> u32 {
@@ -284,11 +307,33 @@ pub enum StorageNodeError<StorageError> {
     DeserializeError(DeserializeError),
 }
 
-impl<TableType, StorageType> ParentNodeTrait for StorageNode<'_, TableType, StorageType>
+impl<TableType, StorageType, Reads> StorageNode<'_, TableType, StorageType, Reads>
 where
     StorageType: StorageInspect<TableType>,
+    Reads: ReadStrategy<TableType, StorageType>,
     TableType: Mappable<Key = Bytes32, SetValue = Primitive, GetValue = Primitive>,
-    StorageType::Error: fmt::Debug,
+    StorageType::Error: fmt::Debug + Clone,
+{
+    fn child(&self, key: &Bytes32, primitive: Option<Cow<'_, Primitive>>) -> ChildResult<Self> {
+        if key == zero_sum() {
+            return Ok(Self::new(self.storage, Node::create_placeholder()));
+        }
+        let primitive = primitive.ok_or(ChildError::ChildNotFound(*key))?;
+        Ok(primitive
+            .into_owned()
+            .try_into()
+            .map(|node| Self::new(self.storage, node))
+            .map_err(StorageNodeError::DeserializeError)?)
+    }
+}
+
+impl<TableType, StorageType, Reads> ParentNodeTrait
+    for StorageNode<'_, TableType, StorageType, Reads>
+where
+    StorageType: StorageInspect<TableType>,
+    Reads: ReadStrategy<TableType, StorageType>,
+    TableType: Mappable<Key = Bytes32, SetValue = Primitive, GetValue = Primitive>,
+    StorageType::Error: fmt::Debug + Clone,
 {
     type Error = StorageNodeError<StorageType::Error>;
 
@@ -297,19 +342,11 @@ where
             return Err(ChildError::NodeIsLeaf);
         }
         let key = self.node.left_child_key();
-        if key == zero_sum() {
-            return Ok(Self::new(self.storage, Node::create_placeholder()));
-        }
         let primitive = self
             .storage
             .get(key)
-            .map_err(StorageNodeError::StorageError)?
-            .ok_or(ChildError::ChildNotFound(*key))?;
-        Ok(primitive
-            .into_owned()
-            .try_into()
-            .map(|node| Self::new(self.storage, node))
-            .map_err(StorageNodeError::DeserializeError)?)
+            .map_err(StorageNodeError::StorageError)?;
+        self.child(key, primitive)
     }
 
     fn right_child(&self) -> ChildResult<Self> {
@@ -317,23 +354,52 @@ where
             return Err(ChildError::NodeIsLeaf);
         }
         let key = self.node.right_child_key();
-        if key == zero_sum() {
-            return Ok(Self::new(self.storage, Node::create_placeholder()));
-        }
         let primitive = self
             .storage
             .get(key)
-            .map_err(StorageNodeError::StorageError)?
-            .ok_or(ChildError::ChildNotFound(*key))?;
-        Ok(primitive
-            .into_owned()
-            .try_into()
-            .map(|node| Self::new(self.storage, node))
-            .map_err(StorageNodeError::DeserializeError)?)
+            .map_err(StorageNodeError::StorageError)?;
+        self.child(key, primitive)
+    }
+
+    /// Read both children together, in a single batch if `Reads` is
+    /// [`BatchReads`](crate::storage::BatchReads). Placeholder children are
+    /// not read from storage.
+    fn children(&self) -> (ChildResult<Self>, ChildResult<Self>) {
+        if self.is_leaf() {
+            return (Err(ChildError::NodeIsLeaf), Err(ChildError::NodeIsLeaf));
+        }
+        let left_key = self.node.left_child_key();
+        let right_key = self.node.right_child_key();
+        let keys = [left_key, right_key]
+            .into_iter()
+            .filter(|key| *key != zero_sum())
+            .copied()
+            .collect::<Vec<_>>();
+        let mut primitives = match Reads::get_batch(self.storage, &keys) {
+            Ok(primitives) => primitives.into_iter(),
+            Err(err) => {
+                let err = StorageNodeError::StorageError(err);
+                return (
+                    Err(ChildError::Error(err.clone())),
+                    Err(ChildError::Error(err)),
+                );
+            }
+        };
+        let mut next_child = |key: &Bytes32| {
+            let primitive = if key == zero_sum() {
+                None
+            } else {
+                primitives.next().flatten()
+            };
+            self.child(key, primitive)
+        };
+        let left_child = next_child(left_key);
+        let right_child = next_child(right_key);
+        (left_child, right_child)
     }
 }
 
-impl<TableType, StorageType> fmt::Debug for StorageNode<'_, TableType, StorageType>
+impl<TableType, StorageType, Reads> fmt::Debug for StorageNode<'_, TableType, StorageType, Reads>
 where
     StorageType: StorageInspect<TableType>,
     TableType: Mappable<Key = Bytes32, SetValue = Primitive, GetValue = Primitive>,
@@ -521,7 +587,7 @@ mod test_storage_node {
             error::DeserializeError, Bytes32, ChildError, ParentNode, PrefixError, StorageMap,
         },
         sparse::{hash::sum, node::StorageNodeError, Node, Primitive, StorageNode},
-        storage::{Mappable, StorageMutate},
+        storage::{Mappable, SingleReads, StorageMutate},
     };
 
     pub struct TestTable;
@@ -545,7 +611,7 @@ mod test_storage_node {
         let node_0 = Node::create_node(&leaf_0, &leaf_1, 1);
         let _ = s.insert(&node_0.hash(), &node_0.as_ref().into());
 
-        let storage_node = StorageNode::new(&s, node_0);
+        let storage_node = StorageNode::<_, _, SingleReads>::new(&s, node_0);
         let child = storage_node.left_child().unwrap();
 
         assert_eq!(child.hash(), leaf_0.hash());
@@ -564,7 +630,7 @@ mod test_storage_node {
         let node_0 = Node::create_node(&leaf_0, &leaf_1, 1);
         let _ = s.insert(&node_0.hash(), &node_0.as_ref().into());
 
-        let storage_node = StorageNode::new(&s, node_0);
+        let storage_node = StorageNode::<_, _, SingleReads>::new(&s, node_0);
         let child = storage_node.right_child().unwrap();
 
         assert_eq!(child.hash(), leaf_1.hash());
@@ -580,7 +646,7 @@ mod test_storage_node {
         let node_0 = Node::create_node(&Node::create_placeholder(), &leaf, 1);
         let _ = s.insert(&node_0.hash(), &node_0.as_ref().into());
 
-        let storage_node = StorageNode::new(&s, node_0);
+        let storage_node = StorageNode::<_, _, SingleReads>::new(&s, node_0);
         let child = storage_node.left_child().unwrap();
 
         assert!(child.node.is_placeholder());
@@ -596,7 +662,7 @@ mod test_storage_node {
         let node_0 = Node::create_node(&leaf, &Node::create_placeholder(), 1);
         let _ = s.insert(&node_0.hash(), &node_0.as_ref().into());
 
-        let storage_node = StorageNode::new(&s, node_0);
+        let storage_node = StorageNode::<_, _, SingleReads>::new(&s, node_0);
         let child = storage_node.right_child().unwrap();
 
         assert!(child.node.is_placeholder());
@@ -607,7 +673,7 @@ mod test_storage_node {
         let s = StorageMap::<TestTable>::new();
 
         let leaf_0 = Node::create_leaf(&sum(b"Hello World"), &[1u8; 32]);
-        let storage_node = StorageNode::new(&s, leaf_0);
+        let storage_node = StorageNode::<_, _, SingleReads>::new(&s, leaf_0);
         let err = storage_node
             .left_child()
             .expect_err("Expected left_child() to return Error; got OK");
@@ -620,7 +686,7 @@ mod test_storage_node {
         let s = StorageMap::<TestTable>::new();
 
         let leaf_0 = Node::create_leaf(&sum(b"Hello World"), &[1u8; 32]);
-        let storage_node = StorageNode::new(&s, leaf_0);
+        let storage_node = StorageNode::<_, _, SingleReads>::new(&s, leaf_0);
         let err = storage_node
             .right_child()
             .expect_err("Expected right_child() to return Error; got OK");
@@ -636,7 +702,7 @@ mod test_storage_node {
         let leaf_1 = Node::create_leaf(&sum(b"Goodbye World"), &[1u8; 32]);
         let node_0 = Node::create_node(&leaf_0, &leaf_1, 1);
 
-        let storage_node = StorageNode::new(&s, node_0);
+        let storage_node = StorageNode::<_, _, SingleReads>::new(&s, node_0);
         let err = storage_node
             .left_child()
             .expect_err("Expected left_child() to return Error; got Ok");
@@ -656,7 +722,7 @@ mod test_storage_node {
         let leaf_1 = Node::create_leaf(&sum(b"Goodbye World"), &[1u8; 32]);
         let node_0 = Node::create_node(&leaf_0, &leaf_1, 1);
 
-        let storage_node = StorageNode::new(&s, node_0);
+        let storage_node = StorageNode::<_, _, SingleReads>::new(&s, node_0);
         let err = storage_node
             .right_child()
             .expect_err("Expected right_child() to return Error; got Ok");
@@ -677,7 +743,7 @@ mod test_storage_node {
         let leaf_1 = Node::create_leaf(&sum(b"Goodbye World"), &[1u8; 32]);
         let node_0 = Node::create_node(&leaf_0, &leaf_1, 1);
 
-        let storage_node = StorageNode::new(&s, node_0);
+        let storage_node = StorageNode::<_, _, SingleReads>::new(&s, node_0);
         let err = storage_node
             .left_child()
             .expect_err("Expected left_child() to be Error; got Ok");
@@ -699,7 +765,7 @@ mod test_storage_node {
         let _ = s.insert(&leaf_1.hash(), &(0xff, 0xff, [0xff; 32], [0xff; 32]));
         let node_0 = Node::create_node(&leaf_0, &leaf_1, 1);
 
-        let storage_node = StorageNode::new(&s, node_0);
+        let storage_node = StorageNode::<_, _, SingleReads>::new(&s, node_0);
         let err = storage_node
             .right_child()
             .expect_err("Expected right_child() to be Error; got Ok");
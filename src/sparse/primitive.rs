@@ -1,5 +1,5 @@
 use crate::{
-    common::{error::DeserializeError, Bytes32, Prefix, PrefixError},
+    common::{check_length, Bytes32, DeserializeError, Encoding, Prefix, PrefixError, VerifyKey},
     sparse::Node,
 };
 
@@ -71,3 +71,102 @@ impl TryFrom<Primitive> for Node {
         Ok(node)
     }
 }
+
+impl VerifyKey<Bytes32> for Primitive {
+    /// A node is stored under its hash. A leaf is also stored under its leaf
+    /// key. The hash does not cover the height, so the height is checked
+    /// against the prefix: a leaf has height 0, and an internal node a height
+    /// of at least 1.
+    fn verify_key(&self, key: &Bytes32) -> bool {
+        match Node::try_from(*self) {
+            Ok(node) => {
+                let height_matches_prefix = if node.is_leaf() {
+                    node.height() == 0
+                } else {
+                    node.height() >= 1
+                };
+                height_matches_prefix
+                    && (node.hash() == *key || (node.is_leaf() && node.leaf_key() == key))
+            }
+            Err(_) => false,
+        }
+    }
+}
+
+impl Encoding for Primitive {
+    const SIZE: usize = 69;
+
+    type Bytes = [u8; 69];
+
+    fn to_bytes(&self) -> Self::Bytes {
+        let mut bytes = [0u8; 69];
+        bytes[0..4].copy_from_slice(&self.0.to_be_bytes());
+        bytes[4] = self.1;
+        bytes[5..37].copy_from_slice(&self.2);
+        bytes[37..69].copy_from_slice(&self.3);
+        bytes
+    }
+
+    /// The prefix is checked, so that the decoded primitive can be converted
+    /// to a [`Node`].
+    fn from_bytes(bytes: &[u8]) -> Result<Self, DeserializeError> {
+        let bytes = check_length::<69>(bytes)?;
+        let height = u32::from_be_bytes(bytes[0..4].try_into().unwrap());
+        let prefix = Prefix::try_from(bytes[4])?;
+        let bytes_lo = bytes[5..37].try_into().unwrap();
+        let bytes_hi = bytes[37..69].try_into().unwrap();
+        Ok((height, prefix as u8, bytes_lo, bytes_hi))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        common::{DeserializeError, Encoding, PrefixError},
+        sparse::{Node, Primitive},
+    };
+
+    #[test]
+    fn test_encoding_round_trips() {
+        let leaf = Node::create_leaf(&[0x11; 32], b"DATA");
+        let node = Node::create_node(&leaf, &Node::create_placeholder(), 1);
+
+        for node in [leaf, node] {
+            let primitive = Primitive::from(&node);
+            let bytes = primitive.to_bytes();
+            assert_eq!(bytes[0..4], node.height().to_be_bytes());
+            assert_eq!(bytes[4], node.prefix() as u8);
+            assert_eq!(&bytes[5..37], node.bytes_lo());
+            assert_eq!(&bytes[37..69], node.bytes_hi());
+            assert_eq!(Primitive::from_bytes(&bytes).unwrap(), primitive);
+
+            let bytes = primitive.to_versioned_bytes();
+            assert_eq!(Primitive::from_versioned_bytes(&bytes).unwrap(), primitive);
+        }
+    }
+
+    #[test]
+    fn test_from_bytes_with_invalid_prefix_returns_prefix_error() {
+        let primitive = Primitive::from(&Node::create_leaf(&[0x11; 32], b"DATA"));
+        let mut bytes = primitive.to_bytes();
+        bytes[4] = 0xff;
+
+        let err = Primitive::from_bytes(&bytes).unwrap_err();
+        assert!(matches!(
+            err,
+            DeserializeError::PrefixError(PrefixError::InvalidPrefix(0xff))
+        ));
+    }
+
+    #[test]
+    fn test_from_bytes_with_wrong_length_returns_invalid_length_error() {
+        let err = Primitive::from_bytes(&[0; 70]).unwrap_err();
+        assert!(matches!(
+            err,
+            DeserializeError::InvalidLength {
+                expected: 69,
+                received: 70
+            }
+        ));
+    }
+}
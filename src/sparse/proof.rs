@@ -0,0 +1,926 @@
+use crate::{
+    common::{Bit, Bytes32, Msb, Prefix, ProofSet},
+    sparse::{hash::sum, zero_sum, Node, Primitive},
+};
+
+use alloc::vec::Vec;
+use core::iter;
+
+/// Calculate the hash of the internal node with the given child hashes.
+fn node_hash(left_child: &Bytes32, right_child: &Bytes32) -> Bytes32 {
+    // The height of the node does not contribute to its hash.
+    Node::new(0, Prefix::Node, *left_child, *right_child).hash()
+}
+
+/// Calculate the root from the hash of a node found by following `path` from
+/// the root to a depth equal to the number of side nodes, and the side nodes
+/// along that path, ordered from the node to the root.
+fn fold_side_nodes(path: &Bytes32, node: Bytes32, side_nodes: &[Bytes32]) -> Bytes32 {
+    let depth = side_nodes.len();
+    side_nodes
+        .iter()
+        .enumerate()
+        .fold(node, |current, (i, side_node)| {
+            let parent_depth = depth - 1 - i;
+            match path.get_bit_at_index_from_msb(parent_depth) {
+                Some(Bit::_0) => node_hash(&current, side_node),
+                _ => node_hash(side_node, &current),
+            }
+        })
+}
+
+/// Returns true if the first `prefix_len` bits of `key` are equal to the
+/// first `prefix_len` bits of `prefix`.
+pub(crate) fn has_prefix(key: &Bytes32, prefix: &Bytes32, prefix_len: usize) -> bool {
+    key.common_prefix_count(prefix) >= prefix_len
+}
+
+/// # Subtree Proof
+///
+/// A proof that ties the root of a subtree to the root of the sparse Merkle
+/// tree containing it. The subtree holds every leaf with a key beginning with
+/// the first `prefix_len` bits of `prefix`; this is the subtree found by
+/// descending from the root of the tree according to the bits of the prefix.
+///
+/// The proof consists of the side nodes of the path from the root to the
+/// subtree, ordered from the subtree to the root. Because a subtree holding a
+/// single leaf is represented by that leaf, and an empty subtree is
+/// represented by a placeholder, the path can end before it reaches the depth
+/// of the prefix:
+///
+/// - If the path ends at a placeholder, the subtree is empty.
+/// - If the path ends at a leaf with a key that has the prefix, the subtree
+///   holds only that leaf, and its root is the hash of the leaf.
+/// - If the path ends at a leaf with a key that does not have the prefix, the
+///   subtree is empty, and the leaf takes the place of the subtree root when
+///   calculating the root of the tree.
+///
+/// When the path ends at a leaf, the proof includes the leaf, allowing the
+/// verifier to determine which of these cases applies.
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SubtreeProof {
+    #[cfg_attr(feature = "serde", serde(with = "crate::common::serde::bytes32"))]
+    prefix: Bytes32,
+    prefix_len: usize,
+    #[cfg_attr(
+        feature = "serde",
+        serde(with = "crate::common::serde::option_encoded")
+    )]
+    leaf: Option<Primitive>,
+    #[cfg_attr(feature = "serde", serde(with = "crate::common::serde::proof_set"))]
+    side_nodes: ProofSet,
+}
+
+impl SubtreeProof {
+    pub(crate) fn new(
+        prefix: Bytes32,
+        prefix_len: usize,
+        leaf: Option<Primitive>,
+        side_nodes: ProofSet,
+    ) -> Self {
+        Self {
+            prefix,
+            prefix_len,
+            leaf,
+            side_nodes,
+        }
+    }
+
+    pub fn prefix(&self) -> &Bytes32 {
+        &self.prefix
+    }
+
+    pub fn prefix_len(&self) -> usize {
+        self.prefix_len
+    }
+
+    /// The leaf found at the end of the path to the subtree, if the path ends
+    /// at a leaf.
+    pub fn leaf(&self) -> Option<&Primitive> {
+        self.leaf.as_ref()
+    }
+
+    pub fn side_nodes(&self) -> &ProofSet {
+        &self.side_nodes
+    }
+
+    /// Verify that the subtree with this proof's prefix has the root
+    /// `subtree_root` in the sparse Merkle tree with root `root`.
+    pub fn verify(&self, root: &Bytes32, subtree_root: &Bytes32) -> bool {
+        let depth = self.side_nodes.len();
+        if depth > self.prefix_len {
+            return false;
+        }
+
+        let node = match self.leaf {
+            Some(primitive) => {
+                let leaf = match Node::try_from(primitive) {
+                    Ok(leaf) if leaf.is_leaf() && !leaf.is_placeholder() => leaf,
+                    _ => return false,
+                };
+                // The leaf must be found on the path at the given depth. The
+                // subtree is either the leaf itself or, if the leaf is outside
+                // of the subtree, empty.
+                if !has_prefix(leaf.leaf_key(), &self.prefix, depth) {
+                    return false;
+                }
+                let expected_subtree_root =
+                    if has_prefix(leaf.leaf_key(), &self.prefix, self.prefix_len) {
+                        leaf.hash()
+                    } else {
+                        *zero_sum()
+                    };
+                if *subtree_root != expected_subtree_root {
+                    return false;
+                }
+                leaf.hash()
+            }
+            None => {
+                // A path that ends before the depth of the prefix without a
+                // leaf ends at a placeholder.
+                if depth < self.prefix_len && subtree_root != zero_sum() {
+                    return false;
+                }
+                *subtree_root
+            }
+        };
+
+        fold_side_nodes(&self.prefix, node, &self.side_nodes) == *root
+    }
+}
+
+/// # Proof
+///
+/// A proof of the leaf found by following the path of a key from the root of
+/// a sparse Merkle tree. The proof consists of the node at which the path ends
+/// and the side nodes of the path, ordered from that node to the root. The
+/// path ends at the leaf with the key if the key is in the tree; otherwise, it
+/// ends at a placeholder or at a leaf with another key, proving that the key
+/// is not in the tree.
+///
+/// Besides verifying the inclusion or exclusion of a key, the proof allows the
+/// root of the tree to be updated without access to storage: the side nodes of
+/// a key's path are the only nodes that contribute to the new root after the
+/// key is updated or deleted. See [`Proof::apply_update`] and
+/// [`Proof::apply_delete`].
+///
+/// A deletion can collapse the sibling of the deleted leaf into its place if
+/// the sibling is a leaf. The proof therefore includes the sibling, allowing
+/// the verifier to determine whether it is a leaf.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Proof {
+    #[cfg_attr(
+        feature = "serde",
+        serde(with = "crate::common::serde::option_encoded")
+    )]
+    leaf: Option<Primitive>,
+    #[cfg_attr(
+        feature = "serde",
+        serde(with = "crate::common::serde::option_encoded")
+    )]
+    sibling: Option<Primitive>,
+    #[cfg_attr(feature = "serde", serde(with = "crate::common::serde::proof_set"))]
+    side_nodes: ProofSet,
+}
+
+impl Proof {
+    pub(crate) fn new(
+        leaf: Option<Primitive>,
+        sibling: Option<Primitive>,
+        side_nodes: ProofSet,
+    ) -> Self {
+        Self {
+            leaf,
+            sibling,
+            side_nodes,
+        }
+    }
+
+    /// The leaf at which the path ends, if the path does not end at a
+    /// placeholder.
+    pub fn leaf(&self) -> Option<&Primitive> {
+        self.leaf.as_ref()
+    }
+
+    /// The sibling of the node at which the path ends, if the sibling is not a
+    /// placeholder.
+    pub fn sibling(&self) -> Option<&Primitive> {
+        self.sibling.as_ref()
+    }
+
+    pub fn side_nodes(&self) -> &ProofSet {
+        &self.side_nodes
+    }
+
+    /// Verify that the leaf with the given key and data is in the sparse
+    /// Merkle tree with root `root`.
+    pub fn verify_inclusion(&self, root: &Bytes32, key: &Bytes32, data: &[u8]) -> bool {
+        match self.leaf_node() {
+            Some(leaf) if leaf.leaf_key() == key => {
+                *leaf.leaf_data() == sum(data) && self.is_valid(root, key)
+            }
+            _ => false,
+        }
+    }
+
+    /// Verify that the given key is not in the sparse Merkle tree with root
+    /// `root`.
+    pub fn verify_exclusion(&self, root: &Bytes32, key: &Bytes32) -> bool {
+        match self.leaf_node() {
+            Some(leaf) if leaf.leaf_key() == key => false,
+            _ => self.is_valid(root, key),
+        }
+    }
+
+    /// Calculate the root of the tree with root `root` after setting the data
+    /// of the leaf with the given key, using only this proof of the key. As
+    /// with [`MerkleTree::update`](crate::sparse::MerkleTree::update), empty
+    /// data deletes the key. Returns `None` if the proof is not a valid proof
+    /// of the key in the tree with root `root`.
+    pub fn apply_update(&self, root: &Bytes32, key: &Bytes32, data: &[u8]) -> Option<Bytes32> {
+        if data.is_empty() {
+            return self.apply_delete(root, key);
+        }
+        if !self.is_valid(root, key) {
+            return None;
+        }
+
+        let leaf = Node::create_leaf(key, data);
+        match self.leaf_node() {
+            Some(actual_leaf) if actual_leaf.leaf_key() != key => {
+                // The new leaf is merged with the leaf currently on its path,
+                // at the depth at which their keys diverge. Placeholders fill
+                // the path between the merged node and the end of the proof's
+                // path.
+                let ancestor_depth = leaf.common_path_length(&actual_leaf);
+                let merged = match key.get_bit_at_index_from_msb(ancestor_depth) {
+                    Some(Bit::_0) => node_hash(&leaf.hash(), &actual_leaf.hash()),
+                    _ => node_hash(&actual_leaf.hash(), &leaf.hash()),
+                };
+                let placeholders_count = ancestor_depth.saturating_sub(self.side_nodes.len());
+                let side_nodes = iter::repeat_n(*zero_sum(), placeholders_count)
+                    .chain(self.side_nodes.iter().cloned())
+                    .collect::<Vec<_>>();
+                Some(fold_side_nodes(key, merged, &side_nodes))
+            }
+            // The new leaf replaces the leaf with the same key, or the
+            // placeholder at the end of the path.
+            _ => Some(fold_side_nodes(key, leaf.hash(), &self.side_nodes)),
+        }
+    }
+
+    /// Calculate the root of the tree with root `root` after deleting the leaf
+    /// with the given key, using only this proof of the key. If the key is not
+    /// in the tree, the root is unchanged. Returns `None` if the proof is not a
+    /// valid proof of the key in the tree with root `root`.
+    pub fn apply_delete(&self, root: &Bytes32, key: &Bytes32) -> Option<Bytes32> {
+        if !self.is_valid(root, key) {
+            return None;
+        }
+        match self.leaf_node() {
+            Some(leaf) if leaf.leaf_key() == key => {}
+            _ => return Some(*root),
+        }
+
+        let sibling = self
+            .sibling
+            .and_then(|primitive| Node::try_from(primitive).ok())
+            .filter(|sibling| sibling.is_leaf());
+        match sibling {
+            Some(sibling) => {
+                // The orphaned sibling leaf takes the place of every ancestor
+                // composed of it and a placeholder, and is joined with the
+                // first non-placeholder side node above it.
+                let side_nodes = self.side_nodes.get(1..)?;
+                let root = match side_nodes
+                    .iter()
+                    .position(|side_node| side_node != zero_sum())
+                {
+                    Some(index) => fold_side_nodes(key, sibling.hash(), &side_nodes[index..]),
+                    None => sibling.hash(),
+                };
+                Some(root)
+            }
+            // The deleted leaf is replaced by a placeholder.
+            None => Some(fold_side_nodes(key, *zero_sum(), &self.side_nodes)),
+        }
+    }
+
+    // PRIVATE
+
+    fn leaf_node(&self) -> Option<Node> {
+        self.leaf
+            .and_then(|primitive| Node::try_from(primitive).ok())
+    }
+
+    /// Calculate the root committed to by this proof of the given key.
+    fn root(&self, key: &Bytes32) -> Bytes32 {
+        let node = self
+            .leaf_node()
+            .map(|leaf| leaf.hash())
+            .unwrap_or(*zero_sum());
+        fold_side_nodes(key, node, &self.side_nodes)
+    }
+
+    fn is_valid(&self, root: &Bytes32, key: &Bytes32) -> bool {
+        let depth = self.side_nodes.len();
+        if depth > Node::max_height() {
+            return false;
+        }
+
+        // The leaf must be found on the path of the key.
+        if let Some(primitive) = self.leaf {
+            match Node::try_from(primitive) {
+                Ok(leaf) if leaf.is_leaf() && !leaf.is_placeholder() => {
+                    if !has_prefix(leaf.leaf_key(), key, depth) {
+                        return false;
+                    }
+                }
+                _ => return false,
+            }
+        }
+
+        // The sibling must be the first side node.
+        let sibling_hash = self
+            .sibling
+            .and_then(|primitive| Node::try_from(primitive).ok())
+            .map(|sibling| sibling.hash());
+        match (self.side_nodes.first(), sibling_hash) {
+            (Some(side_node), Some(sibling_hash)) if *side_node == sibling_hash => {}
+            (Some(side_node), None) if side_node == zero_sum() => {}
+            (None, None) => {}
+            _ => return false,
+        }
+
+        self.root(key) == *root
+    }
+}
+
+/// # Multi-Proof
+///
+/// A single proof of many keys in a sparse Merkle tree, proving the inclusion
+/// or exclusion of each key. The paths of the keys share their upper levels;
+/// the multi-proof holds each node of the union of the paths only once.
+///
+/// The proof is generated by descending from the root along the paths of the
+/// keys, in ascending key order. At each internal node, the keys are divided
+/// according to their bit at the node's depth. If every key continues to the
+/// same child, the other child is a side node and is included in the proof;
+/// otherwise, both children are on the paths and neither is included. A path
+/// ends at a leaf or a placeholder, called the terminal of the keys whose paths
+/// end there. The proof holds the terminals, each with its depth, and the side
+/// nodes in the order in which the descent encounters them.
+///
+/// The verifier repeats the descent using the keys alone, consuming terminals
+/// and side nodes in the same order, and calculates the root bottom-up.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MultiProof {
+    #[cfg_attr(feature = "serde", serde(with = "serde_terminals"))]
+    terminals: Vec<(usize, Option<Primitive>)>,
+    #[cfg_attr(feature = "serde", serde(with = "crate::common::serde::proof_set"))]
+    side_nodes: ProofSet,
+}
+
+/// The terminals of a [`MultiProof`], with each leaf serialized as its
+/// encoding.
+#[cfg(feature = "serde")]
+mod serde_terminals {
+    use crate::{common::serde::Encoded, sparse::Primitive};
+
+    use alloc::vec::Vec;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    type Terminal = (usize, Option<Primitive>);
+
+    pub fn serialize<S>(terminals: &[Terminal], serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_seq(
+            terminals
+                .iter()
+                .map(|(depth, leaf)| (depth, leaf.map(Encoded))),
+        )
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Vec<Terminal>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let terminals = Vec::<(usize, Option<Encoded<Primitive>>)>::deserialize(deserializer)?;
+        Ok(terminals
+            .into_iter()
+            .map(|(depth, leaf)| (depth, leaf.map(|leaf| leaf.0)))
+            .collect())
+    }
+}
+
+impl MultiProof {
+    pub(crate) fn new(terminals: Vec<(usize, Option<Primitive>)>, side_nodes: ProofSet) -> Self {
+        Self {
+            terminals,
+            side_nodes,
+        }
+    }
+
+    /// The depth and, unless it is a placeholder, the leaf at which each path
+    /// ends.
+    pub fn terminals(&self) -> &[(usize, Option<Primitive>)] {
+        &self.terminals
+    }
+
+    pub fn side_nodes(&self) -> &ProofSet {
+        &self.side_nodes
+    }
+
+    /// Verify every claim against the sparse Merkle tree with root `root`. A
+    /// claim of `(key, Some(data))` asserts that the leaf with the key holds
+    /// the data; a claim of `(key, None)` asserts that the key is not in the
+    /// tree. The claims must cover exactly the keys the proof was generated
+    /// for, in any order. A proof with no claims proves nothing and is
+    /// rejected.
+    pub fn verify<D: AsRef<[u8]>>(&self, root: &Bytes32, claims: &[(Bytes32, Option<D>)]) -> bool {
+        let mut claims = claims
+            .iter()
+            .map(|(key, data)| (*key, data.as_ref().map(|data| sum(data.as_ref()))))
+            .collect::<Vec<_>>();
+        claims.sort_by_key(|(key, _)| *key);
+        claims.dedup();
+        if claims.is_empty() || claims.windows(2).any(|pair| pair[0].0 == pair[1].0) {
+            // Conflicting claims of the same key can never be satisfied.
+            return false;
+        }
+
+        let mut terminals = self.terminals.iter().peekable();
+        let mut side_nodes = self.side_nodes.iter();
+        let calculated_root = fold_multiproof(&claims, 0, &mut terminals, &mut side_nodes);
+        calculated_root == Some(*root) && terminals.next().is_none() && side_nodes.next().is_none()
+    }
+}
+
+/// Calculate the hash of the node at the given depth on the paths of the
+/// claimed keys, checking the claims against the terminals. Returns `None` if
+/// the proof is malformed or a claim is false.
+fn fold_multiproof<'a, T, S>(
+    claims: &[(Bytes32, Option<Bytes32>)],
+    depth: usize,
+    terminals: &mut iter::Peekable<T>,
+    side_nodes: &mut S,
+) -> Option<Bytes32>
+where
+    T: Iterator<Item = &'a (usize, Option<Primitive>)>,
+    S: Iterator<Item = &'a Bytes32>,
+{
+    let &(terminal_depth, terminal) = *terminals.peek()?;
+    if terminal_depth < depth {
+        return None;
+    }
+    if terminal_depth == depth {
+        terminals.next();
+        let leaf = match terminal {
+            Some(primitive) => match Node::try_from(primitive) {
+                Ok(leaf) if leaf.is_leaf() && !leaf.is_placeholder() => Some(leaf),
+                _ => return None,
+            },
+            None => None,
+        };
+        for (key, data) in claims {
+            let leaf_data = match &leaf {
+                Some(leaf) if !has_prefix(leaf.leaf_key(), key, depth) => return None,
+                Some(leaf) if leaf.leaf_key() == key => Some(*leaf.leaf_data()),
+                _ => None,
+            };
+            if leaf_data != *data {
+                return None;
+            }
+        }
+        return Some(leaf.map(|leaf| leaf.hash()).unwrap_or(*zero_sum()));
+    }
+    if depth >= Node::max_height() {
+        return None;
+    }
+
+    let split =
+        claims.partition_point(|(key, _)| key.get_bit_at_index_from_msb(depth) == Some(Bit::_0));
+    let (left_claims, right_claims) = claims.split_at(split);
+    let (left, right) = if right_claims.is_empty() {
+        let side_node = *side_nodes.next()?;
+        (
+            fold_multiproof(left_claims, depth + 1, terminals, side_nodes)?,
+            side_node,
+        )
+    } else if left_claims.is_empty() {
+        let side_node = *side_nodes.next()?;
+        (
+            side_node,
+            fold_multiproof(right_claims, depth + 1, terminals, side_nodes)?,
+        )
+    } else {
+        (
+            fold_multiproof(left_claims, depth + 1, terminals, side_nodes)?,
+            fold_multiproof(right_claims, depth + 1, terminals, side_nodes)?,
+        )
+    };
+    Some(node_hash(&left, &right))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        common::{Bytes32, StorageMap},
+        sparse::{hash::sum, zero_sum, MerkleTree, Node, Primitive, Proof},
+    };
+    use alloc::vec::Vec;
+    use fuel_storage::Mappable;
+
+    #[derive(Debug)]
+    struct TestTable;
+
+    impl Mappable for TestTable {
+        type Key = Bytes32;
+        type SetValue = Primitive;
+        type GetValue = Self::SetValue;
+    }
+
+    fn prefix_from_byte(byte: u8) -> Bytes32 {
+        let mut prefix = [0; 32];
+        prefix[0] = byte;
+        prefix
+    }
+
+    #[test]
+    fn test_subtree_root_with_empty_prefix_returns_the_root() {
+        let mut storage = StorageMap::<TestTable>::new();
+        let mut tree = MerkleTree::new(&mut storage);
+        for i in 0_u32..10 {
+            tree.update(&sum(i.to_be_bytes()), b"DATA").unwrap();
+        }
+
+        let subtree_root = tree.subtree_root(&[0; 32], 0).unwrap();
+        assert_eq!(subtree_root, tree.root());
+    }
+
+    #[test]
+    fn test_subtree_root_depends_only_on_the_leaves_with_the_prefix() {
+        let mut storage = StorageMap::<TestTable>::new();
+        let mut tree = MerkleTree::new(&mut storage);
+        for i in 0_u32..100 {
+            tree.update(&sum(i.to_be_bytes()), b"DATA").unwrap();
+        }
+
+        for prefix_len in [1, 2, 4, 6] {
+            for byte in 0..(1_u16 << prefix_len) {
+                let prefix = prefix_from_byte((byte << (8 - prefix_len)) as u8);
+
+                let mut shard_storage = StorageMap::<TestTable>::new();
+                let mut shard = MerkleTree::new(&mut shard_storage);
+                for leaf in tree.leaves_with_prefix(&prefix, prefix_len).unwrap() {
+                    let (key, _) = leaf.unwrap();
+                    shard.update(&key, b"DATA").unwrap();
+                }
+
+                let subtree_root = tree.subtree_root(&prefix, prefix_len).unwrap();
+                let expected_subtree_root = shard.subtree_root(&prefix, prefix_len).unwrap();
+                assert_eq!(subtree_root, expected_subtree_root);
+            }
+        }
+    }
+
+    #[test]
+    fn test_subtree_proof_verifies_every_shard() {
+        let mut storage = StorageMap::<TestTable>::new();
+        let mut tree = MerkleTree::new(&mut storage);
+        for i in 0_u32..20 {
+            tree.update(&sum(i.to_be_bytes()), b"DATA").unwrap();
+        }
+        let root = tree.root();
+
+        // With 20 leaves and 64 shards, the shards include empty subtrees,
+        // subtrees holding a single leaf, and subtrees holding many leaves.
+        let prefix_len = 6;
+        for byte in 0..(1_u8 << prefix_len) {
+            let prefix = prefix_from_byte(byte << (8 - prefix_len));
+            let subtree_root = tree.subtree_root(&prefix, prefix_len).unwrap();
+            let proof = tree.subtree_proof(&prefix, prefix_len).unwrap();
+
+            assert!(proof.verify(&root, &subtree_root));
+            assert!(!proof.verify(&root, &sum(b"SUBTREE")));
+            assert!(!proof.verify(&sum(b"ROOT"), &subtree_root));
+        }
+    }
+
+    #[test]
+    fn test_subtree_proof_verifies_a_subtree_of_a_single_leaf_tree() {
+        let mut storage = StorageMap::<TestTable>::new();
+        let mut tree = MerkleTree::new(&mut storage);
+        let key = sum(b"\x00\x00\x00\x00");
+        tree.update(&key, b"DATA").unwrap();
+        let root = tree.root();
+
+        let proof = tree.subtree_proof(&key, 8).unwrap();
+        assert!(proof.verify(&root, &root));
+        assert!(!proof.verify(&root, zero_sum()));
+
+        let mut other_prefix = key;
+        other_prefix[0] ^= 0x01;
+        let proof = tree.subtree_proof(&other_prefix, 8).unwrap();
+        assert!(proof.leaf().is_some());
+        assert!(proof.verify(&root, zero_sum()));
+        assert!(!proof.verify(&root, &root));
+    }
+
+    #[test]
+    fn test_subtree_proof_verifies_the_empty_tree() {
+        let mut storage = StorageMap::<TestTable>::new();
+        let tree = MerkleTree::new(&mut storage);
+        let root = tree.root();
+
+        let proof = tree.subtree_proof(&[0; 32], 4).unwrap();
+        assert!(proof.verify(&root, zero_sum()));
+        assert!(!proof.verify(&root, &sum(b"SUBTREE")));
+    }
+
+    #[test]
+    fn test_proof_verifies_inclusion_and_exclusion() {
+        let mut storage = StorageMap::<TestTable>::new();
+        let mut tree = MerkleTree::new(&mut storage);
+        for i in 0_u32..20 {
+            tree.update(&sum(i.to_be_bytes()), b"DATA").unwrap();
+        }
+        let root = tree.root();
+
+        for i in 0_u32..20 {
+            let key = sum(i.to_be_bytes());
+            let proof = tree.prove(&key).unwrap();
+            assert!(proof.verify_inclusion(&root, &key, b"DATA"));
+            assert!(!proof.verify_inclusion(&root, &key, b"CHANGE"));
+            assert!(!proof.verify_exclusion(&root, &key));
+            assert!(!proof.verify_inclusion(&sum(b"ROOT"), &key, b"DATA"));
+        }
+
+        for i in 20_u32..40 {
+            let key = sum(i.to_be_bytes());
+            let proof = tree.prove(&key).unwrap();
+            assert!(proof.verify_exclusion(&root, &key));
+            assert!(!proof.verify_inclusion(&root, &key, b"DATA"));
+        }
+    }
+
+    #[test]
+    fn test_proof_verification_fails_for_another_key() {
+        let mut storage = StorageMap::<TestTable>::new();
+        let mut tree = MerkleTree::new(&mut storage);
+        for i in 0_u32..20 {
+            tree.update(&sum(i.to_be_bytes()), b"DATA").unwrap();
+        }
+        let root = tree.root();
+
+        let proof = tree.prove(&sum(0_u32.to_be_bytes())).unwrap();
+        let other_key = sum(1_u32.to_be_bytes());
+        assert!(!proof.verify_inclusion(&root, &other_key, b"DATA"));
+        assert!(!proof.verify_exclusion(&root, &other_key));
+    }
+
+    #[test]
+    fn test_proof_verifies_exclusion_from_the_empty_tree() {
+        let mut storage = StorageMap::<TestTable>::new();
+        let tree = MerkleTree::new(&mut storage);
+        let root = tree.root();
+
+        let key = sum(b"\x00\x00\x00\x00");
+        let proof = tree.prove(&key).unwrap();
+        assert!(proof.leaf().is_none());
+        assert!(proof.side_nodes().is_empty());
+        assert!(proof.verify_exclusion(&root, &key));
+        assert!(!proof.verify_inclusion(&root, &key, b"DATA"));
+    }
+
+    #[test]
+    fn test_apply_update_returns_the_root_of_the_updated_tree() {
+        let mut storage = StorageMap::<TestTable>::new();
+        let mut tree = MerkleTree::new(&mut storage);
+
+        // Insert new keys, starting with the empty tree and a tree with a
+        // single leaf, then overwrite existing keys.
+        let keys = (0_u32..50)
+            .chain(0..10)
+            .map(|i| sum(i.to_be_bytes()))
+            .collect::<Vec<_>>();
+        for (i, key) in keys.iter().enumerate() {
+            let data = (i as u32).to_be_bytes();
+            let proof = tree.prove(key).unwrap();
+            let expected_root = proof.apply_update(&tree.root(), key, &data).unwrap();
+
+            tree.update(key, &data).unwrap();
+            assert_eq!(tree.root(), expected_root);
+        }
+    }
+
+    #[test]
+    fn test_apply_delete_returns_the_root_of_the_updated_tree() {
+        let mut storage = StorageMap::<TestTable>::new();
+        let mut tree = MerkleTree::new(&mut storage);
+        for i in 0_u32..50 {
+            tree.update(&sum(i.to_be_bytes()), b"DATA").unwrap();
+        }
+
+        // Delete every key until the tree is empty, including keys that are
+        // not in the tree.
+        for i in (0_u32..60).rev() {
+            let key = sum(i.to_be_bytes());
+            let root = tree.root();
+            let proof = tree.prove(&key).unwrap();
+            let expected_root = proof.apply_delete(&root, &key).unwrap();
+            assert_eq!(proof.apply_update(&root, &key, b""), Some(expected_root));
+
+            tree.delete(&key).unwrap();
+            assert_eq!(tree.root(), expected_root);
+        }
+        assert_eq!(tree.root(), *zero_sum());
+    }
+
+    #[test]
+    fn test_apply_delete_collapses_the_orphaned_sibling_leaf() {
+        let mut storage = StorageMap::<TestTable>::new();
+        let mut tree = MerkleTree::new(&mut storage);
+        let key_0 = sum(b"\x00\x00\x00\x00");
+        let key_1 = sum(b"\x00\x00\x00\x01");
+        tree.update(&key_0, b"DATA").unwrap();
+        tree.update(&key_1, b"DATA").unwrap();
+
+        let proof = tree.prove(&key_0).unwrap();
+        assert!(proof.sibling().is_some());
+        assert_eq!(
+            proof.apply_delete(&tree.root(), &key_0),
+            Some(Node::create_leaf(&key_1, b"DATA").hash())
+        );
+    }
+
+    #[test]
+    fn test_apply_returns_none_for_a_proof_of_another_root() {
+        let mut storage = StorageMap::<TestTable>::new();
+        let mut tree = MerkleTree::new(&mut storage);
+        for i in 0_u32..10 {
+            tree.update(&sum(i.to_be_bytes()), b"DATA").unwrap();
+        }
+
+        let key = sum(0_u32.to_be_bytes());
+        let proof = tree.prove(&key).unwrap();
+        let other_root = sum(b"ROOT");
+        assert_eq!(proof.apply_update(&other_root, &key, b"NEW DATA"), None);
+        assert_eq!(proof.apply_delete(&other_root, &key), None);
+    }
+
+    #[test]
+    fn test_apply_returns_none_for_a_proof_with_truncated_side_nodes() {
+        let mut storage = StorageMap::<TestTable>::new();
+        let mut tree = MerkleTree::new(&mut storage);
+        let key_0 = sum(b"\x00\x00\x00\x00");
+        let key_1 = sum(b"\x00\x00\x00\x01");
+        tree.update(&key_0, b"DATA").unwrap();
+        tree.update(&key_1, b"DATA").unwrap();
+        let root = tree.root();
+
+        // A proof with a sibling leaf, but without the side nodes.
+        let proof = tree.prove(&key_0).unwrap();
+        let proof = Proof::new(proof.leaf().cloned(), proof.sibling().cloned(), vec![]);
+        assert_eq!(proof.apply_delete(&root, &key_0), None);
+        assert_eq!(proof.apply_update(&root, &key_0, b"NEW DATA"), None);
+    }
+
+    #[test]
+    fn test_proof_verification_fails_if_the_sibling_is_not_the_first_side_node() {
+        let mut storage = StorageMap::<TestTable>::new();
+        let mut tree = MerkleTree::new(&mut storage);
+        for i in 0_u32..20 {
+            tree.update(&sum(i.to_be_bytes()), b"DATA").unwrap();
+        }
+        let root = tree.root();
+
+        let key = sum(0_u32.to_be_bytes());
+        let proof = tree.prove(&key).unwrap();
+        let leaf = Node::create_leaf(&sum(b"OTHER"), b"DATA");
+        let proof = Proof::new(
+            proof.leaf().cloned(),
+            Some(leaf.as_ref().into()),
+            proof.side_nodes().clone(),
+        );
+        assert!(!proof.verify_inclusion(&root, &key, b"DATA"));
+    }
+
+    #[test]
+    fn test_multiproof_verifies_inclusion_and_exclusion_claims() {
+        let mut storage = StorageMap::<TestTable>::new();
+        let mut tree = MerkleTree::new(&mut storage);
+        for i in 0_u32..50 {
+            tree.update(&sum(i.to_be_bytes()), &i.to_be_bytes())
+                .unwrap();
+        }
+        let root = tree.root();
+
+        // Claim the inclusion of every third key and the exclusion of keys
+        // that are not in the tree.
+        let claims = (0_u32..50)
+            .step_by(3)
+            .map(|i| (sum(i.to_be_bytes()), Some(i.to_be_bytes())))
+            .chain((50_u32..60).map(|i| (sum(i.to_be_bytes()), None)))
+            .collect::<Vec<_>>();
+        let keys = claims.iter().map(|(key, _)| *key).collect::<Vec<_>>();
+        let proof = tree.prove_many(&keys).unwrap();
+        assert!(proof.verify(&root, &claims));
+        assert!(!proof.verify(&sum(b"ROOT"), &claims));
+
+        // The claims can be given in any order.
+        let mut reversed_claims = claims.clone();
+        reversed_claims.reverse();
+        assert!(proof.verify(&root, &reversed_claims));
+
+        // Every false claim is rejected.
+        for i in 0..claims.len() {
+            let mut false_claims = claims.clone();
+            false_claims[i].1 = match false_claims[i].1 {
+                Some(_) => None,
+                None => Some(*b"DATA"),
+            };
+            assert!(!proof.verify(&root, &false_claims));
+        }
+    }
+
+    #[test]
+    fn test_multiproof_shares_side_nodes_between_keys() {
+        let mut storage = StorageMap::<TestTable>::new();
+        let mut tree = MerkleTree::new(&mut storage);
+        for i in 0_u32..100 {
+            tree.update(&sum(i.to_be_bytes()), b"DATA").unwrap();
+        }
+
+        let keys = (0_u32..20)
+            .map(|i| sum(i.to_be_bytes()))
+            .collect::<Vec<_>>();
+        let proof = tree.prove_many(&keys).unwrap();
+        let side_nodes_count = keys
+            .iter()
+            .map(|key| tree.prove(key).unwrap().side_nodes().len())
+            .sum::<usize>();
+        assert!(proof.side_nodes().len() < side_nodes_count);
+        assert_eq!(proof.terminals().len(), keys.len());
+    }
+
+    #[test]
+    fn test_multiproof_of_1_key_matches_the_proof_of_the_key() {
+        let mut storage = StorageMap::<TestTable>::new();
+        let mut tree = MerkleTree::new(&mut storage);
+        for i in 0_u32..20 {
+            tree.update(&sum(i.to_be_bytes()), b"DATA").unwrap();
+        }
+        let root = tree.root();
+
+        let key = sum(0_u32.to_be_bytes());
+        let proof = tree.prove_many(&[key]).unwrap();
+        let mut side_nodes = tree.prove(&key).unwrap().side_nodes().clone();
+        side_nodes.reverse();
+        assert_eq!(*proof.side_nodes(), side_nodes);
+        assert!(proof.verify(&root, &[(key, Some(b"DATA"))]));
+    }
+
+    #[test]
+    fn test_multiproof_rejects_claims_of_other_keys() {
+        let mut storage = StorageMap::<TestTable>::new();
+        let mut tree = MerkleTree::new(&mut storage);
+        for i in 0_u32..20 {
+            tree.update(&sum(i.to_be_bytes()), b"DATA").unwrap();
+        }
+        let root = tree.root();
+
+        let keys = [sum(0_u32.to_be_bytes()), sum(1_u32.to_be_bytes())];
+        let proof = tree.prove_many(&keys).unwrap();
+        assert!(proof.verify(&root, &[(keys[0], Some(b"DATA")), (keys[1], Some(b"DATA"))]));
+        assert!(!proof.verify(&root, &[(keys[0], Some(b"DATA"))]));
+        assert!(!proof.verify(
+            &root,
+            &[
+                (keys[0], Some(b"DATA")),
+                (keys[1], Some(b"DATA")),
+                (sum(2_u32.to_be_bytes()), Some(b"DATA"))
+            ]
+        ));
+        assert!(!proof.verify(&root, &[(keys[0], Some(b"DATA")), (keys[0], None)]));
+        assert!(!proof.verify::<&[u8]>(&root, &[]));
+    }
+
+    #[test]
+    fn test_multiproof_verifies_exclusion_from_the_empty_tree() {
+        let mut storage = StorageMap::<TestTable>::new();
+        let tree = MerkleTree::new(&mut storage);
+        let root = tree.root();
+
+        let keys = [sum(0_u32.to_be_bytes()), sum(1_u32.to_be_bytes())];
+        let proof = tree.prove_many(&keys).unwrap();
+        assert!(proof.verify::<&[u8]>(&root, &[(keys[0], None), (keys[1], None)]));
+        assert!(!proof.verify(&root, &[(keys[0], Some(b"DATA")), (keys[1], None)]));
+    }
+}
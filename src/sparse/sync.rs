@@ -0,0 +1,463 @@
+use crate::{
+    common::{error::DeserializeError, Bytes32},
+    sparse::{zero_sum, MerkleTree, MerkleTreeError, Node, Primitive},
+    storage::{Mappable, StorageInspect, StorageMutate},
+};
+
+use alloc::{vec, vec::Vec};
+use core::{fmt, marker::PhantomData};
+
+/// The keys of the non-placeholder children of `node`, in the order in which
+/// they are pushed to a stack of node keys, such that the left child is popped
+/// before the right child.
+fn children(node: &Node) -> impl Iterator<Item = Bytes32> + '_ {
+    let keys = if node.is_node() {
+        [*node.right_child_key(), *node.left_child_key()]
+    } else {
+        [*zero_sum(); 2]
+    };
+    keys.into_iter().filter(|key| key != zero_sum())
+}
+
+/// # Export Iterator
+///
+/// An iterator over the nodes reachable from the root of a sparse Merkle tree,
+/// yielding the nodes in chunks of a fixed size (the final chunk may be
+/// smaller). See [`export`].
+///
+/// Nodes are exported in pre-order: each node is followed by the nodes of its
+/// left subtree, then by the nodes of its right subtree. Placeholders are not
+/// exported. This order allows an [`Importer`] to verify each node against the
+/// hash held by its parent as soon as the node is received.
+///
+/// If a node cannot be loaded from storage, the iterator yields the error and
+/// terminates.
+pub struct ExportIter<'storage, TableType, StorageType> {
+    storage: &'storage StorageType,
+    chunk_size: usize,
+    stack: Vec<Bytes32>,
+    phantom_table: PhantomData<TableType>,
+}
+
+/// Export the nodes of the sparse Merkle tree with the given root, in chunks
+/// of `chunk_size` nodes. The chunks can be imported into another storage by
+/// an [`Importer`] holding the same root. A `chunk_size` of 0 is treated as 1.
+pub fn export<'storage, TableType, StorageType>(
+    storage: &'storage StorageType,
+    root: &Bytes32,
+    chunk_size: usize,
+) -> ExportIter<'storage, TableType, StorageType> {
+    let stack = if root == zero_sum() {
+        vec![]
+    } else {
+        vec![*root]
+    };
+    ExportIter {
+        storage,
+        chunk_size: chunk_size.max(1),
+        stack,
+        phantom_table: Default::default(),
+    }
+}
+
+impl<'storage, TableType, StorageType> ExportIter<'storage, TableType, StorageType>
+where
+    TableType: Mappable<Key = Bytes32, SetValue = Primitive, GetValue = Primitive>,
+    StorageType: StorageInspect<TableType>,
+{
+    fn next_chunk(&mut self) -> Result<Vec<Primitive>, MerkleTreeError<StorageType::Error>> {
+        let mut chunk = Vec::with_capacity(self.chunk_size);
+        while chunk.len() < self.chunk_size {
+            let key = match self.stack.pop() {
+                Some(key) => key,
+                None => break,
+            };
+            let primitive = self
+                .storage
+                .get(&key)?
+                .ok_or(MerkleTreeError::LoadError(key))?
+                .into_owned();
+            let node = Node::try_from(primitive).map_err(MerkleTreeError::DeserializeError)?;
+            self.stack.extend(children(&node));
+            chunk.push(primitive);
+        }
+        Ok(chunk)
+    }
+}
+
+impl<'storage, TableType, StorageType> Iterator for ExportIter<'storage, TableType, StorageType>
+where
+    TableType: Mappable<Key = Bytes32, SetValue = Primitive, GetValue = Primitive>,
+    StorageType: StorageInspect<TableType>,
+{
+    type Item = Result<Vec<Primitive>, MerkleTreeError<StorageType::Error>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.stack.is_empty() {
+            return None;
+        }
+        let chunk = self.next_chunk();
+        if chunk.is_err() {
+            self.stack.clear();
+        }
+        Some(chunk)
+    }
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "std", derive(thiserror::Error))]
+pub enum ImportError<StorageError> {
+    #[cfg_attr(
+        feature = "std",
+        error("received node with hash {}; expected node with hash {}", hex::encode(.0), hex::encode(.1))
+    )]
+    UnexpectedNode(Bytes32, Bytes32),
+
+    #[cfg_attr(
+        feature = "std",
+        error("received node with hash {} at an invalid height", hex::encode(.0))
+    )]
+    InvalidNode(Bytes32),
+
+    #[cfg_attr(feature = "std", error("received node after the import was complete"))]
+    ExcessNode,
+
+    #[cfg_attr(
+        feature = "std",
+        error("import is incomplete; {0} nodes are still expected")
+    )]
+    Incomplete(usize),
+
+    #[cfg_attr(feature = "std", error(transparent))]
+    StorageError(StorageError),
+
+    #[cfg_attr(feature = "std", error(transparent))]
+    DeserializeError(DeserializeError),
+
+    #[cfg_attr(feature = "std", error(transparent))]
+    MerkleTreeError(MerkleTreeError<StorageError>),
+}
+
+impl<StorageError> From<StorageError> for ImportError<StorageError> {
+    fn from(err: StorageError) -> ImportError<StorageError> {
+        ImportError::StorageError(err)
+    }
+}
+
+/// # Importer
+///
+/// Imports the nodes of a sparse Merkle tree, received in chunks from an
+/// untrusted source, into storage. The importer is created with the trusted
+/// root of the tree, and expects the nodes in the order produced by
+/// [`export`].
+///
+/// The importer tracks the hashes of the nodes it expects to receive, starting
+/// with the root. Each received node must hash to the next expected hash; a
+/// node is written to storage only after it has been verified, and its
+/// children are then expected in turn. Because every expected hash is either
+/// the trusted root or held by a verified parent, every node written to
+/// storage belongs to the tree with the trusted root.
+///
+/// The hash of a node does not include its height, so the height is verified
+/// separately: the root of the tree is at the maximum height, the internal
+/// children of an internal node are one level below their parent, and leaves
+/// are at height 0. A node at any other height is rejected.
+///
+/// If a chunk contains an unexpected node, the import of the chunk stops at
+/// that node and an error is returned. The nodes preceding it remain imported,
+/// and the import can resume with a chunk starting at the expected node.
+pub struct Importer<TableType, StorageType, LeavesTableType = TableType> {
+    storage: StorageType,
+    root: Bytes32,
+    // The hash and, if the node is an internal node, the height of each node
+    // expected.
+    expected: Vec<(Bytes32, u32)>,
+    imported: usize,
+    phantom_table: PhantomData<(TableType, LeavesTableType)>,
+}
+
+impl<TableType, StorageType, LeavesTableType, StorageError>
+    Importer<TableType, StorageType, LeavesTableType>
+where
+    TableType: Mappable<Key = Bytes32, SetValue = Primitive, GetValue = Primitive>,
+    LeavesTableType: Mappable<Key = Bytes32, SetValue = Primitive, GetValue = Primitive>,
+    StorageType: StorageMutate<TableType, Error = StorageError>
+        + StorageMutate<LeavesTableType, Error = StorageError>,
+    StorageError: fmt::Debug + Clone + 'static,
+{
+    pub fn new(storage: StorageType, root: &Bytes32) -> Self {
+        let expected = if root == zero_sum() {
+            vec![]
+        } else {
+            vec![(*root, Node::max_height() as u32)]
+        };
+        Self {
+            storage,
+            root: *root,
+            expected,
+            imported: 0,
+            phantom_table: Default::default(),
+        }
+    }
+
+    /// Verify and import the given chunk of nodes.
+    pub fn import(&mut self, chunk: &[Primitive]) -> Result<(), ImportError<StorageError>> {
+        for primitive in chunk {
+            let (expected_hash, expected_height) =
+                *self.expected.last().ok_or(ImportError::ExcessNode)?;
+            let node = Node::try_from(*primitive).map_err(ImportError::DeserializeError)?;
+            let hash = node.hash();
+            if hash != expected_hash {
+                return Err(ImportError::UnexpectedNode(hash, expected_hash));
+            }
+            if !node.is_at_height(expected_height) {
+                return Err(ImportError::InvalidNode(hash));
+            }
+
+            StorageMutate::<TableType>::insert(&mut self.storage, &hash, primitive)?;
+            if node.is_leaf() {
+                StorageMutate::<LeavesTableType>::insert(
+                    &mut self.storage,
+                    node.leaf_key(),
+                    primitive,
+                )?;
+            }
+
+            self.expected.pop();
+            self.expected
+                .extend(children(&node).map(|key| (key, expected_height - 1)));
+            self.imported += 1;
+        }
+
+        Ok(())
+    }
+
+    /// The number of nodes imported so far.
+    pub fn imported(&self) -> usize {
+        self.imported
+    }
+
+    /// Returns true if every node of the tree has been imported.
+    pub fn is_complete(&self) -> bool {
+        self.expected.is_empty()
+    }
+
+    /// Complete the import, returning the imported tree.
+    pub fn finish(
+        self,
+    ) -> Result<MerkleTree<TableType, StorageType, LeavesTableType>, ImportError<StorageError>>
+    {
+        if !self.is_complete() {
+            return Err(ImportError::Incomplete(self.expected.len()));
+        }
+        MerkleTree::load(self.storage, &self.root).map_err(|err| match err {
+            MerkleTreeError::StorageError(err) => ImportError::StorageError(err),
+            err => ImportError::MerkleTreeError(err),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        common::{Bytes32, StorageMap},
+        sparse::{export, hash::sum, ImportError, Importer, MerkleTree, Node, Primitive},
+    };
+    use alloc::vec::Vec;
+    use fuel_storage::{Mappable, StorageMutate};
+
+    #[derive(Debug)]
+    struct TestTable;
+
+    impl Mappable for TestTable {
+        type Key = Bytes32;
+        type SetValue = Primitive;
+        type GetValue = Self::SetValue;
+    }
+
+    fn key(i: u32) -> Bytes32 {
+        sum(i.to_be_bytes())
+    }
+
+    #[test]
+    fn test_export_and_import_reproduce_the_tree() {
+        let mut storage = StorageMap::<TestTable>::new();
+        let mut tree = MerkleTree::new(&mut storage);
+        for i in 0_u32..100 {
+            tree.update(&key(i), b"DATA").unwrap();
+        }
+        let root = tree.root();
+
+        let mut imported_storage = StorageMap::<TestTable>::new();
+        let mut importer = Importer::new(&mut imported_storage, &root);
+        for chunk in export(&storage, &root, 16) {
+            let chunk = chunk.unwrap();
+            assert!(chunk.len() <= 16);
+            importer.import(&chunk).unwrap();
+        }
+        assert!(importer.is_complete());
+
+        // The imported tree can be loaded and updated like the original tree.
+        let mut imported_tree = importer.finish().unwrap();
+        assert_eq!(imported_tree.root(), root);
+        let mut tree = MerkleTree::load(&mut storage, &root).unwrap();
+        for i in 0_u32..10 {
+            imported_tree.delete(&key(i)).unwrap();
+            tree.delete(&key(i)).unwrap();
+        }
+        assert_eq!(imported_tree.root(), tree.root());
+    }
+
+    #[test]
+    fn test_export_of_the_empty_root_is_empty() {
+        let storage = StorageMap::<TestTable>::new();
+        let root = [0; 32];
+
+        assert_eq!(export(&storage, &root, 16).count(), 0);
+
+        let mut imported_storage = StorageMap::<TestTable>::new();
+        let importer = Importer::new(&mut imported_storage, &root);
+        assert!(importer.is_complete());
+        assert_eq!(importer.finish().unwrap().root(), root);
+    }
+
+    #[test]
+    fn test_import_rejects_a_node_not_belonging_to_the_tree() {
+        let mut storage = StorageMap::<TestTable>::new();
+        let mut tree = MerkleTree::new(&mut storage);
+        for i in 0_u32..10 {
+            tree.update(&key(i), b"DATA").unwrap();
+        }
+        let root = tree.root();
+        let original_nodes = export(&storage, &root, 100).next().unwrap().unwrap();
+        let mut nodes = original_nodes.clone();
+
+        // Replace the data of the first leaf.
+        let index = nodes
+            .iter()
+            .position(|(_, prefix, _, _)| *prefix == 0)
+            .unwrap();
+        nodes[index].3 = sum(b"CHANGE");
+
+        let mut imported_storage = StorageMap::<TestTable>::new();
+        let mut importer = Importer::new(&mut imported_storage, &root);
+        let err = importer
+            .import(&nodes)
+            .expect_err("Expected import() to return Error; got Ok");
+        assert!(matches!(err, ImportError::UnexpectedNode(_, _)));
+        assert_eq!(importer.imported(), index);
+        assert!(!importer.is_complete());
+
+        // The import resumes from the rejected node.
+        importer.import(&original_nodes[index..]).unwrap();
+        assert!(importer.is_complete());
+        assert_eq!(importer.finish().unwrap().root(), root);
+    }
+
+    #[test]
+    fn test_import_rejects_a_node_at_a_forged_height() {
+        let mut storage = StorageMap::<TestTable>::new();
+        let mut tree = MerkleTree::new(&mut storage);
+        for i in 0_u32..100 {
+            tree.update(&key(i), b"DATA").unwrap();
+        }
+        let root = tree.root();
+        let original_nodes = export(&storage, &root, 1000).next().unwrap().unwrap();
+
+        // The height of a node does not contribute to its hash. Raise the
+        // height of the first child of the root, an internal node.
+        let mut nodes = original_nodes.clone();
+        assert_eq!(nodes[1].1, 1);
+        nodes[1].0 += 7;
+        let mut imported_storage = StorageMap::<TestTable>::new();
+        let mut importer = Importer::new(&mut imported_storage, &root);
+        let err = importer
+            .import(&nodes)
+            .expect_err("Expected import() to return Error; got Ok");
+        assert!(
+            matches!(err, ImportError::InvalidNode(hash) if hash == Node::try_from(nodes[1]).unwrap().hash())
+        );
+        assert_eq!(importer.imported(), 1);
+
+        // Give the first leaf a height other than 0.
+        let mut nodes = original_nodes;
+        let index = nodes
+            .iter()
+            .position(|(_, prefix, _, _)| *prefix == 0)
+            .unwrap();
+        nodes[index].0 = 1;
+        let mut imported_storage = StorageMap::<TestTable>::new();
+        let mut importer = Importer::new(&mut imported_storage, &root);
+        let err = importer
+            .import(&nodes)
+            .expect_err("Expected import() to return Error; got Ok");
+        assert!(matches!(err, ImportError::InvalidNode(_)));
+        assert_eq!(importer.imported(), index);
+    }
+
+    #[test]
+    fn test_import_rejects_excess_nodes() {
+        let mut storage = StorageMap::<TestTable>::new();
+        let mut tree = MerkleTree::new(&mut storage);
+        tree.update(&key(0), b"DATA").unwrap();
+        let root = tree.root();
+        let nodes = export(&storage, &root, 100).next().unwrap().unwrap();
+
+        let mut imported_storage = StorageMap::<TestTable>::new();
+        let mut importer = Importer::new(&mut imported_storage, &root);
+        let nodes = [nodes.clone(), nodes].concat();
+        let err = importer
+            .import(&nodes)
+            .expect_err("Expected import() to return Error; got Ok");
+        assert!(matches!(err, ImportError::ExcessNode));
+    }
+
+    #[test]
+    fn test_finish_returns_an_error_if_the_import_is_incomplete() {
+        let mut storage = StorageMap::<TestTable>::new();
+        let mut tree = MerkleTree::new(&mut storage);
+        for i in 0_u32..10 {
+            tree.update(&key(i), b"DATA").unwrap();
+        }
+        let root = tree.root();
+        let nodes = export(&storage, &root, 5).next().unwrap().unwrap();
+
+        let mut imported_storage = StorageMap::<TestTable>::new();
+        let mut importer = Importer::new(&mut imported_storage, &root);
+        importer.import(&nodes).unwrap();
+        assert!(matches!(importer.finish(), Err(ImportError::Incomplete(_))));
+    }
+
+    #[test]
+    fn test_export_returns_an_error_if_a_node_is_missing() {
+        let mut storage = StorageMap::<TestTable>::new();
+        let mut tree = MerkleTree::new(&mut storage);
+        for i in 0_u32..10 {
+            tree.update(&key(i), b"DATA").unwrap();
+        }
+        let root = tree.root();
+        let leaf = Node::create_leaf(&key(3), b"DATA");
+        storage.remove(&leaf.hash()).unwrap();
+
+        let chunks = export(&storage, &root, 4).collect::<Vec<_>>();
+        assert!(chunks.last().unwrap().is_err());
+        assert!(chunks[..chunks.len() - 1].iter().all(Result::is_ok));
+    }
+
+    #[test]
+    fn test_export_with_a_chunk_size_of_0_exports_one_node_per_chunk() {
+        let mut storage = StorageMap::<TestTable>::new();
+        let mut tree = MerkleTree::new(&mut storage);
+        for i in 0_u32..10 {
+            tree.update(&key(i), b"DATA").unwrap();
+        }
+        let root = tree.root();
+
+        let chunks = export(&storage, &root, 0)
+            .map(Result::unwrap)
+            .collect::<Vec<_>>();
+        let nodes = export(&storage, &root, 1000).next().unwrap().unwrap();
+        assert!(chunks.iter().all(|chunk| chunk.len() == 1));
+        assert_eq!(chunks.concat(), nodes);
+    }
+}
@@ -0,0 +1,366 @@
+use crate::{
+    common::{Bytes32, StorageMap},
+    sparse::{zero_sum, MerkleTree, Node, Primitive},
+    storage::{Mappable, StorageMutate},
+};
+
+use alloc::{
+    collections::{BTreeMap, BTreeSet},
+    vec::Vec,
+};
+
+/// An operation applied to a sparse Merkle tree, as replayed by
+/// [`Witness::verify`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Operation {
+    /// Set the data of the leaf with the given key. As with
+    /// [`MerkleTree::update`], empty data deletes the key.
+    Update(
+        #[cfg_attr(feature = "serde", serde(with = "crate::common::serde::bytes32"))] Bytes32,
+        #[cfg_attr(feature = "serde", serde(with = "crate::common::serde::bytes"))] Vec<u8>,
+    ),
+    /// Delete the leaf with the given key.
+    Delete(#[cfg_attr(feature = "serde", serde(with = "crate::common::serde::bytes32"))] Bytes32),
+}
+
+/// # Witness
+///
+/// The nodes of a sparse Merkle tree read while a batch of operations was
+/// applied to the tree, recorded by [`MerkleTree::start_recording`]. The
+/// witness proves the transition of the tree from the root before the batch
+/// to the root after the batch: replaying the operations against the witness
+/// alone reproduces the new root.
+///
+/// Each node appears in the witness once, regardless of how many operations
+/// read it, and nodes created by the batch itself are omitted, since the
+/// replay recreates them. The nodes are ordered by hash.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Witness {
+    #[cfg_attr(feature = "serde", serde(with = "crate::common::serde::encoded_seq"))]
+    nodes: Vec<Primitive>,
+}
+
+impl Witness {
+    pub fn nodes(&self) -> &[Primitive] {
+        &self.nodes
+    }
+
+    /// Verify that applying `operations` in order to the sparse Merkle tree
+    /// with root `old_root` results in the tree with root `new_root`.
+    ///
+    /// The operations are replayed on a tree holding only the nodes of the
+    /// witness. Nodes are stored under their hashes, so the replay descends
+    /// from `old_root` only through nodes committed to by that root. The
+    /// verification fails if the replay requires a node missing from the
+    /// witness.
+    ///
+    /// The hash of a node does not include its height, and the replay relies
+    /// on the heights of the nodes to place the nodes it creates. The nodes
+    /// are therefore loaded by descending from `old_root`, and the
+    /// verification fails if a node is not at the height of its position in
+    /// the tree. Nodes not reachable from `old_root` are not loaded.
+    pub fn verify(&self, old_root: &Bytes32, new_root: &Bytes32, operations: &[Operation]) -> bool {
+        let mut nodes = BTreeMap::new();
+        for primitive in self.nodes.iter() {
+            let node = match Node::try_from(*primitive) {
+                Ok(node) => node,
+                Err(_) => return false,
+            };
+            nodes.insert(node.hash(), node);
+        }
+
+        let mut storage = StorageMap::<WitnessTable>::new();
+        let mut stack = Vec::new();
+        if old_root != zero_sum() {
+            stack.push((*old_root, Node::max_height() as u32));
+        }
+        while let Some((key, expected_height)) = stack.pop() {
+            let node = match nodes.remove(&key) {
+                Some(node) => node,
+                None => continue,
+            };
+            if !node.is_at_height(expected_height) {
+                return false;
+            }
+            let _ = storage.insert(&key, &(&node).into());
+            if node.is_node() {
+                for child_key in [node.left_child_key(), node.right_child_key()] {
+                    if child_key != zero_sum() {
+                        stack.push((*child_key, expected_height - 1));
+                    }
+                }
+            }
+        }
+
+        let mut tree = match MerkleTree::load(storage, old_root) {
+            Ok(tree) => tree,
+            Err(_) => return false,
+        };
+
+        for operation in operations {
+            let result = match operation {
+                Operation::Update(key, data) if !data.is_empty() => tree.update(key, data),
+                Operation::Update(key, _) | Operation::Delete(key) => {
+                    // The witness holds nodes under their hashes only. The
+                    // tree finds the leaf to delete by its key; the leaf is
+                    // stored under its key only once the path of the key
+                    // proves that the leaf is in the tree.
+                    match tree.prove(key) {
+                        Ok(proof) => match proof.leaf() {
+                            Some(leaf) => tree.insert_leaf_key(key, leaf),
+                            None => Ok(()),
+                        }
+                        .and_then(|_| tree.delete(key)),
+                        Err(err) => Err(err),
+                    }
+                }
+            };
+            if result.is_err() {
+                return false;
+            }
+        }
+
+        tree.root() == *new_root
+    }
+}
+
+/// Records the nodes read by a sparse Merkle tree, excluding the nodes that
+/// the tree itself wrote since recording started.
+#[derive(Debug, Default)]
+pub(crate) struct Recorder {
+    read: BTreeMap<Bytes32, Primitive>,
+    written: BTreeSet<Bytes32>,
+}
+
+impl Recorder {
+    pub(crate) fn record_read(&mut self, node: &Node) {
+        if node.is_placeholder() {
+            return;
+        }
+        let hash = node.hash();
+        if !self.written.contains(&hash) {
+            self.read.entry(hash).or_insert_with(|| node.into());
+        }
+    }
+
+    pub(crate) fn record_write(&mut self, node: &Node) {
+        self.written.insert(node.hash());
+    }
+
+    pub(crate) fn into_witness(self) -> Witness {
+        Witness {
+            nodes: self.read.into_values().collect(),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct WitnessTable;
+
+impl Mappable for WitnessTable {
+    type Key = Bytes32;
+    type SetValue = Primitive;
+    type GetValue = Self::SetValue;
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        common::{Bytes32, Prefix, StorageMap},
+        sparse::{hash::sum, MerkleTree, Node, Operation, Primitive},
+    };
+    use alloc::{vec, vec::Vec};
+    use fuel_storage::{Mappable, StorageMutate};
+
+    #[derive(Debug)]
+    struct TestTable;
+
+    impl Mappable for TestTable {
+        type Key = Bytes32;
+        type SetValue = Primitive;
+        type GetValue = Self::SetValue;
+    }
+
+    fn key(i: u32) -> Bytes32 {
+        sum(i.to_be_bytes())
+    }
+
+    fn apply(
+        tree: &mut MerkleTree<TestTable, &mut StorageMap<TestTable>>,
+        operations: &[Operation],
+    ) {
+        for operation in operations {
+            match operation {
+                Operation::Update(key, data) => tree.update(key, data).unwrap(),
+                Operation::Delete(key) => tree.delete(key).unwrap(),
+            }
+        }
+    }
+
+    fn batch() -> Vec<Operation> {
+        let mut operations = Vec::new();
+        for i in 0_u32..10 {
+            // Overwrite existing keys and insert new keys.
+            operations.push(Operation::Update(key(i * 7), b"CHANGE".to_vec()));
+            operations.push(Operation::Update(key(100 + i), b"NEW".to_vec()));
+        }
+        for i in 0_u32..5 {
+            // Delete existing keys, keys inserted by the batch, and keys that
+            // are not in the tree.
+            operations.push(Operation::Delete(key(i * 3)));
+            operations.push(Operation::Delete(key(100 + i)));
+            operations.push(Operation::Delete(key(200 + i)));
+        }
+        operations.push(Operation::Update(key(1), vec![]));
+        operations
+    }
+
+    #[test]
+    fn test_witness_verifies_the_recorded_transition() {
+        let mut storage = StorageMap::<TestTable>::new();
+        let mut tree = MerkleTree::new(&mut storage);
+        for i in 0_u32..50 {
+            tree.update(&key(i), b"DATA").unwrap();
+        }
+        let old_root = tree.root();
+
+        let operations = batch();
+        tree.start_recording();
+        apply(&mut tree, &operations);
+        let witness = tree.stop_recording().unwrap();
+        let new_root = tree.root();
+
+        assert!(witness.verify(&old_root, &new_root, &operations));
+        assert!(!witness.verify(&old_root, &old_root, &operations));
+        assert!(!witness.verify(&old_root, &new_root, &operations[..operations.len() - 1]));
+        assert!(!witness.verify(&new_root, &new_root, &operations));
+    }
+
+    #[test]
+    fn test_witness_contains_each_node_once() {
+        let mut storage = StorageMap::<TestTable>::new();
+        let mut tree = MerkleTree::new(&mut storage);
+        for i in 0_u32..50 {
+            tree.update(&key(i), b"DATA").unwrap();
+        }
+
+        tree.start_recording();
+        apply(&mut tree, &batch());
+        let witness = tree.stop_recording().unwrap();
+
+        let mut nodes = witness.nodes().to_vec();
+        nodes.sort();
+        nodes.dedup();
+        assert_eq!(nodes.len(), witness.nodes().len());
+    }
+
+    #[test]
+    fn test_witness_verification_fails_if_a_node_is_missing() {
+        let mut storage = StorageMap::<TestTable>::new();
+        let mut tree = MerkleTree::new(&mut storage);
+        for i in 0_u32..50 {
+            tree.update(&key(i), b"DATA").unwrap();
+        }
+        let old_root = tree.root();
+
+        let operations = batch();
+        tree.start_recording();
+        apply(&mut tree, &operations);
+        let witness = tree.stop_recording().unwrap();
+        let new_root = tree.root();
+
+        for i in 0..witness.nodes().len() {
+            let mut nodes = witness.nodes().to_vec();
+            nodes.remove(i);
+            let witness = super::Witness { nodes };
+            assert!(!witness.verify(&old_root, &new_root, &operations));
+        }
+    }
+
+    #[test]
+    fn test_witness_verification_fails_if_a_height_is_changed() {
+        let mut storage = StorageMap::<TestTable>::new();
+        let mut tree = MerkleTree::new(&mut storage);
+        for i in 0_u32..50 {
+            tree.update(&key(i), b"DATA").unwrap();
+        }
+        let old_root = tree.root();
+
+        let operations = (0_u32..10)
+            .map(|i| Operation::Update(key(100 + i), b"NEW".to_vec()))
+            .collect::<Vec<_>>();
+        tree.start_recording();
+        apply(&mut tree, &operations);
+        let witness = tree.stop_recording().unwrap();
+        let new_root = tree.root();
+
+        // Replay the operations on the nodes of the witness as given, without
+        // checking their heights.
+        let replay = |nodes: &[Primitive]| {
+            let mut storage = StorageMap::<TestTable>::new();
+            for primitive in nodes {
+                let hash = Node::try_from(*primitive).unwrap().hash();
+                storage.insert(&hash, primitive).unwrap();
+            }
+            let mut tree = MerkleTree::load(&mut storage, &old_root).ok()?;
+            for operation in operations.iter() {
+                if let Operation::Update(key, data) = operation {
+                    tree.update(key, data).ok()?;
+                }
+            }
+            Some(tree.root())
+        };
+        assert_eq!(replay(witness.nodes()), Some(new_root));
+
+        // The height of a node does not contribute to its hash, so lowering
+        // the height of an internal node leaves the witness committed to by
+        // the old root, but changes the root produced by the replay.
+        let mut forged_roots = 0;
+        for i in 0..witness.nodes().len() {
+            let (height, prefix, _, _) = witness.nodes()[i];
+            if prefix != Prefix::Node as u8 || height < 4 {
+                continue;
+            }
+            for lowered_by in [1, 3] {
+                let mut nodes = witness.nodes().to_vec();
+                nodes[i].0 -= lowered_by;
+                let forged_root = replay(&nodes);
+                let forged = super::Witness { nodes };
+                assert!(!forged.verify(&old_root, &new_root, &operations));
+                if let Some(forged_root) = forged_root.filter(|root| *root != new_root) {
+                    assert!(!forged.verify(&old_root, &forged_root, &operations));
+                    forged_roots += 1;
+                }
+            }
+        }
+        assert!(forged_roots > 0);
+    }
+
+    #[test]
+    fn test_witness_verifies_a_transition_from_the_empty_tree() {
+        let mut storage = StorageMap::<TestTable>::new();
+        let mut tree = MerkleTree::new(&mut storage);
+        let old_root = tree.root();
+
+        let operations = batch();
+        tree.start_recording();
+        apply(&mut tree, &operations);
+        let witness = tree.stop_recording().unwrap();
+        let new_root = tree.root();
+
+        // Every node read by the batch was created by the batch.
+        assert!(witness.nodes().is_empty());
+        assert!(witness.verify(&old_root, &new_root, &operations));
+    }
+
+    #[test]
+    fn test_stop_recording_returns_none_if_not_recording() {
+        let mut storage = StorageMap::<TestTable>::new();
+        let mut tree = MerkleTree::new(&mut storage);
+        tree.update(&key(0), b"DATA").unwrap();
+
+        assert!(tree.stop_recording().is_none());
+    }
+}
@@ -1,9 +1,135 @@
-use alloc::borrow::Cow;
+use alloc::{borrow::Cow, vec::Vec};
 use core::convert::Infallible;
 
 // Re-export fuel-storage traits
 pub use fuel_storage::{Mappable, StorageInspect, StorageMutate};
 
+/// The values of a batch of keys read by [`StorageInspectBatch::get_batch`].
+pub type GetBatchResult<'a, Value, Error> = Result<Vec<Option<Cow<'a, Value>>>, Error>;
+
+/// An extension of [`StorageInspect`] that reads many keys at once. A tree
+/// switched to [`BatchReads`] reads through `get_batch` wherever the keys of
+/// several nodes are known up front, such as the peaks of a binary tree or the
+/// children of a sparse node. The trees do not require this trait; by default
+/// they read one key at a time.
+///
+/// The default implementation reads the keys one at a time with `get`, so a
+/// storage can opt in with an empty `impl`. Storages backed by a database with
+/// a multi-get operation override `get_batch` to fetch all the keys in a
+/// single round trip.
+pub trait StorageInspectBatch<Type: Mappable>: StorageInspect<Type> {
+    /// Get the values of the given keys, in the order of the keys. The value
+    /// of a key not found in storage is `None`.
+    fn get_batch(&self, keys: &[Type::Key]) -> GetBatchResult<'_, Type::GetValue, Self::Error> {
+        keys.iter().map(|key| self.get(key)).collect()
+    }
+}
+
+impl<S, Type> StorageInspectBatch<Type> for &S
+where
+    S: StorageInspectBatch<Type> + ?Sized,
+    Type: Mappable,
+{
+    fn get_batch(&self, keys: &[Type::Key]) -> GetBatchResult<'_, Type::GetValue, Self::Error> {
+        (**self).get_batch(keys)
+    }
+}
+
+impl<S, Type> StorageInspectBatch<Type> for &mut S
+where
+    S: StorageInspectBatch<Type> + ?Sized,
+    Type: Mappable,
+{
+    fn get_batch(&self, keys: &[Type::Key]) -> GetBatchResult<'_, Type::GetValue, Self::Error> {
+        (**self).get_batch(keys)
+    }
+}
+
+/// An extension of [`StorageMutate`] that writes many changes at once. A
+/// [`StorageOverlay`](crate::common::StorageOverlay) commits its buffered
+/// changes through `write_batch`.
+///
+/// The default implementation writes the changes one at a time with `insert`
+/// and `remove`, so a storage can opt in with an empty `impl`; a failure
+/// partway through leaves the changes already written in storage. Storages
+/// backed by a database with atomic write batches or transactions override
+/// `write_batch` to write either all of the changes or none of them.
+pub trait StorageMutateBatch<Type: Mappable>: StorageMutate<Type> {
+    /// Write the given changes: each key with its new value, or with `None` to
+    /// remove the key.
+    fn write_batch(
+        &mut self,
+        changes: &[(&Type::Key, Option<&Type::SetValue>)],
+    ) -> Result<(), Self::Error> {
+        for (key, value) in changes {
+            match value {
+                Some(value) => self.insert(key, value)?,
+                None => self.remove(key)?,
+            };
+        }
+        Ok(())
+    }
+}
+
+impl<S, Type> StorageMutateBatch<Type> for &mut S
+where
+    S: StorageMutateBatch<Type> + ?Sized,
+    Type: Mappable,
+{
+    fn write_batch(
+        &mut self,
+        changes: &[(&Type::Key, Option<&Type::SetValue>)],
+    ) -> Result<(), Self::Error> {
+        (**self).write_batch(changes)
+    }
+}
+
+/// How a tree reads a batch of keys known up front. [`SingleReads`] reads the
+/// keys one at a time with `get`, and works with any storage; [`BatchReads`]
+/// reads them with [`StorageInspectBatch::get_batch`].
+pub trait ReadStrategy<Type: Mappable, StorageType: StorageInspect<Type>> {
+    /// Get the values of the given keys, in the order of the keys.
+    fn get_batch<'a>(
+        storage: &'a StorageType,
+        keys: &[Type::Key],
+    ) -> GetBatchResult<'a, Type::GetValue, StorageType::Error>;
+}
+
+/// Read a batch of keys one key at a time. The default read strategy of the
+/// trees.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SingleReads;
+
+impl<Type, StorageType> ReadStrategy<Type, StorageType> for SingleReads
+where
+    Type: Mappable,
+    StorageType: StorageInspect<Type>,
+{
+    fn get_batch<'a>(
+        storage: &'a StorageType,
+        keys: &[Type::Key],
+    ) -> GetBatchResult<'a, Type::GetValue, StorageType::Error> {
+        keys.iter().map(|key| storage.get(key)).collect()
+    }
+}
+
+/// Read a batch of keys with [`StorageInspectBatch::get_batch`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BatchReads;
+
+impl<Type, StorageType> ReadStrategy<Type, StorageType> for BatchReads
+where
+    Type: Mappable,
+    StorageType: StorageInspectBatch<Type>,
+{
+    fn get_batch<'a>(
+        storage: &'a StorageType,
+        keys: &[Type::Key],
+    ) -> GetBatchResult<'a, Type::GetValue, StorageType::Error> {
+        storage.get_batch(keys)
+    }
+}
+
 pub trait StorageInspectInfallible<Type: Mappable> {
     fn get(&self, key: &Type::Key) -> Option<Cow<Type::GetValue>>;
     fn contains_key(&self, key: &Type::Key) -> bool;
@@ -1,8 +1,10 @@
 mod hash;
+mod integrity;
 mod merkle_tree;
 mod node;
 
 pub(crate) use hash::{empty_sum, leaf_sum, node_sum};
+pub use integrity::check;
 pub use merkle_tree::MerkleTree;
 pub use merkle_tree::MerkleTreeError;
 pub(crate) use node::Node;
@@ -0,0 +1,277 @@
+use crate::{
+    common::{Bytes32, IntegrityIssue, IntegrityReport},
+    storage::{Mappable, StorageInspect},
+    sum::{empty_sum, node_sum, Node},
+};
+
+use alloc::vec;
+
+/// Check the integrity of the binary Merkle sum tree with the given root,
+/// walking every node from the root. The check reports:
+///
+/// - nodes referenced by their parents but not found in storage;
+/// - nodes stored under a key other than their hash;
+/// - internal nodes whose hash or fee does not match their children, or that
+///   do not reference two children;
+/// - left children not one level below their parents, and right children not
+///   below their parents.
+///
+/// The check only descends into children at a valid height, so a node that
+/// references itself or one of its ancestors is reported instead of walked
+/// forever. A subtree referenced more than once, such as a leaf pushed twice
+/// with the same fee and data, is checked once.
+///
+/// The data of the leaves is not stored, so the hashes of the leaves are not
+/// checked. A storage error stops the check and is returned.
+pub fn check<TableType, StorageType>(
+    storage: &StorageType,
+    root: &Bytes32,
+) -> Result<IntegrityReport<Bytes32>, StorageType::Error>
+where
+    TableType: Mappable<Key = Bytes32, SetValue = Node, GetValue = Node>,
+    StorageType: StorageInspect<TableType>,
+{
+    let mut report = IntegrityReport::default();
+    if root == empty_sum() {
+        return Ok(report);
+    }
+
+    let mut stack = vec![*root];
+    while let Some(key) = stack.pop() {
+        if !report.reach(&key) {
+            continue;
+        }
+        let node = match storage.get(&key)? {
+            Some(node) => node.into_owned(),
+            None => {
+                report.report(IntegrityIssue::MissingNode(key));
+                continue;
+            }
+        };
+        report.nodes_checked += 1;
+        if *node.hash() != key {
+            report.report(IntegrityIssue::HashMismatch(key));
+        }
+
+        let children = (node.left_child_key(), node.right_child_key());
+        if node.is_leaf() {
+            report.leaves_checked += 1;
+            if children != (None, None) {
+                report.report(IntegrityIssue::MalformedNode(key));
+            }
+            continue;
+        }
+        let (left_child_key, right_child_key) = match children {
+            (Some(left_child_key), Some(right_child_key)) => (left_child_key, right_child_key),
+            _ => {
+                report.report(IntegrityIssue::MalformedNode(key));
+                continue;
+            }
+        };
+
+        let left_child = storage.get(&left_child_key)?;
+        let right_child = storage.get(&right_child_key)?;
+        if let (Some(left_child), Some(right_child)) = (&left_child, &right_child) {
+            check_children(&mut report, &key, &node, left_child, right_child);
+        }
+
+        // Subtrees are joined into the subtree on their left, so the left
+        // child is always one level below its parent. The right child is at
+        // most one level below its parent. A missing child is descended into,
+        // to be reported as missing.
+        let expected_height = node.height() - 1;
+        let descend_left = match left_child.map(|child| child.height()) {
+            Some(height) if height != expected_height => {
+                report.report(IntegrityIssue::HeightMismatch {
+                    key: left_child_key,
+                    expected: expected_height,
+                    found: height,
+                });
+                false
+            }
+            _ => true,
+        };
+        let descend_right = match right_child.map(|child| child.height()) {
+            Some(height) if height > expected_height => {
+                report.report(IntegrityIssue::HeightMismatch {
+                    key: right_child_key,
+                    expected: expected_height,
+                    found: height,
+                });
+                false
+            }
+            _ => true,
+        };
+        if descend_right {
+            stack.push(right_child_key);
+        }
+        if descend_left {
+            stack.push(left_child_key);
+        }
+    }
+
+    Ok(report)
+}
+
+fn check_children(
+    report: &mut IntegrityReport<Bytes32>,
+    key: &Bytes32,
+    node: &Node,
+    left_child: &Node,
+    right_child: &Node,
+) {
+    let key = *key;
+    let hash = node_sum(
+        left_child.fee(),
+        left_child.hash(),
+        right_child.fee(),
+        right_child.hash(),
+    );
+    if hash != key {
+        report.report(IntegrityIssue::HashMismatch(key));
+    }
+    if left_child.fee().checked_add(right_child.fee()) != Some(node.fee()) {
+        report.report(IntegrityIssue::FeeMismatch(key));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        common::{Bytes32, IntegrityIssue, StorageMap},
+        storage::{Mappable, StorageMutate},
+        sum::{check, MerkleTree, Node},
+    };
+    use fuel_merkle_test_helpers::TEST_DATA;
+
+    struct TestTable;
+
+    impl Mappable for TestTable {
+        type Key = Bytes32;
+        type SetValue = Node;
+        type GetValue = Self::SetValue;
+    }
+
+    const FEE: u64 = 100;
+
+    fn storage_with_leaves(n: usize) -> (StorageMap<TestTable>, Bytes32) {
+        let mut storage = StorageMap::<TestTable>::new();
+        let mut tree = MerkleTree::new(&mut storage);
+        for datum in TEST_DATA[0..n].iter() {
+            tree.push(FEE, datum).unwrap();
+        }
+        let (_, root) = tree.root().unwrap();
+        (storage, root)
+    }
+
+    #[test]
+    fn test_check_of_a_valid_tree_reports_no_issues() {
+        for n in 1..TEST_DATA.len() {
+            let (storage, root) = storage_with_leaves(n);
+            let report = check::<TestTable, _>(&storage, &root).unwrap();
+            assert!(report.is_ok(), "{:?}", report.issues);
+            assert_eq!(report.leaves_checked, n as u64);
+            assert_eq!(report.nodes_checked, 2 * n as u64 - 1);
+        }
+    }
+
+    #[test]
+    fn test_check_of_the_empty_tree_reports_no_issues() {
+        let (storage, root) = storage_with_leaves(0);
+        let report = check::<TestTable, _>(&storage, &root).unwrap();
+        assert!(report.is_ok());
+        assert_eq!(report.nodes_checked, 0);
+    }
+
+    #[test]
+    fn test_check_reports_a_missing_node() {
+        let (mut storage, root) = storage_with_leaves(4);
+        let missing_key = *Node::create_leaf(FEE, TEST_DATA[2]).hash();
+        storage.remove(&missing_key).unwrap();
+
+        let report = check::<TestTable, _>(&storage, &root).unwrap();
+        assert_eq!(report.issues, [IntegrityIssue::MissingNode(missing_key)]);
+    }
+
+    #[test]
+    fn test_check_reports_a_node_with_a_wrong_fee() {
+        let (mut storage, root) = storage_with_leaves(4);
+        let leaf_key = *Node::create_leaf(FEE, TEST_DATA[2]).hash();
+        let sibling_key = *Node::create_leaf(FEE, TEST_DATA[3]).hash();
+        // Store a leaf with a different fee under the key of the original
+        // leaf: the leaf no longer hashes to its key, and its parent no
+        // longer matches its children.
+        let corrupt_leaf = Node::create_leaf(FEE + 1, TEST_DATA[2]);
+        storage.insert(&leaf_key, &corrupt_leaf).unwrap();
+
+        let report = check::<TestTable, _>(&storage, &root).unwrap();
+        let parent_key = *Node::create_node(1, FEE, &leaf_key, FEE, &sibling_key).hash();
+        assert_eq!(
+            report.issues,
+            [
+                IntegrityIssue::HashMismatch(parent_key),
+                IntegrityIssue::FeeMismatch(parent_key),
+                IntegrityIssue::HashMismatch(leaf_key),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_check_reports_a_node_with_a_wrong_height() {
+        let (mut storage, root) = storage_with_leaves(2);
+        let leaf_key = *Node::create_leaf(FEE, TEST_DATA[0]).hash();
+        let sibling_key = *Node::create_leaf(FEE, TEST_DATA[1]).hash();
+        let tall_root = Node::create_node(2, FEE, &leaf_key, FEE, &sibling_key);
+        storage.insert(tall_root.hash(), &tall_root).unwrap();
+        assert_eq!(*tall_root.hash(), root);
+
+        let report = check::<TestTable, _>(&storage, &root).unwrap();
+        assert_eq!(
+            report.issues,
+            [IntegrityIssue::HeightMismatch {
+                key: leaf_key,
+                expected: 1,
+                found: 0
+            }]
+        );
+    }
+
+    #[test]
+    fn test_check_of_a_node_referencing_itself_terminates() {
+        let (mut storage, root) = storage_with_leaves(2);
+        let sibling_key = *Node::create_leaf(FEE, TEST_DATA[1]).hash();
+        // The root, with itself as its left child.
+        let looping_root = Node::create_node(1, FEE, &root, FEE, &sibling_key);
+        storage.insert(&root, &looping_root).unwrap();
+
+        let report = check::<TestTable, _>(&storage, &root).unwrap();
+        assert_eq!(
+            report.issues,
+            [
+                IntegrityIssue::HashMismatch(root),
+                IntegrityIssue::HashMismatch(root),
+                IntegrityIssue::FeeMismatch(root),
+                IntegrityIssue::HeightMismatch {
+                    key: root,
+                    expected: 0,
+                    found: 1
+                },
+            ]
+        );
+        assert_eq!(report.nodes_checked, 2);
+    }
+
+    #[test]
+    fn test_check_of_a_leaf_referenced_twice_checks_it_once() {
+        let mut storage = StorageMap::<TestTable>::new();
+        let mut tree = MerkleTree::new(&mut storage);
+        tree.push(FEE, TEST_DATA[0]).unwrap();
+        tree.push(FEE, TEST_DATA[0]).unwrap();
+        let (_, root) = tree.root().unwrap();
+
+        let report = check::<TestTable, _>(&storage, &root).unwrap();
+        assert!(report.is_ok(), "{:?}", report.issues);
+        assert_eq!(report.leaves_checked, 1);
+        assert_eq!(report.nodes_checked, 2);
+    }
+}
@@ -68,14 +68,15 @@ where
         Ok(root_pair)
     }
 
+    /// Push a leaf with the given fee and data to the tree. If a storage
+    /// error occurs, the tree is left as it was before the push.
     pub fn push(&mut self, fee: u64, data: &[u8]) -> Result<(), StorageError> {
         let node = Node::create_leaf(fee, data);
         self.storage.insert(node.hash(), &node)?;
 
-        let next = self.head.take();
+        let next = self.head.clone();
         let head = Box::new(Subtree::<Node>::new(node, next));
-        self.head = Some(head);
-        self.join_all_subtrees()?;
+        self.head = Some(self.join_all_subtrees(head)?);
 
         Ok(())
     }
@@ -101,25 +102,23 @@ where
         Ok(root_node)
     }
 
-    fn join_all_subtrees(&mut self) -> Result<(), StorageError> {
+    fn join_all_subtrees(
+        &mut self,
+        mut head: Box<Subtree<Node>>,
+    ) -> Result<Box<Subtree<Node>>, StorageError> {
         loop {
-            let current = self.head.as_ref().unwrap();
-            if !(current.next().is_some()
-                && current.node().height() == current.next_node().unwrap().height())
+            if !(head.next().is_some()
+                && head.node().height() == head.next_node().unwrap().height())
             {
                 break;
             }
 
             // Merge the two front nodes of the list into a single node
-            let joined_node = {
-                let mut head = self.head.take().unwrap();
-                let mut head_next = head.take_next().unwrap();
-                self.join_subtrees(&mut head_next, &mut head)?
-            };
-            self.head = Some(joined_node);
+            let mut head_next = head.take_next().unwrap();
+            head = self.join_subtrees(&mut head_next, &mut head)?;
         }
 
-        Ok(())
+        Ok(head)
     }
 
     fn join_subtrees(
@@ -146,7 +145,10 @@ where
 #[cfg(test)]
 mod test {
     use crate::{
-        common::{Bytes32, StorageMap},
+        common::{
+            faulty_storage::{FaultError, FaultyStorage},
+            Bytes32, StorageMap,
+        },
         sum::{empty_sum, leaf_sum, node_sum, MerkleTree, Node},
     };
     use fuel_merkle_test_helpers::TEST_DATA;
@@ -291,4 +293,37 @@ mod test {
         let expected = (FEE * 7, node_5);
         assert_eq!(root, expected);
     }
+
+    #[test]
+    fn push_with_failing_writes_keeps_the_tree_consistent() {
+        let data = &TEST_DATA[0..8];
+        let mut expected_storage = StorageMap::<TestTable>::new();
+        let mut expected_tree = MerkleTree::new(&mut expected_storage);
+        for datum in data.iter() {
+            expected_tree.push(FEE, datum).unwrap();
+        }
+        let expected_root = expected_tree.root().unwrap();
+
+        // Pushing the eighth leaf writes the leaf and joins three subtrees.
+        for writes in 0..4 {
+            let mut storage = FaultyStorage::new(StorageMap::<TestTable>::new());
+            let faults = storage.faults();
+            let mut tree = MerkleTree::new(&mut storage);
+            for datum in data[0..7].iter() {
+                tree.push(FEE, datum).unwrap();
+            }
+            let root = tree.root().unwrap();
+
+            faults.fail_writes_after(writes);
+            let err = tree
+                .push(FEE, data[7])
+                .expect_err("Expected push() to return Error; got Ok");
+            assert_eq!(err, FaultError::WriteFailed);
+            faults.clear();
+            assert_eq!(tree.root().unwrap(), root);
+
+            tree.push(FEE, data[7]).unwrap();
+            assert_eq!(tree.root().unwrap(), expected_root);
+        }
+    }
 }
@@ -1,4 +1,4 @@
-use crate::common::Bytes32;
+use crate::common::{check_length, Bytes32, DeserializeError, Encoding};
 use crate::sum::{leaf_sum, node_sum};
 use core::fmt;
 
@@ -91,3 +91,116 @@ impl fmt::Debug for Node {
         }
     }
 }
+
+/// **Leaf buffer:**
+///
+/// | Allocation | Data                                 |
+/// |------------|--------------------------------------|
+/// | `00 - 04`  | Height (4 bytes, `0`)                |
+/// | `04 - 12`  | Fee (8 bytes)                        |
+/// | `12 - 44`  | Hash (32 bytes)                      |
+/// | `44 - 108` | Zeroes (64 bytes)                    |
+///
+/// **Node buffer:**
+///
+/// | Allocation | Data                                 |
+/// |------------|--------------------------------------|
+/// | `00 - 04`  | Height (4 bytes)                     |
+/// | `04 - 12`  | Fee (8 bytes)                        |
+/// | `12 - 44`  | Hash (32 bytes)                      |
+/// | `44 - 76`  | Left child key (32 bytes)            |
+/// | `76 - 108` | Right child key (32 bytes)           |
+///
+impl Encoding for Node {
+    const SIZE: usize = 108;
+
+    type Bytes = [u8; 108];
+
+    fn to_bytes(&self) -> Self::Bytes {
+        let mut bytes = [0u8; 108];
+        bytes[0..4].copy_from_slice(&self.height.to_be_bytes());
+        bytes[4..12].copy_from_slice(&self.fee.to_be_bytes());
+        bytes[12..44].copy_from_slice(&self.hash);
+        bytes[44..76].copy_from_slice(&self.left_child_key.unwrap_or_default());
+        bytes[76..108].copy_from_slice(&self.right_child_key.unwrap_or_default());
+        bytes
+    }
+
+    /// A leaf with child keys other than zeroes is not a canonical encoding.
+    fn from_bytes(bytes: &[u8]) -> Result<Self, DeserializeError> {
+        let bytes = check_length::<108>(bytes)?;
+        let height = u32::from_be_bytes(bytes[0..4].try_into().unwrap());
+        let fee = u64::from_be_bytes(bytes[4..12].try_into().unwrap());
+        let hash = bytes[12..44].try_into().unwrap();
+        let left_child_key: Bytes32 = bytes[44..76].try_into().unwrap();
+        let right_child_key: Bytes32 = bytes[76..108].try_into().unwrap();
+        let (left_child_key, right_child_key) = if height == 0 {
+            if left_child_key != Bytes32::default() || right_child_key != Bytes32::default() {
+                return Err(DeserializeError::NonCanonical);
+            }
+            (None, None)
+        } else {
+            (Some(left_child_key), Some(right_child_key))
+        };
+        Ok(Self {
+            height,
+            hash,
+            fee,
+            left_child_key,
+            right_child_key,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        common::{DeserializeError, Encoding},
+        sum::Node,
+    };
+
+    #[test]
+    fn test_encoding_round_trips() {
+        let lhs = Node::create_leaf(10, b"LHS");
+        let rhs = Node::create_leaf(20, b"RHS");
+        let node = Node::create_node(1, lhs.fee(), lhs.hash(), rhs.fee(), rhs.hash());
+
+        let bytes = node.to_bytes();
+        assert_eq!(bytes[0..4], 1u32.to_be_bytes());
+        assert_eq!(bytes[4..12], 30u64.to_be_bytes());
+        assert_eq!(&bytes[44..76], lhs.hash());
+
+        for node in [lhs, rhs, node] {
+            let decoded = Node::from_bytes(&node.to_bytes()).unwrap();
+            assert_eq!(decoded.height(), node.height());
+            assert_eq!(decoded.fee(), node.fee());
+            assert_eq!(decoded.hash(), node.hash());
+            assert_eq!(decoded.left_child_key(), node.left_child_key());
+            assert_eq!(decoded.right_child_key(), node.right_child_key());
+
+            let decoded = Node::from_versioned_bytes(&node.to_versioned_bytes()).unwrap();
+            assert_eq!(decoded.hash(), node.hash());
+        }
+    }
+
+    #[test]
+    fn test_from_bytes_of_a_leaf_with_child_keys_returns_non_canonical_error() {
+        let mut bytes = Node::create_leaf(10, b"DATA").to_bytes();
+        bytes[107] = 0x01;
+
+        let err = Node::from_bytes(&bytes).unwrap_err();
+        assert!(matches!(err, DeserializeError::NonCanonical));
+    }
+
+    #[test]
+    fn test_from_bytes_with_wrong_length_returns_invalid_length_error() {
+        let err = Node::from_bytes(&[0; 40]).unwrap_err();
+        assert!(matches!(
+            err,
+            DeserializeError::InvalidLength {
+                expected: 108,
+                received: 40
+            }
+        ));
+    }
+}